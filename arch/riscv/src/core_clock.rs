@@ -0,0 +1,141 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Core clock frequency for converting `mcycle` counts to time.
+//!
+//! A [`CoreClock`] holds the frequency at which `mcycle` increments, so that
+//! cycle counts, e.g. from a [`CycleScope`](crate::cycle_scope::CycleScope),
+//! can be converted to time, and short busy-wait delays can be expressed in
+//! microseconds.
+//!
+//! The frequency starts out as the nominal value the board was built for. On
+//! boards where the core clock is configured at runtime, and so may differ
+//! from that value, [`CoreClock::calibrate`] measures the actual frequency
+//! against a timer of known frequency:
+//!
+//! ```rust,ignore
+//! let core_clock = static_init!(
+//!     riscv::core_clock::CoreClock,
+//!     riscv::core_clock::CoreClock::new(CHIP_FREQ, true)
+//! );
+//!
+//! // After the clocks are configured, measure over ~1ms of the timer.
+//! let _ = core_clock.calibrate(hardware_alarm, hardware_alarm.ticks_from_ms(1));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{Frequency, Ticks, Time};
+use kernel::ErrorCode;
+
+use crate::csr::CSR;
+
+/// The frequency of the core clock, as counted by `mcycle`.
+pub struct CoreClock {
+    hz: Cell<u32>,
+    /// Whether the core implements `mcountinhibit`.
+    has_mcountinhibit: bool,
+}
+
+impl CoreClock {
+    /// Create a clock running at `nominal_hz` until it is calibrated.
+    ///
+    /// `has_mcountinhibit` must only be set if the core implements the
+    /// `mcountinhibit` CSR, which was added in version 1.11 of the privileged
+    /// spec. Reading it on an older core, e.g. the FE310, traps. If it is
+    /// not set, `mcycle` is assumed to be always running.
+    pub const fn new(nominal_hz: u32, has_mcountinhibit: bool) -> CoreClock {
+        CoreClock {
+            hz: Cell::new(nominal_hz),
+            has_mcountinhibit,
+        }
+    }
+
+    /// Whether `mcycle` is incrementing, as far as can be told on this core.
+    fn cycle_counter_running(&self) -> bool {
+        !self.has_mcountinhibit || CSR.cycle_counter_running()
+    }
+
+    /// Frequency of the core clock in Hz.
+    pub fn frequency(&self) -> u32 {
+        self.hz.get()
+    }
+
+    pub fn set_frequency(&self, hz: u32) {
+        self.hz.set(hz);
+    }
+
+    /// Measure the frequency of the core clock by counting the cycles spent
+    /// over `ticks` ticks of `time`, store it and return it.
+    ///
+    /// This busy-waits for the whole interval, so it is meant to be called
+    /// once during board initialization, after the clocks are configured.
+    /// The interval starts on a tick boundary of `time`, so the measurement
+    /// is off by at most one tick: the longer the interval, the more
+    /// accurate the result. `time` must be running, or this never returns.
+    ///
+    /// Fails with `OFF` if `mcycle` is stopped through `mcountinhibit` (only
+    /// checked if the core implements it, see `new`), and
+    /// with `INVAL` if `ticks` is zero. The stored frequency is left
+    /// unchanged on failure.
+    pub fn calibrate<T: Time>(&self, time: &T, ticks: T::Ticks) -> Result<u32, ErrorCode> {
+        if !self.cycle_counter_running() {
+            return Err(ErrorCode::OFF);
+        }
+        if ticks == T::Ticks::from(0) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        // Wait for the next tick, so that the measurement doesn't start part
+        // way through one.
+        let first = time.now();
+        let mut start = time.now();
+        while start == first {
+            start = time.now();
+        }
+        let start_cycles = CSR.read_cycle_counter();
+
+        let mut now = time.now();
+        while now.wrapping_sub(start) < ticks {
+            now = time.now();
+        }
+        let cycles = CSR.read_cycle_counter().wrapping_sub(start_cycles);
+
+        // Use the ticks that actually elapsed, which may exceed `ticks` if
+        // the loop was interrupted.
+        let elapsed = now.wrapping_sub(start).into_u64();
+        let hz = cycles as u128 * T::Frequency::frequency() as u128 / elapsed as u128;
+        let hz = u32::try_from(hz).unwrap_or(u32::MAX);
+        self.hz.set(hz);
+        Ok(hz)
+    }
+
+    /// Number of cycles in `us` microseconds.
+    pub fn cycles_in_us(&self, us: u32) -> u64 {
+        self.hz.get() as u64 * us as u64 / 1_000_000
+    }
+
+    /// Number of microseconds in `cycles` cycles, rounding down. Returns 0
+    /// if the frequency is zero.
+    pub fn us_in_cycles(&self, cycles: u64) -> u64 {
+        (cycles as u128 * 1_000_000)
+            .checked_div(self.hz.get() as u128)
+            .map_or(0, |us| us as u64)
+    }
+
+    /// Busy-wait for at least `cycles` core cycles. Returns immediately if
+    /// `mcycle` is known to be stopped.
+    pub fn delay_cycles(&self, cycles: u64) {
+        if !self.cycle_counter_running() {
+            return;
+        }
+        let start = CSR.read_cycle_counter();
+        while CSR.read_cycle_counter().wrapping_sub(start) < cycles {}
+    }
+
+    /// Busy-wait for at least `us` microseconds, at the current frequency.
+    pub fn delay_us(&self, us: u32) {
+        self.delay_cycles(self.cycles_in_us(us));
+    }
+}
@@ -0,0 +1,15 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+use kernel::utilities::registers::register_bitfields;
+
+// mcountinhibit stops the cycle, instret and hpm counters from incrementing
+// while the respective bit is set.
+register_bitfields![usize,
+    pub mcountinhibit [
+        cy OFFSET(0) NUMBITS(1) [],
+        ir OFFSET(2) NUMBITS(1) [],
+        hpm OFFSET(3) NUMBITS(29) []
+    ]
+];
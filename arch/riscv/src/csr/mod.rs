@@ -5,23 +5,24 @@
 //! Tock Register interface for using CSR registers.
 
 use riscv_csr::csr::{
-    ReadWriteRiscvCsr, MCAUSE, MCYCLE, MCYCLEH, MEPC, MIE, MINSTRET, MINSTRETH, MIP, MSCRATCH,
-    MSECCFG, MSECCFGH, MSTATUS, MTVAL, MTVEC, PMPADDR0, PMPADDR1, PMPADDR10, PMPADDR11, PMPADDR12,
-    PMPADDR13, PMPADDR14, PMPADDR15, PMPADDR16, PMPADDR17, PMPADDR18, PMPADDR19, PMPADDR2,
-    PMPADDR20, PMPADDR21, PMPADDR22, PMPADDR23, PMPADDR24, PMPADDR25, PMPADDR26, PMPADDR27,
-    PMPADDR28, PMPADDR29, PMPADDR3, PMPADDR30, PMPADDR31, PMPADDR32, PMPADDR33, PMPADDR34,
-    PMPADDR35, PMPADDR36, PMPADDR37, PMPADDR38, PMPADDR39, PMPADDR4, PMPADDR40, PMPADDR41,
-    PMPADDR42, PMPADDR43, PMPADDR44, PMPADDR45, PMPADDR46, PMPADDR47, PMPADDR48, PMPADDR49,
-    PMPADDR5, PMPADDR50, PMPADDR51, PMPADDR52, PMPADDR53, PMPADDR54, PMPADDR55, PMPADDR56,
-    PMPADDR57, PMPADDR58, PMPADDR59, PMPADDR6, PMPADDR60, PMPADDR61, PMPADDR62, PMPADDR63,
-    PMPADDR7, PMPADDR8, PMPADDR9, PMPCFG0, PMPCFG1, PMPCFG10, PMPCFG11, PMPCFG12, PMPCFG13,
-    PMPCFG14, PMPCFG15, PMPCFG2, PMPCFG3, PMPCFG4, PMPCFG5, PMPCFG6, PMPCFG7, PMPCFG8, PMPCFG9,
-    STVEC, UTVEC,
+    ReadWriteRiscvCsr, MCAUSE, MCOUNTINHIBIT, MCYCLE, MCYCLEH, MEPC, MIE, MINSTRET, MINSTRETH, MIP,
+    MSCRATCH, MSECCFG, MSECCFGH, MSTATUS, MTVAL, MTVEC, PMPADDR0, PMPADDR1, PMPADDR10, PMPADDR11,
+    PMPADDR12, PMPADDR13, PMPADDR14, PMPADDR15, PMPADDR16, PMPADDR17, PMPADDR18, PMPADDR19,
+    PMPADDR2, PMPADDR20, PMPADDR21, PMPADDR22, PMPADDR23, PMPADDR24, PMPADDR25, PMPADDR26,
+    PMPADDR27, PMPADDR28, PMPADDR29, PMPADDR3, PMPADDR30, PMPADDR31, PMPADDR32, PMPADDR33,
+    PMPADDR34, PMPADDR35, PMPADDR36, PMPADDR37, PMPADDR38, PMPADDR39, PMPADDR4, PMPADDR40,
+    PMPADDR41, PMPADDR42, PMPADDR43, PMPADDR44, PMPADDR45, PMPADDR46, PMPADDR47, PMPADDR48,
+    PMPADDR49, PMPADDR5, PMPADDR50, PMPADDR51, PMPADDR52, PMPADDR53, PMPADDR54, PMPADDR55,
+    PMPADDR56, PMPADDR57, PMPADDR58, PMPADDR59, PMPADDR6, PMPADDR60, PMPADDR61, PMPADDR62,
+    PMPADDR63, PMPADDR7, PMPADDR8, PMPADDR9, PMPCFG0, PMPCFG1, PMPCFG10, PMPCFG11, PMPCFG12,
+    PMPCFG13, PMPCFG14, PMPCFG15, PMPCFG2, PMPCFG3, PMPCFG4, PMPCFG5, PMPCFG6, PMPCFG7, PMPCFG8,
+    PMPCFG9, STVEC, UTVEC,
 };
 use tock_registers::fields::FieldValue;
 use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
 pub mod mcause;
+pub mod mcountinhibit;
 pub mod mcycle;
 pub mod mepc;
 pub mod mie;
@@ -51,6 +52,9 @@ pub struct CSR {
     pub mcycleh: ReadWriteRiscvCsr<usize, mcycle::mcycleh::Register, MCYCLEH>,
     pub mcycle: ReadWriteRiscvCsr<usize, mcycle::mcycle::Register, MCYCLE>,
 
+    pub mcountinhibit:
+        ReadWriteRiscvCsr<usize, mcountinhibit::mcountinhibit::Register, MCOUNTINHIBIT>,
+
     #[cfg(not(target_arch = "riscv64"))]
     pub pmpcfg0: ReadWriteRiscvCsr<usize, pmpconfig::pmpcfg::Register, PMPCFG0>,
     #[cfg(not(target_arch = "riscv64"))]
@@ -169,6 +173,8 @@ pub const CSR: &CSR = &CSR {
     mcycleh: ReadWriteRiscvCsr::new(),
     mcycle: ReadWriteRiscvCsr::new(),
 
+    mcountinhibit: ReadWriteRiscvCsr::new(),
+
     pmpcfg0: ReadWriteRiscvCsr::new(),
     #[cfg(not(target_arch = "riscv64"))]
     pmpcfg1: ReadWriteRiscvCsr::new(),
@@ -278,17 +284,30 @@ pub const CSR: &CSR = &CSR {
 
 impl CSR {
     // resets the cycle counter to 0
-    #[cfg(not(target_arch = "riscv64"))]
     pub fn reset_cycle_counter(&self) {
-        // Write lower first so that we don't overflow before writing the upper
+        self.write_cycle_counter(0);
+    }
+
+    // sets the cycle counter to `value`, e.g. to start a measurement window
+    // from a known value. `mcycle` can only be written from machine mode, and
+    // this disturbs any other code using the cycle counter, such as a
+    // `CycleScope` that is in progress.
+    #[cfg(not(target_arch = "riscv64"))]
+    pub fn write_cycle_counter(&self, value: u64) {
+        // Clear the lower bits first so that they cannot carry into the upper
+        // bits between the two writes, which would leave the counter off by
+        // 2^32 cycles.
         CSR.mcycle.write(mcycle::mcycle::mcycle.val(0));
-        CSR.mcycleh.write(mcycle::mcycleh::mcycleh.val(0));
+        CSR.mcycleh
+            .write(mcycle::mcycleh::mcycleh.val((value >> 32) as usize));
+        CSR.mcycle
+            .write(mcycle::mcycle::mcycle.val(value as u32 as usize));
     }
 
-    // resets the cycle counter to 0
+    // sets the cycle counter to `value`, see the 32-bit version above
     #[cfg(target_arch = "riscv64")]
-    pub fn reset_cycle_counter(&self) {
-        CSR.mcycle.write(mcycle::mcycle::mcycle.val(0));
+    pub fn write_cycle_counter(&self, value: u64) {
+        CSR.mcycle.write(mcycle::mcycle::mcycle.val(value as usize));
     }
 
     // reads the cycle counter
@@ -316,6 +335,37 @@ impl CSR {
         CSR.mcycle.read(mcycle::mcycle::mcycle)
     }
 
+    // returns whether the cycle counter is incrementing, i.e. it is not
+    // stopped through mcountinhibit
+    pub fn cycle_counter_running(&self) -> bool {
+        !CSR.mcountinhibit.is_set(mcountinhibit::mcountinhibit::cy)
+    }
+
+    // stops the cycle counter, e.g. for a deterministic measurement or to
+    // save power
+    pub fn inhibit_cycle(&self) {
+        CSR.mcountinhibit
+            .modify(mcountinhibit::mcountinhibit::cy::SET);
+    }
+
+    // lets the cycle counter increment again
+    pub fn allow_cycle(&self) {
+        CSR.mcountinhibit
+            .modify(mcountinhibit::mcountinhibit::cy::CLEAR);
+    }
+
+    // stops the retired instruction counter
+    pub fn inhibit_instret(&self) {
+        CSR.mcountinhibit
+            .modify(mcountinhibit::mcountinhibit::ir::SET);
+    }
+
+    // lets the retired instruction counter increment again
+    pub fn allow_instret(&self) {
+        CSR.mcountinhibit
+            .modify(mcountinhibit::mcountinhibit::ir::CLEAR);
+    }
+
     pub fn pmpconfig_get(&self, index: usize) -> usize {
         match index {
             0 => self.pmpcfg0.get(),
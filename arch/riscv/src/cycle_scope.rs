@@ -0,0 +1,134 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Cycle-accurate profiling of kernel code using the `mcycle` counter.
+//!
+//! A [`CycleScope`] reads the 64-bit cycle counter when it is created and
+//! again when it is dropped, and reports the number of cycles in between to a
+//! [`CycleSink`]. [`CycleAccumulator`] is a sink that keeps simple statistics
+//! and can be printed at a convenient point, e.g. from a debug command. The
+//! board allocates it and passes it to the code to measure:
+//!
+//! ```rust,ignore
+//! let alarm_cycles = static_init!(
+//!     riscv::cycle_scope::CycleAccumulator,
+//!     riscv::cycle_scope::CycleAccumulator::new()
+//! );
+//!
+//! // In a capsule holding `cycles: &'static dyn CycleSink`:
+//! fn alarm(&self) {
+//!     riscv::cycle_scope!(self.cycles);
+//!     // ... code to measure ...
+//! }
+//! ```
+//!
+//! The measured cycles include the time spent in any interrupt handler that
+//! ran within the scope.
+//!
+//! This only works while `mcycle` is running. The counter may be stopped
+//! through the `mcountinhibit` CSR, either by the platform at reset or by
+//! kernel code saving power, in which case every scope measures zero cycles.
+//! Use [`CSR::cycle_counter_running`](crate::csr::CSR::cycle_counter_running)
+//! to check this before relying on the measurements.
+
+use core::cell::Cell;
+
+use crate::csr::CSR;
+
+/// Receives the number of cycles measured by a [`CycleScope`].
+pub trait CycleSink {
+    fn record(&self, cycles: u64);
+}
+
+/// Measures the cycles from its creation until it is dropped.
+pub struct CycleScope<'a> {
+    start: u64,
+    sink: &'a dyn CycleSink,
+}
+
+impl<'a> CycleScope<'a> {
+    pub fn new(sink: &'a dyn CycleSink) -> CycleScope<'a> {
+        CycleScope {
+            start: CSR.read_cycle_counter(),
+            sink,
+        }
+    }
+}
+
+impl Drop for CycleScope<'_> {
+    fn drop(&mut self) {
+        let end = CSR.read_cycle_counter();
+        self.sink.record(end.wrapping_sub(self.start));
+    }
+}
+
+/// Measure the cycles until the end of the enclosing block and report them
+/// to the given [`CycleSink`].
+#[macro_export]
+macro_rules! cycle_scope {
+    ($sink:expr) => {
+        let _cycle_scope = $crate::cycle_scope::CycleScope::new($sink);
+    };
+}
+
+/// A [`CycleSink`] that accumulates the number of measurements and their
+/// total and maximum number of cycles.
+pub struct CycleAccumulator {
+    count: Cell<u32>,
+    total: Cell<u64>,
+    max: Cell<u64>,
+}
+
+impl CycleAccumulator {
+    pub const fn new() -> CycleAccumulator {
+        CycleAccumulator {
+            count: Cell::new(0),
+            total: Cell::new(0),
+            max: Cell::new(0),
+        }
+    }
+
+    /// Number of measurements recorded.
+    pub fn count(&self) -> u32 {
+        self.count.get()
+    }
+
+    /// Sum of the cycles of all measurements.
+    pub fn total(&self) -> u64 {
+        self.total.get()
+    }
+
+    /// Largest number of cycles of a single measurement.
+    pub fn max(&self) -> u64 {
+        self.max.get()
+    }
+
+    /// Average number of cycles per measurement, or 0 if there are none.
+    pub fn average(&self) -> u64 {
+        self.total
+            .get()
+            .checked_div(self.count.get() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn reset(&self) {
+        self.count.set(0);
+        self.total.set(0);
+        self.max.set(0);
+    }
+}
+
+impl Default for CycleAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CycleSink for CycleAccumulator {
+    fn record(&self, cycles: u64) {
+        self.count.set(self.count.get().wrapping_add(1));
+        self.total.set(self.total.get().wrapping_add(cycles));
+        self.max.set(self.max.get().max(cycles));
+    }
+}
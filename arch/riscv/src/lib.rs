@@ -8,7 +8,9 @@
 #![crate_type = "rlib"]
 #![no_std]
 
+pub mod core_clock;
 pub mod csr;
+pub mod cycle_scope;
 
 #[cfg(target_arch = "riscv32")]
 pub const XLEN: usize = 32;
@@ -123,6 +123,10 @@ impl<A: 'static + time::Alarm<'static>> Component for AlarmDriverComponent<A> {
         ));
 
         virtual_alarm1.set_alarm_client(alarm);
+        debug_assert!(
+            AlarmDriver::<VirtualMuxAlarm<'static, A>>::frequency_is_valid(),
+            "alarm frequency of 0 or too high for the alarm width"
+        );
         alarm
     }
 }
@@ -7,20 +7,30 @@
 //! This provides two Components, Isl29035Component, which provides
 //! direct access to the ISL29035 within the kernel, and
 //! AmbientLightComponent, which provides the ambient light system
-//! call interface to the ISL29035. Note that only one of these
-//! Components should be instantiated, as AmbientLightComponent itself
-//! creates an Isl29035Component, which depends on a static buffer: if you
-//! allocate both, then the two instances of Isl29035Component will conflict
-//! on the buffer.
+//! call interface to an ambient light sensor such as the one returned by
+//! Isl29035Component.
+//!
+//! Each invocation of `isl29035_component_static!` allocates its own virtual
+//! alarm, I2C device and I2C buffer, so separate Isl29035Components never
+//! share state. The buffers are allocated with `static_buf!`, which panics at
+//! boot with "Single static_buf!() called twice" if the same invocation is
+//! evaluated twice, e.g. in a loop, rather than handing the same buffers to
+//! two live sensors. Since the sensor has a fixed I2C address, instantiate at
+//! most one Isl29035Component per I2C bus.
 //!
 //! Usage
 //! -----
 //! ```rust
 //! let isl29035 = Isl29035Component::new(mux_i2c, mux_alarm)
 //!     .finalize(components::isl29035_component_static!(sam4l::ast::Ast));
-//! let ambient_light =
-//!     AmbientLightComponent::new(board_kernel, capsules_extra::ambient_light::DRIVER_NUM, isl29035)
-//!         .finalize(components::ambient_light_component_static!());
+//! let ambient_light = AmbientLightComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ambient_light::DRIVER_NUM,
+//!     isl29035,
+//!     components::isl29035::DEFAULT_DARK_BELOW_LUX,
+//!     components::isl29035::DEFAULT_LIGHT_ABOVE_LUX,
+//! )
+//! .finalize(components::ambient_light_component_static!());
 //! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
@@ -115,22 +125,41 @@ impl<A: 'static + time::Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Co
     }
 }
 
+/// Default threshold in lux below which processes are notified that it became
+/// dark. Around 50 lux is a dim living room, where indoor lights would usually
+/// be switched on.
+pub const DEFAULT_DARK_BELOW_LUX: usize = 50;
+
+/// Default threshold in lux above which processes are notified that it became
+/// light. It is twice the dark threshold, so that the noise of readings around
+/// either threshold doesn't flip the state back and forth.
+pub const DEFAULT_LIGHT_ABOVE_LUX: usize = 100;
+
 pub struct AmbientLightComponent<L: 'static + hil::sensors::AmbientLight<'static>> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
     light_sensor: &'static L,
+    dark_below: usize,
+    light_above: usize,
 }
 
 impl<L: 'static + hil::sensors::AmbientLight<'static>> AmbientLightComponent<L> {
+    /// `dark_below` and `light_above` are the thresholds in lux at which
+    /// processes are notified that it became dark or light, see
+    /// `AmbientLight::set_thresholds`.
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
         light_sensor: &'static L,
+        dark_below: usize,
+        light_above: usize,
     ) -> Self {
         AmbientLightComponent {
             board_kernel,
             driver_num,
             light_sensor,
+            dark_below,
+            light_above,
         }
     }
 }
@@ -146,6 +175,7 @@ impl<L: 'static + hil::sensors::AmbientLight<'static>> Component for AmbientLigh
             self.light_sensor,
             self.board_kernel.create_grant(self.driver_num, &grant_cap),
         ));
+        ambient_light.set_thresholds(self.dark_below, self.light_above);
         hil::sensors::AmbientLight::set_client(self.light_sensor, ambient_light);
         ambient_light
     }
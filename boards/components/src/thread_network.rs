@@ -29,9 +29,7 @@
 use capsules_core::virtualizers::virtual_aes_ccm::MuxAES128CCM;
 use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
 use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
-use capsules_extra::net::network_capabilities::{
-    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
-};
+use capsules_extra::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB};
 
 use capsules_core::virtualizers::virtual_alarm::MuxAlarm;
@@ -212,12 +210,9 @@ impl<
         unsafe impl capabilities::UdpDriverCapability for DriverCap {}
         static DRIVER_CAP: DriverCap = DriverCap;
 
-        let net_cap = s.2.write(NetworkCapability::new(
-            AddrRange::Any,
-            PortRange::Any,
-            PortRange::Any,
-            &create_cap,
-        ));
+        // Thread attaches by multicasting MLE requests to all routers.
+        let net_cap =
+            s.2.write(NetworkCapability::unrestricted(&create_cap).with_broadcast(&create_cap));
 
         let send_buffer = s.4.write([0; MAX_PAYLOAD_LEN]);
         let recv_buffer = s.5.write([0; MAX_PAYLOAD_LEN]);
@@ -24,9 +24,7 @@
 use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
-use capsules_extra::net::network_capabilities::{
-    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
-};
+use capsules_extra::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use capsules_extra::net::udp::udp_port_table::UdpPortManager;
 use capsules_extra::net::udp::udp_recv::MuxUdpReceiver;
 use capsules_extra::net::udp::udp_recv::UDPReceiver;
@@ -135,12 +133,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         unsafe impl capabilities::UdpDriverCapability for DriverCap {}
         static DRIVER_CAP: DriverCap = DriverCap;
 
-        let net_cap = s.2.write(NetworkCapability::new(
-            AddrRange::Any,
-            PortRange::Any,
-            PortRange::Any,
-            &create_cap,
-        ));
+        let net_cap = s.2.write(NetworkCapability::unrestricted(&create_cap));
 
         let buffer = s.4.write([0; MAX_PAYLOAD_LEN]);
 
@@ -304,7 +304,7 @@ impl<A: Alarm<'static> + 'static, M: MacDevice<'static>> Component for UDPMuxCom
         let udp_recv_mux = s.6.write(MuxUdpReceiver::new());
         ip_receive.set_client(udp_recv_mux);
 
-        let udp_send_mux = s.5.write(MuxUdpSender::new(ip_send));
+        let udp_send_mux = s.5.write(MuxUdpSender::new(ip_send, ip_vis));
         ip_send.set_client(udp_send_mux);
 
         let kernel_ports = s.10.write([None; MAX_NUM_BOUND_PORTS]);
@@ -427,6 +427,8 @@ unsafe fn start() -> (
         board_kernel,
         capsules_extra::ambient_light::DRIVER_NUM,
         isl29035,
+        components::isl29035::DEFAULT_DARK_BELOW_LUX,
+        components::isl29035::DEFAULT_LIGHT_ABOVE_LUX,
     )
     .finalize(components::ambient_light_component_static!());
 
@@ -21,9 +21,7 @@ use capsules_extra::net::ieee802154::MacAddress;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
 use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules_extra::net::network_capabilities::{
-    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
-};
+use capsules_extra::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use capsules_extra::net::sixlowpan::sixlowpan_compression;
 use capsules_extra::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
 use kernel::ErrorCode;
@@ -85,7 +83,7 @@ pub unsafe fn run(
     let create_cap = create_capability!(NetworkCapabilityCreationCapability);
     let net_cap = static_init!(
         NetworkCapability,
-        NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        NetworkCapability::unrestricted(&create_cap)
     );
     let ip_vis = static_init!(
         IpVisibilityCapability,
@@ -28,7 +28,8 @@
 //! binding and sending orders to ensure that port binding and sending
 //! is enforced as expected.
 //! The messages sent are long enough to require multiple fragments. The payload of each message
-//! is all 0's.
+//! starts with a 2 byte value, followed by an incrementing pattern for mock_udp1 and a
+//! pseudo-random pattern for mock_udp2, so that reordered or corrupted fragments can be detected.
 //!
 //! start_rx() runs a test where an app and a userspace capsule both verify correctness of port
 //! binding across userspace apps and capsules, and then both attempt UDP reception on
@@ -40,8 +41,24 @@
 //! start_dual_rx() tests multiple capsules attempting to bind to different ports and receive
 //! messages in quick succession, to test in-kernel distribution of received packets.
 //!
+//! start_pattern_rx() binds both capsules to the destination ports used by start(), and verifies
+//! the payload pattern of every received packet byte-for-byte. It should be run on a second board
+//! while the first board runs start().
+//!
+//! start_frag_sweep() sends one packet from mock_udp1 for every payload length from 2 bytes up to
+//! the largest supported payload, one length after the other. This crosses every fragmentation
+//! boundary of the 6LoWPAN layer: packets that just fit in a single frame, the first ones that
+//! need a second fragment, fragments filled up to the last byte, and so on, wherever these
+//! boundaries fall for the current header compression. The first 2 bytes of each payload hold
+//! its length.
+//!
+//! start_frag_sweep_rx() should be run on a second board while the first board runs
+//! start_frag_sweep(), with the same setup requirements as start_dual_rx(). It checks both the
+//! pattern and the length of every reassembled packet.
+//!
 //! Depending on the test you want to run, replace the call to start() with calls to
-//! start_rx(), start_dual_rx(), or start_with_app().
+//! start_rx(), start_dual_rx(), start_pattern_rx(), start_frag_sweep(), start_frag_sweep_rx(),
+//! or start_with_app().
 //! Only one of these should be included at a time. Each is used for a different
 //! set of kernel tests, some of which require additional boards or that userland
 //! apps be flashed simultaneously.
@@ -64,6 +81,10 @@
 //! All UDP kernel tests complete.
 //! ```
 //!
+//! Each send test records how many `send_done` callbacks it expects. If they
+//! have not all arrived by the time the next test starts (`TEST_DELAY_MS`
+//! later), a `TEST FAIL` line reporting the missing completions is printed.
+//!
 //! start_with_app() should be used alongside the userland app `examples/tests/udp/udp_virt_app_kernel`
 //!
 //! start_with_app() expected output:
@@ -118,6 +139,22 @@
 //!
 //! [MOCK_UDP 2] Received packet from IPAddr([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]):81, contents: [72, 101, 108, 108, 111, 32, 87, 111, 114, 108, 100, 32, 45, 32, 65, 112, 112, 50, 10]
 //! ```
+//!
+//! start_pattern_rx() has the same setup requirements as start_dual_rx(). For each packet sent
+//! by send_test on the other board, it prints the received packet followed by:
+//!
+//! ```
+//! [MOCK_UDP 1] Payload of 192 bytes matches pattern
+//! ```
+//!
+//! Any corrupted byte is instead reported as `TEST FAIL: payload mismatch at byte <offset>`.
+//!
+//! start_frag_sweep() prints `Fragmentation sweep sent <n> payload lengths` once done, and reports
+//! any send that was rejected or did not complete in time as a `TEST FAIL`. On the receiving
+//! board, start_frag_sweep_rx() prints the pattern check of start_pattern_rx() for each packet,
+//! and reports packets that were reassembled with the wrong length as
+//! `TEST FAIL: received <n> bytes of a <m> byte payload`. A length missing from the received
+//! sequence indicates a packet lost at that boundary.
 
 use super::super::imix_components::test::mock_udp::MockUDPComponent;
 use crate::mock_udp_component_static;
@@ -130,7 +167,7 @@ use capsules_extra::net::network_capabilities::{
 use capsules_extra::net::udp::udp_port_table::UdpPortManager;
 use capsules_extra::net::udp::udp_recv::MuxUdpReceiver;
 use capsules_extra::net::udp::udp_send::MuxUdpSender;
-use capsules_extra::test::udp::MockUdp;
+use capsules_extra::test::udp::{MockUdp, PayloadPattern, DST_ADDR};
 use core::cell::Cell;
 use core::ptr::addr_of_mut;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
@@ -142,6 +179,9 @@ use kernel::static_init;
 use kernel::ErrorCode;
 
 pub const TEST_DELAY_MS: u32 = 2000;
+/// Delay between the sends of start_frag_sweep(), long enough for a packet
+/// of the largest size to be sent and reassembled.
+pub const FRAG_SWEEP_DELAY_MS: u32 = 100;
 pub const TEST_LOOP: bool = false;
 static mut UDP_PAYLOAD: [u8; PAYLOAD_LEN] = [0; PAYLOAD_LEN]; //Becomes payload of UDP packet
 
@@ -156,6 +196,9 @@ enum TestMode {
     WithAppMode,
     RxMode,
     DualRxMode,
+    PatternRxMode,
+    FragSweepMode,
+    FragSweepRxMode,
 }
 
 pub struct LowpanTest<'a, A: time::Alarm<'a>> {
@@ -165,6 +208,11 @@ pub struct LowpanTest<'a, A: time::Alarm<'a>> {
     mock_udp1: &'a MockUdp<'a, A>,
     mock_udp2: &'a MockUdp<'a, A>,
     test_mode: Cell<TestMode>,
+    // `send_done` counts of (`mock_udp1`, `mock_udp2`) that must be reached
+    // before the next test starts.
+    expected_send_done: Cell<Option<(usize, usize)>>,
+    // Payload length of the next packet sent by `frag_sweep_step`.
+    sweep_len: Cell<usize>,
 }
 
 pub unsafe fn initialize_all(
@@ -182,7 +230,7 @@ pub unsafe fn initialize_all(
     let create_cap = create_capability!(NetworkCapabilityCreationCapability);
     let net_cap = static_init!(
         NetworkCapability,
-        NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        NetworkCapability::unrestricted(&create_cap)
     );
     let udp_vis = static_init!(
         UdpVisibilityCapability,
@@ -237,6 +285,13 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         mock_udp1: &'static MockUdp<'a, A>,
         mock_udp2: &'static MockUdp<'a, A>,
     ) -> LowpanTest<'a, A> {
+        // Use distinct patterns so packets from the two capsules cannot be
+        // mistaken for one another.
+        mock_udp1.set_payload_pattern(PayloadPattern::Incrementing, PAYLOAD_LEN - UDP_HDR_SIZE);
+        mock_udp2.set_payload_pattern(
+            PayloadPattern::PseudoRandom(0x5a),
+            PAYLOAD_LEN - UDP_HDR_SIZE,
+        );
         LowpanTest {
             alarm,
             test_counter: Cell::new(0),
@@ -244,6 +299,8 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
             mock_udp1,
             mock_udp2,
             test_mode: Cell::new(TestMode::DefaultMode),
+            expected_send_done: Cell::new(None),
+            sweep_len: Cell::new(2),
         }
     }
 
@@ -266,10 +323,54 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         self.schedule_next();
     }
 
+    pub fn start_pattern_rx(&self) {
+        self.test_mode.set(TestMode::PatternRxMode);
+        self.schedule_next();
+    }
+
+    pub fn start_frag_sweep(&self) {
+        self.test_mode.set(TestMode::FragSweepMode);
+        self.schedule_next();
+    }
+
+    pub fn start_frag_sweep_rx(&self) {
+        self.test_mode.set(TestMode::FragSweepRxMode);
+        self.schedule_next();
+    }
+
     fn schedule_next(&self) {
-        let delta = self.alarm.ticks_from_ms(TEST_DELAY_MS);
-        let now = self.alarm.now();
-        self.alarm.set_alarm(now, delta);
+        // Fail loudly rather than run the tests with a shortened delay if
+        // `TEST_DELAY_MS` doesn't fit in the alarm's counter.
+        let delay = self
+            .alarm
+            .checked_ticks_from_ms(TEST_DELAY_MS)
+            .expect("TEST_DELAY_MS exceeds the alarm period");
+        self.alarm.set_alarm_from_now(delay);
+    }
+
+    // Record that `send_done` callbacks are expected for the sends in
+    // `results` which were accepted by the UDP stack.
+    fn expect_send_done(&self, results: (Result<(), ErrorCode>, Result<(), ErrorCode>)) {
+        self.expected_send_done.set(Some((
+            self.mock_udp1.send_done_count() + results.0.is_ok() as usize,
+            self.mock_udp2.send_done_count() + results.1.is_ok() as usize,
+        )));
+    }
+
+    // Check that all sends issued by the previous test have completed. This
+    // runs `TEST_DELAY_MS` after the sends were issued.
+    fn check_send_done(&self) {
+        if let Some((expected1, expected2)) = self.expected_send_done.take() {
+            let done1 = self.mock_udp1.send_done_count();
+            let done2 = self.mock_udp2.send_done_count();
+            if done1 < expected1 || done2 < expected2 {
+                debug!(
+                    "TEST FAIL: missing send_done callbacks (mock_udp1: {}, mock_udp2: {})",
+                    expected1.saturating_sub(done1),
+                    expected2.saturating_sub(done2)
+                );
+            }
+        }
     }
 
     fn run_test_and_increment(&self) {
@@ -282,7 +383,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
     }
 
     fn num_tests(&self) -> usize {
-        10
+        13
     }
 
     fn run_test(&self, test_id: usize) {
@@ -304,6 +405,9 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
                     7 => self.capsule_send_invalid_net_cap_port_test(),
                     8 => self.capsule_send_invalid_net_cap_addr_test(),
                     9 => self.capsule_send_invalid_net_cap_addr_port_test(),
+                    10 => self.port_table_range_test(),
+                    11 => self.port_table_ephemeral_test(),
+                    12 => self.capsule_send_dst_addr_test(),
                     _ => return,
                 }
             }
@@ -315,6 +419,23 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
                 0 => self.capsule_dual_receive_test(),
                 _ => return,
             },
+            TestMode::PatternRxMode => match test_id {
+                0 => self.capsule_pattern_receive_test(),
+                _ => return,
+            },
+            TestMode::FragSweepMode => {
+                // Paced by `frag_sweep_step` rather than `TEST_DELAY_MS`
+                if test_id == 0 {
+                    self.mock_udp1.bind(14000);
+                    self.mock_udp1.set_dst(15000);
+                }
+                self.frag_sweep_step();
+                return;
+            }
+            TestMode::FragSweepRxMode => match test_id {
+                0 => self.capsule_frag_sweep_receive_test(),
+                _ => return,
+            },
             TestMode::WithAppMode => match test_id {
                 0 => self.bind_test(),
                 1 => self.capsule_send_test(),
@@ -331,7 +452,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         let net_cap = unsafe {
             static_init!(
                 NetworkCapability,
-                NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+                NetworkCapability::unrestricted(&create_cap)
             )
         };
         let mut socket1 = self.port_table.create_socket().unwrap();
@@ -359,7 +480,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         let net_cap = unsafe {
             static_init!(
                 NetworkCapability,
-                NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+                NetworkCapability::unrestricted(&create_cap)
             )
         };
         // Initialize bindings.
@@ -429,6 +550,86 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         debug!("port_table_test2 passed");
     }
 
+    // Tests that binding a range of ports either binds all of them or none.
+    fn port_table_range_test(&self) {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let net_cap = unsafe {
+            static_init!(
+                NetworkCapability,
+                NetworkCapability::unrestricted(&create_cap)
+            )
+        };
+        let sockets = || {
+            [
+                self.port_table.create_socket().unwrap(),
+                self.port_table.create_socket().unwrap(),
+                self.port_table.create_socket().unwrap(),
+            ]
+        };
+
+        let bindings = self
+            .port_table
+            .bind_range(sockets(), 5000, net_cap)
+            .expect("UDP range bind fail");
+        for (i, (send_bind, recv_bind)) in bindings.iter().enumerate() {
+            assert_eq!(send_bind.get_port(), 5000 + i as u16);
+            assert_eq!(recv_bind.get_port(), 5000 + i as u16);
+        }
+
+        // A range overlapping a bound port binds none of its ports, including
+        // the free ones before the bound port.
+        let unbound = self.port_table.bind_range(sockets(), 4998, net_cap);
+        assert!(unbound.is_err());
+        assert_eq!(self.port_table.is_bound(4998), Ok(false));
+        // A range past the last port binds nothing.
+        assert!(self
+            .port_table
+            .bind_range(unbound.unwrap_err(), u16::MAX - 1, net_cap)
+            .is_err());
+
+        assert!(self.port_table.unbind_all(bindings).is_ok());
+        assert_eq!(self.port_table.is_bound(5000), Ok(false));
+
+        debug!("port_table_range_test passed");
+    }
+
+    // Tests that ephemeral bindings pick distinct ports that are allowed by
+    // the capability.
+    fn port_table_ephemeral_test(&self) {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let net_cap = unsafe {
+            static_init!(
+                NetworkCapability,
+                NetworkCapability::new(
+                    AddrRange::Any,
+                    PortRange::Any,
+                    PortRange::Range(60000, 60001),
+                    &create_cap
+                )
+            )
+        };
+        let (send_bind1, recv_bind1) = self
+            .port_table
+            .bind_ephemeral(self.port_table.create_socket().unwrap(), net_cap)
+            .expect("UDP ephemeral bind fail1");
+        let (send_bind2, recv_bind2) = self
+            .port_table
+            .bind_ephemeral(self.port_table.create_socket().unwrap(), net_cap)
+            .expect("UDP ephemeral bind fail2");
+        let mut ports = [send_bind1.get_port(), send_bind2.get_port()];
+        ports.sort_unstable();
+        assert_eq!(ports, [60000, 60001]);
+
+        // All ports allowed by the capability are taken
+        let socket3 = self.port_table.create_socket().unwrap();
+        assert!(self.port_table.bind_ephemeral(socket3, net_cap).is_err());
+
+        assert!(self.port_table.unbind(send_bind1, recv_bind1).is_ok());
+        assert!(self.port_table.unbind(send_bind2, recv_bind2).is_ok());
+
+        debug!("port_table_ephemeral_test passed");
+    }
+
     fn capsule_send_fail(&self) {
         let ret = self.mock_udp1.send(0);
         assert!(ret != Ok(())); //trying to send while not bound should fail!
@@ -441,10 +642,19 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         self.mock_udp1.set_dst(15000);
         self.mock_udp2.bind(14001);
         self.mock_udp2.set_dst(15001);
-        // Send from 2 different capsules in quick succession - second send should execute once
-        // first completes!
-        let _ = self.mock_udp1.send(22);
-        let _ = self.mock_udp2.send(23);
+        // Send from 2 different capsules in quick succession - second send should be queued
+        // behind the first, and execute once the first completes!
+        assert!(self.mock_udp1.is_ready() && self.mock_udp2.is_ready());
+        let depth = self.mock_udp1.queue_depth();
+        let ret1 = self.mock_udp1.send(22);
+        assert_eq!(ret1, Ok(()));
+        assert!(!self.mock_udp1.is_ready());
+        assert_eq!(self.mock_udp2.queue_depth(), depth + 1);
+        let ret2 = self.mock_udp2.send(23);
+        assert_eq!(ret2, Ok(()));
+        assert!(!self.mock_udp2.is_ready());
+        assert_eq!(self.mock_udp1.queue_depth(), depth + 2);
+        self.expect_send_done((ret1, ret2));
 
         debug!("send_test executed, look at printed results once callbacks arrive");
     }
@@ -466,6 +676,53 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         self.mock_udp2.bind(16124);
     }
 
+    // Receives the packets sent by `capsule_send_test` on another board, and
+    // checks their payload against the pattern each capsule sends with.
+    fn capsule_pattern_receive_test(&self) {
+        self.mock_udp1.set_verify_rx_payload(true);
+        self.mock_udp2.set_verify_rx_payload(true);
+        self.mock_udp1.bind(15000);
+        self.mock_udp2.bind(15001);
+    }
+
+    // Sends the packet of the current length of the sweep started with
+    // `start_frag_sweep`, and schedules the next one.
+    fn frag_sweep_step(&self) {
+        let len = self.sweep_len.get();
+        if len > PAYLOAD_LEN - UDP_HDR_SIZE {
+            debug!("Fragmentation sweep sent {} payload lengths", len - 2);
+            return;
+        }
+        // The previous send must have completed within the delay, otherwise
+        // the buffer of `mock_udp1` is still in use
+        if !self.mock_udp1.is_ready() {
+            debug!(
+                "TEST FAIL: send of a {} byte payload did not complete",
+                len - 1
+            );
+            return;
+        }
+        self.mock_udp1
+            .set_payload_pattern(PayloadPattern::Incrementing, len);
+        if let Err(e) = self.mock_udp1.send(len as u16) {
+            debug!("TEST FAIL: send of a {} byte payload failed: {:?}", len, e);
+        }
+        self.sweep_len.set(len + 1);
+        let delay = self
+            .alarm
+            .checked_ticks_from_ms(FRAG_SWEEP_DELAY_MS)
+            .expect("FRAG_SWEEP_DELAY_MS exceeds the alarm period");
+        self.alarm.set_alarm_from_now(delay);
+    }
+
+    // Receives the packets sent by `frag_sweep_step` on another board, and
+    // checks that each was reassembled with the length it was sent with.
+    fn capsule_frag_sweep_receive_test(&self) {
+        self.mock_udp1.set_verify_rx_payload(true);
+        self.mock_udp1.set_verify_rx_len(true);
+        self.mock_udp1.bind(15000);
+    }
+
     // Test network capability enforcement for addrs
     fn addr_range_valid_test(&self) {
         let ip_addr1 = IPAddr([
@@ -490,19 +747,11 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         for ip_addr in [ip_addr1, ip_addr2, ip_addr3].iter() {
             assert!(!no_addrs.is_addr_valid(*ip_addr))
         }
-        let addr_set = AddrRange::AddrSet([
-            IPAddr::new(),
-            IPAddr::new(),
-            IPAddr::new(),
-            IPAddr::new(),
-            IPAddr::new(),
-            IPAddr::new(),
-            ip_addr3,
-            ip_addr1,
-        ]);
+        let addr_set = AddrRange::from_slice(&[ip_addr3, ip_addr1]).unwrap();
         assert!(addr_set.is_addr_valid(ip_addr1));
         assert!(!addr_set.is_addr_valid(ip_addr2));
         assert!(addr_set.is_addr_valid(ip_addr3));
+        assert!(!addr_set.is_addr_valid(IPAddr::new()));
         // Single addr
         let single_addr = AddrRange::Addr(ip_addr1);
         assert!(single_addr.is_addr_valid(ip_addr1));
@@ -559,6 +808,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         // first completes, assuming valid capabilities for each.
         let ret1 = self.mock_udp1.send(22);
         let ret2 = self.mock_udp2.send(23);
+        self.expect_send_done((ret1, ret2));
         debug!("send_test executed, look at printed results once callbacks arrive");
         (ret1, ret2)
     }
@@ -631,7 +881,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         let net_cap1 = unsafe {
             static_init!(
                 NetworkCapability,
-                NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+                NetworkCapability::unrestricted(&create_cap)
             )
         };
         // net_cap2 has an invalid address range
@@ -685,10 +935,36 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
         assert_eq!(ret2, Err(ErrorCode::RESERVE));
         debug!("send_invalid_net_cap_addr_port test executed, expect one send with Result: Ok(())");
     }
+
+    // Same capability for both capsules, which only grants `DST_ADDR`, but
+    // different destination addresses.
+    fn capsule_send_dst_addr_test(&self) {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let net_cap = unsafe {
+            static_init!(
+                NetworkCapability,
+                NetworkCapability::new(
+                    AddrRange::Addr(DST_ADDR),
+                    PortRange::Any,
+                    PortRange::Any,
+                    &create_cap
+                )
+            )
+        };
+        let mut other_addr = DST_ADDR;
+        other_addr.0[15] ^= 0x01;
+        self.mock_udp2.set_dst_addr(other_addr);
+        let (ret1, ret2) = self.capsule_send_net_cap_test(net_cap, net_cap);
+        self.mock_udp2.set_dst_addr(DST_ADDR);
+        assert_eq!(ret1, Ok(()));
+        assert_eq!(ret2, Err(ErrorCode::RESERVE));
+        debug!("send_dst_addr test executed, expect one send with Result: Ok(())");
+    }
 }
 
 impl<'a, A: time::Alarm<'a>> time::AlarmClient for LowpanTest<'a, A> {
     fn alarm(&self) {
+        self.check_send_done();
         self.run_test_and_increment();
     }
 }
@@ -205,6 +205,10 @@ unsafe fn start() -> (
     );
     peripherals.init();
 
+    // Start from a clean pin configuration, the GPIO registers are not reset
+    // if the previous run rebooted without resetting the chip
+    peripherals.gpio.reset_all();
+
     // Setup the GPIO pins to use the HFXT (high frequency external) oscillator (48MHz)
     peripherals.gpio.pins[msp432::gpio::PinNr::PJ_2 as usize].enable_primary_function();
     peripherals.gpio.pins[msp432::gpio::PinNr::PJ_3 as usize].enable_primary_function();
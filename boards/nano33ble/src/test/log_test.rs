@@ -309,7 +309,7 @@ impl<A: 'static + Alarm<'static>> LogTest<A> {
                     Ok(()) => panic!("Read with too-large max read length succeeded unexpectedly!"),
                     Err((error, original_buffer)) => {
                         self.buffer.replace(original_buffer);
-                        assert_eq!(error, ErrorCode::INVAL);
+                        assert_eq!(error, ErrorCode::SIZE);
                     }
                 },
             )
@@ -377,7 +377,7 @@ impl<A: 'static + Alarm<'static>> LogTest<A> {
                     Ok(()) => panic!("Appending with too-small buffer succeeded unexpectedly!"),
                     Err((error, original_buffer)) => {
                         self.buffer.replace(original_buffer);
-                        assert_eq!(error, ErrorCode::INVAL);
+                        assert_eq!(error, ErrorCode::SIZE);
                     }
                 },
             )
@@ -592,6 +592,11 @@ impl<A: Alarm<'static>> LogWriteClient for LogTest<A> {
                     }
                 }
 
+                // Make sure that the log is empty, with the read position at its start.
+                assert_eq!(self.log.log_start(), self.log.log_end());
+                assert_eq!(self.log.next_read_entry_id(), self.log.log_start());
+                assert_eq!(self.log.used_size(), 0);
+
                 // Make sure that a read on an empty log fails normally.
                 self.buffer.take().map(move |buffer| {
                     if let Err((error, original_buffer)) = self.log.read(buffer, BUFFER_LEN) {
@@ -0,0 +1,72 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Test stopping the cycle counter through `mcountinhibit`, and writing it.
+
+use crate::tests::run_kernel_op;
+use kernel::debug;
+use rv32i::csr::CSR;
+
+fn busy_work() {
+    for i in 0..1000 {
+        core::hint::black_box(i);
+    }
+}
+
+#[test_case]
+fn mcycle_inhibit() {
+    debug!("check mcycle stops while inhibited... ");
+    run_kernel_op(100);
+
+    CSR.inhibit_cycle();
+    assert!(!CSR.cycle_counter_running());
+    let before = CSR.read_cycle_counter();
+    busy_work();
+    let after = CSR.read_cycle_counter();
+    CSR.allow_cycle();
+
+    assert_eq!(before, after);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn mcycle_allow() {
+    debug!("check mcycle advances while allowed... ");
+    run_kernel_op(100);
+
+    CSR.allow_cycle();
+    assert!(CSR.cycle_counter_running());
+    let before = CSR.read_cycle_counter();
+    busy_work();
+    let after = CSR.read_cycle_counter();
+
+    assert!(after > before);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn mcycle_write() {
+    debug!("check mcycle can be written... ");
+    run_kernel_op(100);
+
+    // Stop the counter so the written value can be read back exactly. Use a
+    // value whose lower half is about to wrap, so a carry into the upper half
+    // would be noticed.
+    CSR.inhibit_cycle();
+    CSR.write_cycle_counter(0x1_ffff_fff0);
+    let value = CSR.read_cycle_counter();
+    CSR.reset_cycle_counter();
+    let reset = CSR.read_cycle_counter();
+    CSR.allow_cycle();
+
+    assert_eq!(value, 0x1_ffff_fff0);
+    assert_eq!(reset, 0);
+
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
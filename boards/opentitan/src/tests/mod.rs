@@ -57,6 +57,7 @@ mod aes_test;
 mod csrng;
 mod flash;
 mod hmac;
+mod mcycle;
 mod multi_alarm;
 mod otbn;
 mod rsa;
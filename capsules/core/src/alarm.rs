@@ -5,15 +5,46 @@
 //! Tock syscall driver capsule for Alarms, which issue callbacks when
 //! a point in time has been reached.
 
+use core::cell::Cell;
+
+use kernel::capabilities::AlarmDiagnosticsCapability;
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
-use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::{ErrorCode, ProcessId};
 
+#[cfg(feature = "trace_alarm")]
+use crate::trace_throttle::TraceThrottle;
+
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Alarm as usize;
 
+/// Bits of the feature mask returned by command 0, which allows userspace to
+/// detect which ways of setting an alarm are supported.
+pub mod features {
+    /// Setting an absolute expiration, the removed command 4.
+    pub const ABSOLUTE: u32 = 1 << 0;
+    /// Setting an expiration relative to now, command 5.
+    pub const RELATIVE: u32 = 1 << 1;
+    /// Periodic alarms.
+    pub const PERIODIC: u32 = 1 << 2;
+    /// Setting an expiration relative to a reference point, command 6.
+    pub const REFERENCE: u32 = 1 << 3;
+    /// Storing a wall-clock offset, commands 7 and 8.
+    pub const EPOCH_OFFSET: u32 = 1 << 4;
+    /// Reading the counter and setting alarms with 64-bit tick values,
+    /// commands 9 to 11. Only with the `alarm_64bit` feature and a 64-bit
+    /// counter.
+    pub const TICKS64: u32 = 1 << 5;
+    /// Reading the next expiration of the driver, command 12. Only if the
+    /// board enabled it with `AlarmDriver::enable_diagnostics`.
+    pub const DIAGNOSTICS: u32 = 1 << 6;
+}
+
+/// Features supported by this driver, as reported by command 0.
+const FEATURES: u32 = features::RELATIVE | features::REFERENCE | features::EPOCH_OFFSET;
+
 #[derive(Copy, Clone, Debug)]
 struct Expiration<T: Ticks> {
     reference: T,
@@ -38,6 +69,16 @@ pub struct AlarmDriver<'a, A: Alarm<'a>> {
     alarm: &'a A,
     app_alarms:
         Grant<AlarmData<A::Ticks>, UpcallCount<NUM_UPCALLS>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// Largest left-justified `dt` accepted from userspace.
+    max_dt: Cell<u32>,
+    /// Offset between the counter and wall-clock time, shared by all
+    /// processes. Set and read through commands 7 and 8.
+    epoch_offset: Cell<u64>,
+    /// Whether processes may read the next expiration with command 12.
+    diagnostics: Cell<bool>,
+    /// Rate limit for the traces of the `trace_alarm` feature.
+    #[cfg(feature = "trace_alarm")]
+    trace: TraceThrottle,
 }
 
 impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
@@ -53,9 +94,99 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
         AlarmDriver {
             alarm,
             app_alarms: grant,
+            max_dt: Cell::new(u32::MAX),
+            epoch_offset: Cell::new(0),
+            diagnostics: Cell::new(false),
+            #[cfg(feature = "trace_alarm")]
+            trace: TraceThrottle::new(1),
         }
     }
 
+    /// Like `new`, but only trace one out of every `trace_every` expired
+    /// alarms with the `trace_alarm` feature.
+    #[cfg(feature = "trace_alarm")]
+    pub const fn new_with_trace_every(
+        alarm: &'a A,
+        grant: Grant<
+            AlarmData<A::Ticks>,
+            UpcallCount<NUM_UPCALLS>,
+            AllowRoCount<0>,
+            AllowRwCount<0>,
+        >,
+        trace_every: u32,
+    ) -> AlarmDriver<'a, A> {
+        AlarmDriver {
+            trace: TraceThrottle::new(trace_every),
+            ..Self::new(alarm, grant)
+        }
+    }
+
+    /// Returns whether the frequency of the underlying alarm can be reported
+    /// to userspace: it must be nonzero, and still fit in a `u32` once
+    /// scaled for the left-justified ticks (see command 1). A misconfigured
+    /// clock would otherwise only show up as wrong alarm timing in apps.
+    pub fn frequency_is_valid() -> bool {
+        let freq = A::Frequency::frequency() as u64;
+        freq != 0 && freq << A::Ticks::u32_padding() <= u32::MAX as u64
+    }
+
+    /// Whether commands 9 to 11 are available: they need the `alarm_64bit`
+    /// feature, and a counter with the full 64 bits so that its tick values
+    /// don't wrap around.
+    fn ticks64_supported() -> bool {
+        cfg!(feature = "alarm_64bit") && A::Ticks::width() >= 64
+    }
+
+    /// Features supported by this driver, as reported by command 0.
+    fn features(&self) -> u32 {
+        let mut mask = FEATURES;
+        if Self::ticks64_supported() {
+            mask |= features::TICKS64;
+        }
+        if self.diagnostics.get() {
+            mask |= features::DIAGNOSTICS;
+        }
+        mask
+    }
+
+    /// Arm `expiration` to fire at the absolute 64-bit tick value
+    /// `expires_at`, which fires right away if it already passed. Returns
+    /// the tick value the alarm will fire at.
+    ///
+    /// This requires a counter which doesn't wrap around, see
+    /// `ticks64_supported`.
+    fn rearm_u64_expiration(
+        now: A::Ticks,
+        expires_at: u64,
+        expiration: &mut Option<Expiration<A::Ticks>>,
+    ) -> u64 {
+        let expires_at = core::cmp::max(expires_at, now.into_u64());
+        *expiration = Some(Expiration {
+            reference: now,
+            dt: A::Ticks::from_or_max(expires_at - now.into_u64()),
+        });
+        expires_at
+    }
+
+    /// Limit the `dt` that processes may request through commands 5, 6, 10
+    /// and 11.
+    ///
+    /// `max_dt` is expressed in the same left-justified units as the `dt`
+    /// passed by userspace (see command 1). Requests exceeding it are
+    /// rejected with `INVAL`. By default any `dt` is accepted; setting this
+    /// to e.g. `1 << 31` (half of the tick space) prevents a process from
+    /// arming an alarm which effectively never fires.
+    pub fn set_max_dt(&self, max_dt: u32) {
+        self.max_dt.set(max_dt);
+    }
+
+    /// Let processes read the next expiration programmed into the
+    /// underlying alarm with command 12, to debug alarms that don't fire.
+    /// This is shared by all processes, so it is disabled by default.
+    pub fn enable_diagnostics(&self, _cap: &dyn AlarmDiagnosticsCapability) {
+        self.diagnostics.set(true);
+    }
+
     /// Find the earliest [`Expiration`] from an iterator of expirations.
     ///
     /// Each [`Expiration`] value is provided as a tuple, with
@@ -150,6 +281,10 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
     /// - invoke upcalls for all expired app alarms, resetting them afterwards,
     /// - re-arming the alarm for the next earliest [`Expiration`], or
     /// - disarming the alarm if no unexpired [`Expiration`] is found.
+    ///
+    /// All expired alarms are handled in a single, non-recursive pass, so
+    /// stack usage does not grow with the number of alarms that expired at
+    /// the same time.
     fn process_rearm_or_callback(&self) {
         // Ask the clock about a current reference once. This can incur a
         // volatile read, and this may not be optimized if done in a loop:
@@ -160,12 +295,27 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
             // closure on the Grant iterator has returned. We are thus not
             // risking reentrancy here.
 
+            #[cfg(feature = "trace_alarm")]
+            if self.trace.should_trace() {
+                #[cfg(not(test))]
+                kernel::debug!(
+                    "alarm: {:?} fired {} late",
+                    process_id,
+                    time::TicksDuration::<A::Ticks, A::Frequency>::new(
+                        now.wrapping_sub(expired.reference.wrapping_add(expired.dt))
+                    )
+                );
+            }
+
             // Enter the app's grant again:
             let _ = self.app_alarms.enter(*process_id, |alarm_state, upcalls| {
                 // Reset this app's alarm:
                 alarm_state.expiration = None;
 
-                // Deliver the upcall:
+                // Deliver the upcall. An alarm upcall the process has not
+                // serviced yet is stale, replace it rather than queueing
+                // another one (see the subscribe documentation):
+                upcalls.remove_pending_upcalls(ALARM_CALLBACK_NUM).ok();
                 upcalls
                     .schedule_upcall(
                         ALARM_CALLBACK_NUM,
@@ -207,8 +357,12 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
                 let _ = self.alarm.disarm();
             }
 
-            // A future, non-expired alarm should fire:
-            Ok(Some((Expiration { reference, dt }, _))) => {
+            // A future, non-expired alarm should fire. Iterating over all
+            // grants may have taken a while, so check against a fresh `now`
+            // whether it is still in the future:
+            Ok(Some((expiration, _))) => {
+                let (reference, dt) =
+                    Self::rearm_reference_dt(self.alarm.now(), expiration, self.alarm.minimum_dt());
                 self.alarm.set_alarm(reference, dt);
             }
 
@@ -220,6 +374,32 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
         }
     }
 
+    /// Returns the `reference` and `dt` to program the underlying alarm with
+    /// for `expiration`. If `expiration` has passed by `now`, the alarm is set
+    /// to fire after `minimum_dt` instead, so that it fires promptly rather
+    /// than relying on the underlying alarm to detect that it is already in
+    /// the past.
+    fn rearm_reference_dt(
+        now: A::Ticks,
+        expiration: Expiration<A::Ticks>,
+        minimum_dt: A::Ticks,
+    ) -> (A::Ticks, A::Ticks) {
+        let Expiration { reference, dt } = expiration;
+        if now.within_range(reference, reference.wrapping_add(dt)) {
+            (reference, dt)
+        } else {
+            (now, minimum_dt)
+        }
+    }
+
+    /// Raise a left-justified `dt` requested relative to now to at least
+    /// `minimum_dt`. The underlying alarm would enforce the minimum anyway,
+    /// but applying it here lets the driver report the time at which the
+    /// alarm actually fires.
+    fn clamp_u32_left_justified_dt(dt_u32: u32, minimum_dt: A::Ticks) -> u32 {
+        dt_u32.max(minimum_dt.into_u32_left_justified())
+    }
+
     fn rearm_u32_left_justified_expiration(
         now: A::Ticks,
         reference_u32: Option<u32>,
@@ -249,6 +429,13 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
             dt_u32 >> A::Ticks::u32_padding()
         };
 
+        // Rounding up can push a `dt` close to the full 32 bit range just
+        // past the width of a narrower timer, e.g. to `1 << 16` for a 16-bit
+        // timer. Converting that would truncate it to 0 and fire the alarm
+        // right away, so saturate to the longest representable `dt` instead,
+        // which fires less than one tick early.
+        let dt = A::Ticks::from_or_max(dt_unshifted as u64);
+
         // For timers less than 32-bit wide, we do not have to handle a
         // `reference + dt` overflow specially. This is because those timers are
         // conveyed to us left-justified, and as such userspace would already
@@ -284,24 +471,25 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
                     // 3. adding back the userspace-provided reference.
 
                     // Build 1 << 32:
-                    let bit33 = A::Ticks::from(0xffffffff).wrapping_add(A::Ticks::from(0x1));
+                    let bit33 =
+                        A::Ticks::from_raw(0xffffffff).wrapping_add(A::Ticks::from_raw(0x1));
 
                     // Perform step 1, subtracting 1 << 32:
                     let sub_1_upper = now.wrapping_sub(bit33);
 
                     // Perform step 2, setting first 32 bit to zero:
                     let sub_lower =
-                        sub_1_upper.wrapping_sub(A::Ticks::from(sub_1_upper.into_u32()));
+                        sub_1_upper.wrapping_sub(A::Ticks::from_raw(sub_1_upper.into_u32()));
 
                     // Perform step 3, add back the userspace-provided reference:
                     let rebased_reference =
-                        sub_lower.wrapping_add(A::Ticks::from(userspace_reference_unshifted));
+                        sub_lower.wrapping_add(A::Ticks::from_raw(userspace_reference_unshifted));
 
                     // Finally, return the new expiration. We don't have to do
                     // anything special for `dt`, as it's relative:
                     Expiration {
                         reference: rebased_reference,
-                        dt: A::Ticks::from(dt_unshifted),
+                        dt,
                     }
                 } else {
                     // The lower 32-bit of reference are equal to or larger than the
@@ -313,17 +501,17 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
                     // 2. adding back the userspace-provided reference.
 
                     // Perform step 1, setting first 32 bit to zero:
-                    let sub_lower = now.wrapping_sub(A::Ticks::from(now.into_u32()));
+                    let sub_lower = now.wrapping_sub(A::Ticks::from_raw(now.into_u32()));
 
                     // Perform step 2, add back the userspace-provided reference:
                     let rebased_reference =
-                        sub_lower.wrapping_add(A::Ticks::from(userspace_reference_unshifted));
+                        sub_lower.wrapping_add(A::Ticks::from_raw(userspace_reference_unshifted));
 
                     // Finally, return the new expiration. We don't have to do
                     // anything special for `dt`, as it's relative:
                     Expiration {
                         reference: rebased_reference,
-                        dt: A::Ticks::from(dt_unshifted),
+                        dt,
                     }
                 }
             }
@@ -332,17 +520,14 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
                 // We have a userspace reference and timer is (less than) 32
                 // bit. Simply set to unshifted values:
                 Expiration {
-                    reference: A::Ticks::from(userspace_reference_unshifted),
-                    dt: A::Ticks::from(dt_unshifted),
+                    reference: A::Ticks::from_raw(userspace_reference_unshifted),
+                    dt,
                 }
             }
 
             (None, _) => {
                 // We have no userspace reference. Use `now` as a reference:
-                Expiration {
-                    reference: now,
-                    dt: A::Ticks::from(dt_unshifted),
-                }
+                Expiration { reference: now, dt }
             }
         };
 
@@ -362,7 +547,8 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
     ///
     /// ### `command_num`
     ///
-    /// - `0`: Driver existence check.
+    /// - `0`: Driver existence check. Returns a mask of the [`features`]
+    ///        supported by this driver.
     /// - `1`: Return the clock frequency in Hz.
     /// - `2`: Read the current clock value
     /// - `3`: Stop the alarm if it is outstanding
@@ -370,6 +556,29 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
     /// - `5`: Set an alarm to fire at a given clock value `time` relative to `now`
     /// - `6`: Set an alarm to fire at a given clock value `time` relative to a provided
     ///        reference point.
+    /// - `7`: Set the epoch offset to the 64-bit value with the lower half in
+    ///        `data` and the upper half in `data2`.
+    /// - `8`: Read the epoch offset.
+    /// - `9`: Read the current clock value as 64-bit ticks.
+    /// - `10`: Set an alarm to fire `dt` ticks from now, with the lower half
+    ///        of the 64-bit `dt` in `data` and the upper half in `data2`.
+    /// - `11`: Set an alarm to fire at an absolute 64-bit tick value, split
+    ///        like for command `10`.
+    /// - `12`: Read the left-justified reference and `dt` of the next
+    ///        expiration programmed into the underlying alarm, or fail with
+    ///        `OFF` if no alarm is armed. Their sum is the expiration, but
+    ///        underlying alarms that don't override `get_alarm_reference`
+    ///        report the synthetic reference `get_alarm() - half_max_value()`
+    ///        and the matching `dt`, not the values they were set with.
+    ///
+    /// Commands `9` to `11` need the `alarm_64bit` feature and a 64-bit
+    /// counter, and fail with `NOSUPPORT` otherwise. Their tick values are
+    /// not left-justified. Command `12` fails with `NOSUPPORT` unless it was
+    /// enabled with [`AlarmDriver::enable_diagnostics`].
+    ///
+    /// Commands `5`, `6`, `10` and `11` fail with `INVAL` if `dt`, or for
+    /// command `11` the time from now to the expiration, exceeds the limit
+    /// configured with [`AlarmDriver::set_max_dt`].
     fn command(
         &self,
         cmd_type: usize,
@@ -387,10 +596,10 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                 let now = self.alarm.now();
 
                 match cmd_type {
-                    // Driver check:
+                    // Driver check, reporting the supported features:
                     //
                     // Don't re-arm the timer:
-                    0 => (CommandReturn::success(), false),
+                    0 => (CommandReturn::success_u32(self.features()), false),
 
                     1 => {
                         // Get clock frequency. We return a frequency scaled by
@@ -400,6 +609,7 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                         // accurately determine the duration of a single tick.
                         //
                         // Don't re-arm the timer:
+                        debug_assert!(Self::frequency_is_valid());
                         let scaled_freq =
                             <A::Ticks>::u32_left_justified_scale_freq::<A::Frequency>();
                         (CommandReturn::success_u32(scaled_freq), false)
@@ -444,6 +654,19 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                     5 => {
                         // Set relative expiration.
                         //
+                        // Reject a `dt` larger than this driver is configured
+                        // to accept. Don't re-arm the timer:
+                        if data as u32 > self.max_dt.get() {
+                            return (CommandReturn::failure(ErrorCode::INVAL), false);
+                        }
+
+                        // A `dt` below what the underlying alarm supports is
+                        // raised to its minimum, so that the returned
+                        // expiration is the one the alarm will actually fire
+                        // at:
+                        let dt =
+                            Self::clamp_u32_left_justified_dt(data as u32, self.alarm.minimum_dt());
+
                         // We provided userspace a potentially padded version of
                         // our in-kernel Ticks object, and as such we have to
                         // invert that operation through a right shift.
@@ -457,8 +680,8 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                             now,
                             // No userspace-provided reference:
                             None,
-                            // Left-justified `dt` value:
-                            data as u32,
+                            // Left-justified, clamped `dt` value:
+                            dt,
                             // Reference to the `Option<Expiration>`, also used
                             // to update the counter of armed alarms:
                             &mut td.expiration,
@@ -471,6 +694,14 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                         (CommandReturn::success_u32(new_exp_left_justified), true)
                     }
                     6 => {
+                        // Set expiration relative to a reference point.
+                        //
+                        // Reject a `dt` larger than this driver is configured
+                        // to accept. Don't re-arm the timer:
+                        if data2 as u32 > self.max_dt.get() {
+                            return (CommandReturn::failure(ErrorCode::INVAL), false);
+                        }
+
                         // Also, we need to keep track of the currently armed
                         // timers.
                         //
@@ -493,6 +724,82 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                         // the grant region:
                         (CommandReturn::success_u32(new_exp_left_justified), true)
                     }
+                    7 => {
+                        // Set the epoch offset. This is shared by all
+                        // processes, such that a single clock process can
+                        // establish the time for everyone else. The driver
+                        // doesn't interpret the value, it is up to userspace
+                        // to add it to the ticks read with command 2.
+                        //
+                        // Don't re-arm the timer:
+                        let offset = (data2 as u32 as u64) << 32 | data as u32 as u64;
+                        self.epoch_offset.set(offset);
+                        (CommandReturn::success(), false)
+                    }
+                    8 => {
+                        // Read the epoch offset.
+                        //
+                        // Don't re-arm the timer:
+                        (CommandReturn::success_u64(self.epoch_offset.get()), false)
+                    }
+                    9..=11 if !Self::ticks64_supported() => {
+                        // Don't re-arm the timer:
+                        (CommandReturn::failure(ErrorCode::NOSUPPORT), false)
+                    }
+                    9 => {
+                        // Capture time as 64-bit ticks.
+                        //
+                        // Don't re-arm the timer:
+                        (CommandReturn::success_u64(now.into_u64()), false)
+                    }
+                    10 | 11 => {
+                        // Set a relative or absolute 64-bit expiration. The
+                        // counter doesn't wrap around, so both can be
+                        // expressed as an absolute tick value.
+                        let value = (data2 as u32 as u64) << 32 | data as u32 as u64;
+                        let expires_at = if cmd_type == 10 {
+                            let dt = core::cmp::max(value, self.alarm.minimum_dt().into_u64());
+                            now.into_u64().saturating_add(dt)
+                        } else {
+                            value
+                        };
+                        // Reject an expiration further away than this
+                        // driver is configured to accept. A 64-bit counter
+                        // has no padding, so `max_dt` is in plain ticks.
+                        // Don't re-arm the timer:
+                        if expires_at.saturating_sub(now.into_u64()) > self.max_dt.get() as u64 {
+                            return (CommandReturn::failure(ErrorCode::INVAL), false);
+                        }
+                        let expires_at =
+                            Self::rearm_u64_expiration(now, expires_at, &mut td.expiration);
+
+                        // Report success, with the time at which the alarm
+                        // will fire. Also ask for the timer to be re-armed:
+                        (CommandReturn::success_u64(expires_at), true)
+                    }
+
+                    12 if !self.diagnostics.get() => {
+                        // Don't re-arm the timer:
+                        (CommandReturn::failure(ErrorCode::NOSUPPORT), false)
+                    }
+                    12 => {
+                        // Read the next expiration. The underlying alarm is
+                        // always set to the earliest expiration of all
+                        // processes, or disarmed if there is none.
+                        //
+                        // Don't re-arm the timer:
+                        if self.alarm.is_armed() {
+                            (
+                                CommandReturn::success_u32_u32(
+                                    self.alarm.get_alarm_reference().into_u32_left_justified(),
+                                    self.alarm.get_alarm_dt().into_u32_left_justified(),
+                                ),
+                                false,
+                            )
+                        } else {
+                            (CommandReturn::failure(ErrorCode::OFF), false)
+                        }
+                    }
 
                     // Unknown command:
                     //
@@ -525,56 +832,11 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
 #[cfg(test)]
 mod test {
     use core::cell::Cell;
-    use core::marker::PhantomData;
 
-    use kernel::hil::time::{
-        Alarm, AlarmClient, Freq10MHz, Frequency, Ticks, Ticks24, Ticks32, Ticks64, Time,
-    };
-    use kernel::utilities::cells::OptionalCell;
-    use kernel::ErrorCode;
+    use kernel::hil::time::{Freq10MHz, Freq32KHz, Ticks, Ticks16, Ticks24, Ticks32, Ticks64};
 
     use super::{AlarmDriver, Expiration};
-
-    struct MockAlarm<'a, T: Ticks, F: Frequency> {
-        current_ticks: Cell<T>,
-        client: OptionalCell<&'a dyn AlarmClient>,
-        _frequency: PhantomData<F>,
-    }
-
-    impl<'a, T: Ticks, F: Frequency> Time for MockAlarm<'a, T, F> {
-        type Frequency = F;
-        type Ticks = T;
-
-        fn now(&self) -> Self::Ticks {
-            self.current_ticks.get()
-        }
-    }
-
-    impl<'a, T: Ticks, F: Frequency> Alarm<'a> for MockAlarm<'a, T, F> {
-        fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
-            self.client.set(client);
-        }
-
-        fn set_alarm(&self, _reference: Self::Ticks, _dt: Self::Ticks) {
-            unimplemented!()
-        }
-
-        fn get_alarm(&self) -> Self::Ticks {
-            unimplemented!()
-        }
-
-        fn disarm(&self) -> Result<(), ErrorCode> {
-            unimplemented!()
-        }
-
-        fn is_armed(&self) -> bool {
-            unimplemented!()
-        }
-
-        fn minimum_dt(&self) -> Self::Ticks {
-            unimplemented!()
-        }
-    }
+    use crate::test::mock_alarm::MockAlarm;
 
     #[test]
     fn test_earliest_alarm_no_alarms() {
@@ -744,6 +1006,38 @@ mod test {
         assert!(bool_exp_list == [true, false, false, false, true, true, true]);
     }
 
+    #[test]
+    fn test_earliest_alarm_all_expired() {
+        let fired_count: [Cell<usize>; 5] = Default::default();
+
+        let exp_handler = |_exp, id: &usize| -> Option<()> {
+            fired_count[*id].set(fired_count[*id].get() + 1);
+            None
+        };
+
+        // All alarms lie in the past, as on a heavily delayed system:
+        let res = AlarmDriver::<MockAlarm<Ticks32, Freq10MHz>>::earliest_alarm(
+            // Now:
+            1000_u32.into(),
+            // Expirations:
+            (0..5_usize).map(|id| {
+                (
+                    Expiration {
+                        reference: (id as u32 * 100).into(),
+                        dt: 50_u32.into(),
+                    },
+                    id,
+                    &exp_handler,
+                )
+            }),
+        );
+
+        // Iteration was not stopped, and every alarm fired exactly once
+        // within the single call:
+        assert!(res.is_ok());
+        assert!(fired_count.iter().all(|count| count.get() == 1));
+    }
+
     #[test]
     fn test_earliest_alarm_expired_stop() {
         let exp_list: [Cell<bool>; 4] = Default::default();
@@ -847,6 +1141,34 @@ mod test {
         assert_eq!(expiration.dt.into_u32(), 1234);
     }
 
+    #[test]
+    fn test_frequency_is_valid() {
+        assert!(AlarmDriver::<MockAlarm<Ticks32, Freq10MHz>>::frequency_is_valid());
+        assert!(AlarmDriver::<MockAlarm<Ticks24, Freq10MHz>>::frequency_is_valid());
+        assert!(AlarmDriver::<MockAlarm<Ticks16, Freq32KHz>>::frequency_is_valid());
+        // 10MHz shifted left by 16 bits doesn't fit in a u32:
+        assert!(!AlarmDriver::<MockAlarm<Ticks16, Freq10MHz>>::frequency_is_valid());
+    }
+
+    #[test]
+    fn test_clamp_24bit_left_justified_dt() {
+        type Driver<'a> = AlarmDriver<'a, MockAlarm<'a, Ticks24, Freq10MHz>>;
+        let min = Ticks24::from(3_u32);
+
+        // A `dt` of less than the minimum is raised to it:
+        assert_eq!(Driver::clamp_u32_left_justified_dt(0, min), 3 << 8);
+        assert_eq!(
+            Driver::clamp_u32_left_justified_dt((2 << 8) + 0xff, min),
+            3 << 8
+        );
+        // Larger ones are unchanged:
+        assert_eq!(
+            Driver::clamp_u32_left_justified_dt((3 << 8) + 1, min),
+            (3 << 8) + 1
+        );
+        assert_eq!(Driver::clamp_u32_left_justified_dt(u32::MAX, min), u32::MAX);
+    }
+
     #[test]
     fn test_rearm_24bit_left_justified_noref_wrapping() {
         let mut expiration = None;
@@ -1067,4 +1389,185 @@ mod test {
         assert_eq!(expiration.reference.into_u64(), 0xDEACCAFEB0BA_u64);
         assert_eq!(expiration.dt.into_u64(), 0x1BADB002_u64);
     }
+
+    #[test]
+    fn test_rearm_u64_beyond_32bit() {
+        #[cfg(feature = "alarm_64bit")]
+        assert!(AlarmDriver::<MockAlarm<Ticks64, Freq10MHz>>::ticks64_supported());
+        assert!(!AlarmDriver::<MockAlarm<Ticks32, Freq10MHz>>::ticks64_supported());
+
+        let now = Ticks64::from(0xFFFF_FFF0_u64);
+        let mut expiration = None;
+
+        // Relative to now, crossing the 32-bit boundary:
+        let armed_time = AlarmDriver::<MockAlarm<Ticks64, Freq10MHz>>::rearm_u64_expiration(
+            now,
+            0xFFFF_FFF0_u64 + 0x100,
+            &mut expiration,
+        );
+        let exp = expiration.unwrap();
+        assert_eq!(armed_time, 0x1_0000_00F0_u64);
+        assert_eq!(exp.reference.into_u64(), 0xFFFF_FFF0_u64);
+        assert_eq!(exp.dt.into_u64(), 0x100_u64);
+
+        // Far beyond the 32-bit range:
+        let armed_time = AlarmDriver::<MockAlarm<Ticks64, Freq10MHz>>::rearm_u64_expiration(
+            now,
+            0xDEAD_BEEF_CAFE_u64,
+            &mut expiration,
+        );
+        let exp = expiration.unwrap();
+        assert_eq!(armed_time, 0xDEAD_BEEF_CAFE_u64);
+        assert_eq!(
+            exp.reference.wrapping_add(exp.dt).into_u64(),
+            0xDEAD_BEEF_CAFE_u64
+        );
+        assert!(Ticks64::from(0x1_0000_0000_u64)
+            .within_range(exp.reference, exp.reference.wrapping_add(exp.dt)));
+
+        // An expiration that already passed fires right away:
+        let armed_time = AlarmDriver::<MockAlarm<Ticks64, Freq10MHz>>::rearm_u64_expiration(
+            now,
+            0x10_u64,
+            &mut expiration,
+        );
+        assert_eq!(armed_time, 0xFFFF_FFF0_u64);
+        assert_eq!(expiration.unwrap().dt.into_u64(), 0);
+    }
+
+    #[test]
+    fn test_rearm_reference_dt_future() {
+        let (reference, dt) = AlarmDriver::<MockAlarm<Ticks32, Freq10MHz>>::rearm_reference_dt(
+            // Now:
+            50_u32.into(),
+            Expiration {
+                reference: 42_u32.into(),
+                dt: 10_u32.into(),
+            },
+            // Minimum dt:
+            2_u32.into(),
+        );
+        assert_eq!(reference.into_u32(), 42);
+        assert_eq!(dt.into_u32(), 10);
+    }
+
+    #[test]
+    fn test_rearm_reference_dt_passed() {
+        // The expiration at 52 passed while computing the earliest alarm:
+        for now in [52_u32, 60, u32::MAX] {
+            let (reference, dt) = AlarmDriver::<MockAlarm<Ticks32, Freq10MHz>>::rearm_reference_dt(
+                now.into(),
+                Expiration {
+                    reference: 42_u32.into(),
+                    dt: 10_u32.into(),
+                },
+                2_u32.into(),
+            );
+            assert_eq!(reference.into_u32(), now);
+            assert_eq!(dt.into_u32(), 2);
+        }
+    }
+
+    #[test]
+    fn test_rearm_16bit_left_justified_noref_max_dt() {
+        let mut expiration = None;
+
+        assert!(Ticks16::u32_padding() == 16);
+
+        let armed_time =
+            AlarmDriver::<MockAlarm<Ticks16, Freq10MHz>>::rearm_u32_left_justified_expiration(
+                // Current time:
+                Ticks16::from(1337_u32),
+                // No userspace-provided reference:
+                None,
+                // Left-justified `dt` value, which rounded up is one past the
+                // largest 16-bit value:
+                u32::MAX,
+                // Reference to the `Option<Expiration>`, also used
+                // to update the counter of armed alarms:
+                &mut expiration,
+            );
+
+        let expiration = expiration.unwrap();
+
+        // dt saturates instead of wrapping around to 0:
+        assert_eq!(expiration.reference.into_u32(), 1337);
+        assert_eq!(expiration.dt.into_u32(), 0xffff);
+        assert_eq!(armed_time, 1336 << Ticks16::u32_padding());
+    }
+
+    /// Check `earliest_alarm` and `rearm_reference_dt` for expirations around
+    /// the wraparound of `T`, which for timers narrower than 32 bit is not at
+    /// `u32::MAX`.
+    fn check_expirations_across_wrap<T: Ticks>() {
+        let max = T::max_value();
+        let exp_handler = |exp, id: &usize| -> Option<()> {
+            panic!("Alarm should not be expired: {:?}, id: {}", exp, id)
+        };
+
+        // Now is just before the wrap, both alarms expire after it:
+        let now = max.wrapping_sub(T::from(1_u32));
+        let (earliest, id) = AlarmDriver::<MockAlarm<T, Freq10MHz>>::earliest_alarm(
+            now,
+            [
+                (
+                    // Will expire at 7:
+                    Expiration {
+                        reference: max.wrapping_sub(T::from(2_u32)),
+                        dt: T::from(10_u32),
+                    },
+                    0,
+                    exp_handler,
+                ),
+                (
+                    // Will expire at 3:
+                    Expiration {
+                        reference: now,
+                        dt: T::from(5_u32),
+                    },
+                    1,
+                    exp_handler,
+                ),
+            ]
+            .into_iter(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(earliest.reference, now);
+
+        // Now is just after the wrap. An expiration at 0 has passed, one at 7
+        // has not:
+        let now = T::from(2_u32);
+        let passed = Expiration {
+            reference: max.wrapping_sub(T::from(2_u32)),
+            dt: T::from(3_u32),
+        };
+        let (reference, dt) =
+            AlarmDriver::<MockAlarm<T, Freq10MHz>>::rearm_reference_dt(now, passed, T::from(1_u32));
+        assert_eq!((reference, dt), (now, T::from(1_u32)));
+
+        let future = Expiration {
+            reference: max.wrapping_sub(T::from(2_u32)),
+            dt: T::from(10_u32),
+        };
+        let (reference, dt) =
+            AlarmDriver::<MockAlarm<T, Freq10MHz>>::rearm_reference_dt(now, future, T::from(1_u32));
+        assert_eq!((reference, dt), (future.reference, future.dt));
+    }
+
+    #[test]
+    fn test_expirations_across_wrap_16bit() {
+        check_expirations_across_wrap::<Ticks16>();
+    }
+
+    #[test]
+    fn test_expirations_across_wrap_24bit() {
+        check_expirations_across_wrap::<Ticks24>();
+    }
+
+    #[test]
+    fn test_expirations_across_wrap_32bit() {
+        check_expirations_across_wrap::<Ticks32>();
+    }
 }
@@ -33,9 +33,7 @@ impl<'a, A: Alarm<'a>> TestAlarm<'a, A> {
     fn set_next_alarm(&self, ms: u32) {
         self.ms.set(ms);
         let now: A::Ticks = self.alarm.now();
-        let freq: u64 = <A::Frequency>::frequency() as u64;
-        let lticks: u64 = ms as u64 * freq;
-        let ticks: u32 = (lticks / 1000) as u32;
+        let ticks: u32 = <A::Frequency>::ticks_in_ms(ms);
         debug!("Setting alarm to {} + {}", now.into_u32(), ticks);
         self.alarm.set_alarm(now, A::Ticks::from(ticks));
     }
@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Stress test for `gpio::Output::toggle` on two pins that share a port
+//! output register.
+//!
+//! The test toggles `pin_a` in bursts and `pin_b` once from every alarm
+//! callback, and checks after every toggle that both pins still read back
+//! the expected level. A `toggle` implemented as an unprotected
+//! read-modify-write of the port register loses the update of the other pin
+//! whenever an interrupt that writes the same register arrives in between,
+//! which shows up here as a mismatch. Pick two pins of the same port, ideally
+//! one that is also driven from interrupt context on the board (e.g. a debug
+//! GPIO), and leave the alarm running for a while. Depends on a working UART
+//! and debug! macro.
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::gpio;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+
+/// Number of toggles of `pin_a` per alarm callback.
+const BURST: usize = 1000;
+
+pub struct TestGpioToggle<'a, A: Alarm<'a>, P: gpio::Pin> {
+    alarm: &'a A,
+    pin_a: &'a P,
+    pin_b: &'a P,
+    rounds: Cell<usize>,
+    failures: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>, P: gpio::Pin> TestGpioToggle<'a, A, P> {
+    pub fn new(alarm: &'a A, pin_a: &'a P, pin_b: &'a P) -> TestGpioToggle<'a, A, P> {
+        TestGpioToggle {
+            alarm,
+            pin_a,
+            pin_b,
+            rounds: Cell::new(0),
+            failures: Cell::new(0),
+        }
+    }
+
+    /// Run `rounds` alarm callbacks worth of toggles.
+    pub fn run(&self, rounds: usize) {
+        debug!("Starting GPIO toggle stress test.");
+        self.pin_a.make_output();
+        self.pin_b.make_output();
+        self.pin_a.clear();
+        self.pin_b.clear();
+        self.rounds.set(rounds);
+        self.failures.set(0);
+        self.alarm.set_alarm_from_now(self.alarm.ticks_from_ms(1));
+    }
+
+    fn check(&self, pin: &P, expected: bool, toggled: bool) {
+        if toggled != expected || pin.read() != expected {
+            self.failures.set(self.failures.get() + 1);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, P: gpio::Pin> AlarmClient for TestGpioToggle<'a, A, P> {
+    fn alarm(&self) {
+        let b = !self.pin_b.read();
+        let toggled = self.pin_b.toggle();
+        self.check(self.pin_b, b, toggled);
+
+        let mut a = self.pin_a.read();
+        for _ in 0..BURST {
+            a = !a;
+            let toggled = self.pin_a.toggle();
+            self.check(self.pin_a, a, toggled);
+            self.check(self.pin_b, b, self.pin_b.read());
+        }
+
+        let rounds = self.rounds.get().saturating_sub(1);
+        self.rounds.set(rounds);
+        if rounds > 0 {
+            self.alarm.set_alarm_from_now(self.alarm.ticks_from_ms(1));
+        } else if self.failures.get() == 0 {
+            debug!("GPIO toggle stress test passed.");
+        } else {
+            debug!(
+                "GPIO toggle stress test FAILED: {} mismatches.",
+                self.failures.get()
+            );
+        }
+    }
+}
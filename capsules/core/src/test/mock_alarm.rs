@@ -0,0 +1,200 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Deterministic `Alarm` for unit tests of the capsules built on alarms.
+//!
+//! Unlike a hardware alarm, `MockAlarm` only moves when the test tells it
+//! to: `now()` returns the time set with `set_now` or `advance`, and the
+//! client is only called from `fire`. It records how often it was armed and
+//! disarmed, so tests can check that a capsule programs the hardware as
+//! expected. `FireLog` and `LoggingClient` record which clients fired, in
+//! order.
+//!
+//! ```rust,ignore
+//! let alarm = MockAlarm::<Ticks32, Freq1KHz>::new();
+//! let mux = MuxAlarm::new(&alarm);
+//! alarm.set_alarm_client(&mux);
+//! // ... arm virtual alarms ...
+//! alarm.advance(100.into());
+//! assert!(alarm.fire());
+//! log.assert_order(&[1]);
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct MockAlarm<'a, T: Ticks, F: Frequency> {
+    now: Cell<T>,
+    reference: Cell<T>,
+    dt: Cell<T>,
+    armed: Cell<bool>,
+    /// Number of calls to `set_alarm`.
+    set_alarm_calls: Cell<usize>,
+    /// Number of calls to `disarm`, whether or not the alarm was armed.
+    disarm_calls: Cell<usize>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+    _frequency: PhantomData<F>,
+}
+
+impl<'a, T: Ticks, F: Frequency> MockAlarm<'a, T, F> {
+    pub fn new() -> Self {
+        Self {
+            now: Cell::new(T::from(0)),
+            reference: Cell::new(T::from(0)),
+            dt: Cell::new(T::from(0)),
+            armed: Cell::new(false),
+            set_alarm_calls: Cell::new(0),
+            disarm_calls: Cell::new(0),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+
+    pub fn set_now(&self, now: T) {
+        self.now.set(now);
+    }
+
+    /// Move the clock forward by `ticks`, wrapping around. This doesn't
+    /// fire the alarm, see `fire`.
+    pub fn advance(&self, ticks: T) {
+        self.now.set(self.now.get().wrapping_add(ticks));
+    }
+
+    /// Time at which the alarm is set to fire, if it is armed.
+    pub fn expiration(&self) -> Option<T> {
+        if self.armed.get() {
+            Some(self.reference.get().wrapping_add(self.dt.get()))
+        } else {
+            None
+        }
+    }
+
+    /// If the alarm is armed and has expired, disarm it and call the client
+    /// like a hardware alarm would. Returns whether the client was called.
+    pub fn fire(&self) -> bool {
+        let expired = self.armed.get()
+            && !self.now.get().within_range(
+                self.reference.get(),
+                self.reference.get().wrapping_add(self.dt.get()),
+            );
+        if expired {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+        expired
+    }
+
+    pub fn set_alarm_calls(&self) -> usize {
+        self.set_alarm_calls.get()
+    }
+
+    pub fn disarm_calls(&self) -> usize {
+        self.disarm_calls.get()
+    }
+}
+
+impl<'a, T: Ticks, F: Frequency> Time for MockAlarm<'a, T, F> {
+    type Frequency = F;
+    type Ticks = T;
+
+    fn now(&self) -> Self::Ticks {
+        self.now.get()
+    }
+}
+
+impl<'a, T: Ticks, F: Frequency> Alarm<'a> for MockAlarm<'a, T, F> {
+    fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+        self.set_alarm_calls.set(self.set_alarm_calls.get() + 1);
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn get_alarm_reference(&self) -> Self::Ticks {
+        self.reference.get()
+    }
+
+    fn get_alarm_dt(&self) -> Self::Ticks {
+        self.dt.get()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        self.disarm_calls.set(self.disarm_calls.get() + 1);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        T::from(1)
+    }
+}
+
+/// Maximum number of calls a `FireLog` records.
+pub const FIRE_LOG_SIZE: usize = 16;
+
+/// Records the order in which `LoggingClient`s fired.
+pub struct FireLog {
+    ids: Cell<[usize; FIRE_LOG_SIZE]>,
+    len: Cell<usize>,
+}
+
+impl FireLog {
+    pub fn new() -> Self {
+        Self {
+            ids: Cell::new([0; FIRE_LOG_SIZE]),
+            len: Cell::new(0),
+        }
+    }
+
+    fn push(&self, id: usize) {
+        let len = self.len.get();
+        assert!(len < FIRE_LOG_SIZE, "FireLog is full");
+        let mut ids = self.ids.get();
+        ids[len] = id;
+        self.ids.set(ids);
+        self.len.set(len + 1);
+    }
+
+    /// Assert that exactly the clients in `expected` fired since the last
+    /// call, in that order, and clear the log.
+    pub fn assert_order(&self, expected: &[usize]) {
+        let ids = self.ids.get();
+        assert_eq!(&ids[..self.len.get()], expected);
+        self.len.set(0);
+    }
+}
+
+/// Alarm client that adds its id to a `FireLog` when it fires.
+pub struct LoggingClient<'a> {
+    id: usize,
+    log: &'a FireLog,
+}
+
+impl<'a> LoggingClient<'a> {
+    pub fn new(id: usize, log: &'a FireLog) -> Self {
+        Self { id, log }
+    }
+}
+
+impl AlarmClient for LoggingClient<'_> {
+    fn alarm(&self) {
+        self.log.push(self.id);
+    }
+}
@@ -6,6 +6,9 @@ pub mod alarm;
 pub mod alarm_edge_cases;
 pub mod capsule_test;
 pub mod double_grant_entry;
+pub mod gpio_toggle;
+#[cfg(test)]
+pub mod mock_alarm;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
@@ -0,0 +1,71 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Rate limiting for debug traces.
+//!
+//! Printing a trace for every occurrence of a frequent event, such as an alarm
+//! firing, can make the console the bottleneck and change the very timing
+//! that is being debugged. A `TraceThrottle` lets only one out of every
+//! `every` events through, so traces can stay enabled during long runs.
+//!
+//! ```rust,ignore
+//! if self.trace.should_trace() {
+//!     debug!("alarm fired");
+//! }
+//! ```
+
+use core::cell::Cell;
+
+pub struct TraceThrottle {
+    every: u32,
+    skipped: Cell<u32>,
+}
+
+impl TraceThrottle {
+    /// Create a throttle that lets through one out of every `every` events.
+    /// Both 0 and 1 let through every event.
+    pub const fn new(every: u32) -> TraceThrottle {
+        TraceThrottle {
+            every,
+            skipped: Cell::new(0),
+        }
+    }
+
+    /// Record an event, and return whether it should be traced. The first
+    /// event is always traced.
+    pub fn should_trace(&self) -> bool {
+        let skipped = self.skipped.get();
+        if skipped == 0 {
+            self.skipped.set(1 % self.every.max(1));
+            true
+        } else {
+            self.skipped.set((skipped + 1) % self.every.max(1));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceThrottle;
+
+    #[test]
+    fn test_every_event() {
+        for every in [0, 1] {
+            let throttle = TraceThrottle::new(every);
+            assert!((0..5).all(|_| throttle.should_trace()));
+        }
+    }
+
+    #[test]
+    fn test_one_in_three() {
+        let throttle = TraceThrottle::new(3);
+        let traced: [bool; 7] = core::array::from_fn(|_| throttle.should_trace());
+        assert_eq!(traced, [true, false, false, true, false, false, true]);
+
+        let throttle = TraceThrottle::new(2);
+        let traced: [bool; 4] = core::array::from_fn(|_| throttle.should_trace());
+        assert_eq!(traced, [true, false, true, false]);
+    }
+}
@@ -6,6 +6,7 @@ pub mod virtual_adc;
 pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
 pub mod virtual_flash;
+pub mod virtual_gpio;
 pub mod virtual_i2c;
 pub mod virtual_pwm;
 pub mod virtual_rng;
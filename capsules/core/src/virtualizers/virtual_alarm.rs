@@ -12,6 +12,9 @@ use kernel::hil::time::{self, Alarm, Ticks, Time};
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
+#[cfg(feature = "trace_alarm")]
+use crate::trace_throttle::TraceThrottle;
+
 #[derive(Copy, Clone)]
 struct TickDtReference<T: Ticks> {
     /// Reference time point when this alarm was setup.
@@ -46,6 +49,11 @@ pub struct VirtualMuxAlarm<'a, A: Alarm<'a>> {
     next: ListLink<'a, VirtualMuxAlarm<'a, A>>,
     /// Alarm client for this node in the list.
     client: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Identifier of this alarm in the traces of the `trace_alarm` feature.
+    id: Cell<u32>,
+    /// Whether the alarm fired so late that the counter may have wrapped
+    /// around since it was set, see `may_have_wrapped`.
+    may_have_wrapped: Cell<bool>,
 }
 
 impl<'a, A: Alarm<'a>> ListNode<'a, VirtualMuxAlarm<'a, A>> for VirtualMuxAlarm<'a, A> {
@@ -68,6 +76,8 @@ impl<'a, A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
             armed: Cell::new(false),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
+            id: Cell::new(0),
+            may_have_wrapped: Cell::new(false),
         }
     }
 
@@ -76,6 +86,60 @@ impl<'a, A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
     pub fn setup(&'a self) {
         self.mux.virtual_alarms.push_head(self);
     }
+
+    /// Set an identifier for this alarm, which is included in the traces of
+    /// the `trace_alarm` feature. This allows telling apart the users of a
+    /// mux when debugging. Alarms without an identifier use 0.
+    pub fn set_id(&self, id: u32) {
+        self.id.set(id);
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id.get()
+    }
+
+    /// Returns whether more than a full period of the counter may have
+    /// elapsed between the last `set_alarm` and the alarm firing, e.g.
+    /// because the system was blocked or asleep for a very long time.
+    ///
+    /// Ticks only measure time modulo the counter period, so a full wrap
+    /// can't be observed directly. Instead, this is set when the alarm (or
+    /// the intermediate wakeup of an alarm longer than half the period)
+    /// fires more than half a period after its expiration: the mux assumes
+    /// a lower latency than that, so the elapsed time is then unreliable.
+    /// A safety-critical client can check this from its `alarm` callback
+    /// rather than treating the expiration as on time. It is cleared by
+    /// `set_alarm`.
+    pub fn may_have_wrapped(&self) -> bool {
+        self.may_have_wrapped.get()
+    }
+
+    /// Ticks left until this alarm (or its intermediate wakeup) expires as
+    /// seen at `now`. An alarm that has already expired is due right away,
+    /// so this is 0 for it.
+    fn remaining(&self, now: A::Ticks) -> A::Ticks {
+        let when = self.dt_reference.get();
+        if !now.within_range(when.reference, when.reference_plus_dt()) {
+            A::Ticks::from(0u32)
+        } else {
+            when.reference_plus_dt().wrapping_sub(now)
+        }
+    }
+
+    /// While the mux is firing, record that this alarm was set or disarmed
+    /// from a callback. Only the alarm whose callback is running is tracked
+    /// by the firing pass itself; any other change means that the pass may
+    /// have seen a stale expiration.
+    fn note_changed_while_firing(&self) {
+        if self.mux.firing.get()
+            && !self
+                .mux
+                .firing_alarm
+                .map_or(false, |cur| core::ptr::eq(cur, self))
+        {
+            self.mux.rescan.set(true);
+        }
+    }
 }
 
 impl<'a, A: Alarm<'a>> Time for VirtualMuxAlarm<'a, A> {
@@ -93,21 +157,27 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
     }
 
     fn disarm(&self) -> Result<(), ErrorCode> {
+        self.disarm_if_armed().map(|_| ())
+    }
+
+    fn disarm_if_armed(&self) -> Result<bool, ErrorCode> {
         if !self.armed.get() {
-            return Ok(());
+            return Ok(false);
         }
 
         self.armed.set(false);
+        self.note_changed_while_firing();
 
         let enabled = self.mux.enabled.get() - 1;
         self.mux.enabled.set(enabled);
 
         // If there are not more enabled alarms, disable the underlying alarm
-        // completely.
-        if enabled == 0 {
+        // completely. While firing, the mux reprograms the underlying alarm
+        // once all expired alarms have been handled.
+        if enabled == 0 && !self.mux.firing.get() {
             let _ = self.mux.alarm.disarm();
         }
-        Ok(())
+        Ok(true)
     }
 
     fn is_armed(&self) -> bool {
@@ -135,6 +205,7 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
             }
         };
         self.dt_reference.set(dt_reference);
+        self.may_have_wrapped.set(false);
         // Ensure local variable has correct value when used below
         let dt = dt_reference.dt;
 
@@ -142,17 +213,29 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
             self.mux.enabled.set(enabled + 1);
             self.armed.set(true);
         }
-
-        // First alarm, so set it
-        if enabled == 0 {
-            //debug!("virtual_alarm: first alarm: set it.");
+        self.note_changed_while_firing();
+
+        if self.mux.firing.get() {
+            // The underlying alarm must not be programmed while the mux is
+            // firing: it has not yet settled which alarms expired, and
+            // `enabled` does not count the alarms that are still to be
+            // fired. The new expiration is kept in `dt_reference`, from
+            // which the mux picks the soonest alarm after firing.
+        } else if enabled == 0 {
+            // First alarm, so set it
+            #[cfg(feature = "trace_alarm")]
+            if self.mux.trace.should_trace() {
+                #[cfg(not(test))]
+                kernel::debug!(
+                    "virtual_alarm {}: first alarm, fires in {}",
+                    self.id(),
+                    time::TicksDuration::<A::Ticks, A::Frequency>::new(dt)
+                );
+            }
             self.mux.set_alarm(reference, dt);
-        } else if !self.mux.firing.get() {
-            // If firing is true, the mux will scan all the alarms after
-            // firing and pick the soonest one so do not need to modify the
-            // mux. Otherwise, this is an alarm
-            // started in a separate code path (e.g., another event).
-            // This new alarm fires sooner if two things are both true:
+        } else {
+            // This is an alarm started in a separate code path (e.g.,
+            // another event). This new alarm fires sooner if two things are both true:
             //    1. The current earliest alarm expiration doesn't fall
             //    in the range of [reference, reference+dt): this means
             //    it is either in the past (before reference) or the future
@@ -187,6 +270,19 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
         dt_reference.reference_plus_dt().wrapping_add(extension)
     }
 
+    fn get_alarm_reference(&self) -> Self::Ticks {
+        self.dt_reference.get().reference
+    }
+
+    fn get_alarm_dt(&self) -> Self::Ticks {
+        let dt_reference = self.dt_reference.get();
+        if dt_reference.extended {
+            dt_reference.dt.wrapping_add(Self::Ticks::half_max_value())
+        } else {
+            dt_reference.dt
+        }
+    }
+
     fn minimum_dt(&self) -> Self::Ticks {
         self.mux.alarm.minimum_dt()
     }
@@ -206,10 +302,25 @@ pub struct MuxAlarm<'a, A: Alarm<'a>> {
     enabled: Cell<usize>,
     /// Underlying alarm, over which the virtual alarms are multiplexed.
     alarm: &'a A,
-    /// Whether we are firing; used to delay restarted alarms
+    /// Whether we are firing. Virtual alarms set or disarmed in the meantime
+    /// do not touch the underlying alarm, which is reprogrammed from all
+    /// armed virtual alarms at the end of `alarm()`.
     firing: Cell<bool>,
+    /// Virtual alarm whose callback is running while firing.
+    firing_alarm: OptionalCell<&'a VirtualMuxAlarm<'a, A>>,
+    /// Whether a callback set or disarmed another virtual alarm than its
+    /// own while firing, so that the next alarm must be found by scanning
+    /// the whole list again.
+    rescan: Cell<bool>,
     /// Reference to next alarm
     next_tick_vals: Cell<Option<(A::Ticks, A::Ticks)>>,
+    /// Alarms expiring at most this many ticks after the underlying alarm
+    /// fired are fired together with the expired ones. `None` until set, as
+    /// `Ticks` can't be created in a `const fn`, which is the same as 0.
+    granularity: Cell<Option<A::Ticks>>,
+    /// Rate limit for the traces of the `trace_alarm` feature.
+    #[cfg(feature = "trace_alarm")]
+    trace: TraceThrottle,
 }
 
 impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
@@ -219,7 +330,123 @@ impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
             enabled: Cell::new(0),
             alarm,
             firing: Cell::new(false),
+            firing_alarm: OptionalCell::empty(),
+            rescan: Cell::new(false),
             next_tick_vals: Cell::new(None),
+            granularity: Cell::new(None),
+            #[cfg(feature = "trace_alarm")]
+            trace: TraceThrottle::new(1),
+        }
+    }
+
+    /// Like `new`, but only trace one out of every `trace_every` alarm
+    /// events with the `trace_alarm` feature.
+    #[cfg(feature = "trace_alarm")]
+    pub const fn new_with_trace_every(alarm: &'a A, trace_every: u32) -> MuxAlarm<'a, A> {
+        MuxAlarm {
+            trace: TraceThrottle::new(trace_every),
+            ..Self::new(alarm)
+        }
+    }
+
+    /// Coalesce alarms that expire close to each other into one wakeup.
+    ///
+    /// When the underlying alarm fires, virtual alarms expiring within the
+    /// next `granularity` ticks are fired right away together with the
+    /// expired ones, instead of programming another wakeup for each of
+    /// them. This saves wakeups and thus energy when alarms are spread over
+    /// short intervals, at the cost of precision: an alarm may fire up to
+    /// `granularity` ticks early. The default of 0 never fires an alarm
+    /// early, and should be kept if any client needs precise timing.
+    pub fn set_granularity(&self, granularity: A::Ticks) {
+        self.granularity.set(Some(granularity));
+    }
+
+    /// Returns when the underlying alarm next needs to fire, as an absolute
+    /// time, or `None` if no virtual alarm is armed. This doesn't arm
+    /// anything, so a power management loop can use it to decide how deep
+    /// to sleep and whether the alarm's clock must stay powered.
+    ///
+    /// An alarm that has already expired but not fired yet is due now, and
+    /// so returns the current time. For alarms longer than half the range of
+    /// `Ticks`, this is the intermediate wakeup at which the remaining time
+    /// is programmed, not the final expiration.
+    pub fn next_deadline(&self) -> Option<A::Ticks> {
+        let now = self.alarm.now();
+        self.soonest_armed(now).map(|valrm| {
+            let when = valrm.dt_reference.get();
+            if now.within_range(when.reference, when.reference_plus_dt()) {
+                when.reference_plus_dt()
+            } else {
+                now
+            }
+        })
+    }
+
+    /// Recover from a lost interrupt of the underlying alarm. If the
+    /// underlying alarm expired more than `margin` ticks ago without having
+    /// fired, this handles it as if it had just fired, and returns `true`.
+    ///
+    /// Without this, a missed interrupt, e.g. because interrupts were
+    /// masked for too long, leaves all virtual alarms waiting forever. The
+    /// scheduler can call this regularly to turn such a hang into a delay
+    /// of at most `margin` ticks plus the time between two calls. `margin`
+    /// avoids racing with an interrupt that is merely about to be handled;
+    /// handling an alarm twice is harmless though, as only expired virtual
+    /// alarms fire.
+    ///
+    /// Ticks wrap around, so an expiration can only be recognized as past
+    /// until the counter comes back to the reference it was set from. As
+    /// the mux never sets the underlying alarm more than half the range of
+    /// `Ticks` ahead, this must be called at least once every half period
+    /// of the counter, which is short for narrow or fast counters.
+    pub fn check_overdue(&self, margin: A::Ticks) -> bool {
+        if self.firing.get() {
+            return false;
+        }
+        match self.next_tick_vals.get() {
+            Some((reference, dt)) => {
+                let elapsed = self.alarm.now().wrapping_sub(reference);
+                if elapsed >= dt && elapsed.wrapping_sub(dt) >= margin {
+                    time::AlarmClient::alarm(self);
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the armed virtual alarm that expires first, as seen at `now`.
+    fn soonest_armed(&self, now: A::Ticks) -> Option<&'a VirtualMuxAlarm<'a, A>> {
+        self.scan_armed(now).1
+    }
+
+    /// Returns the number of armed virtual alarms, and the one among them
+    /// that expires first as seen at `now`, in a single traversal of the
+    /// list.
+    fn scan_armed(&self, now: A::Ticks) -> (usize, Option<&'a VirtualMuxAlarm<'a, A>>) {
+        let mut count = 0;
+        let mut soonest = None;
+        for cur in self.virtual_alarms.iter().filter(|cur| cur.armed.get()) {
+            count += 1;
+            soonest = Self::sooner(soonest, cur, now);
+        }
+        (count, soonest)
+    }
+
+    /// Returns the one of `soonest` and `candidate` that expires first as
+    /// seen at `now`. On a tie, `soonest` is kept, so the alarm found first
+    /// wins.
+    fn sooner(
+        soonest: Option<&'a VirtualMuxAlarm<'a, A>>,
+        candidate: &'a VirtualMuxAlarm<'a, A>,
+        now: A::Ticks,
+    ) -> Option<&'a VirtualMuxAlarm<'a, A>> {
+        match soonest {
+            Some(valrm) if valrm.remaining(now) <= candidate.remaining(now) => soonest,
+            _ => Some(candidate),
         }
     }
 
@@ -240,19 +467,43 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
     fn alarm(&self) {
         // Check whether to fire each alarm. At this level, alarms are one-shot,
         // so a repeating client will set it again in the alarm() callback.
+        //
+        // The same pass counts the alarms that are still armed afterwards,
+        // and finds the soonest of them (if any), from which the "next"
+        // underlying alarm is set. This covers alarms that stay armed, and
+        // alarms re-armed from their own callback, which is the common case
+        // of a periodic client. Only if a callback changed another alarm,
+        // which the pass may already have looked at, is the list scanned
+        // again.
         self.firing.set(true);
-        self.virtual_alarms
-            .iter()
-            .filter(|cur| {
-                let dt_ref = cur.dt_reference.get();
-                // It is very important to get the current now time as the reference could have been
-                // set from now in the previous for_each iteration. We rely on the reference always
-                // being in the past when compared to now.
-                let now = self.alarm.now();
-                cur.armed.get() && !now.within_range(dt_ref.reference, dt_ref.reference_plus_dt())
-            })
-            .for_each(|cur| {
-                let dt_ref = cur.dt_reference.get();
+        self.rescan.set(false);
+        let mut enabled = 0;
+        let mut soonest = None;
+        for cur in self.virtual_alarms.iter() {
+            if !cur.armed.get() {
+                continue;
+            }
+            let dt_ref = cur.dt_reference.get();
+            // It is very important to get the current now time as the reference could have been
+            // set from now in the previous iteration. We rely on the reference always being in
+            // the past when compared to now.
+            let now = self.alarm.now();
+            let expiration = dt_ref.reference_plus_dt();
+            let expired = !now.within_range(dt_ref.reference, expiration);
+            // Only coalesce alarms that fire their client. The first part of
+            // an extended alarm must really expire: the second part starts
+            // from its expiration, which must not be in the future.
+            if expired
+                || (!dt_ref.extended
+                    && self.granularity.get().map_or(false, |granularity| {
+                        expiration.wrapping_sub(now) <= granularity
+                    }))
+            {
+                // Alarms coalesced by the granularity fire before their
+                // expiration, so only check the lateness of expired ones.
+                if expired && now.wrapping_sub(expiration) > A::Ticks::half_max_value() {
+                    cur.may_have_wrapped.set(true);
+                }
                 if dt_ref.extended {
                     // The first part of the extended alarm just fired, leave alarm armed with
                     // remaining time.
@@ -265,32 +516,45 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
                     // Alarm fully expired, disarm and fire callback
                     cur.armed.set(false);
                     self.enabled.set(self.enabled.get() - 1);
-                    //debug!("  Virtualizer: {:?} outside {:?}-{:?}, fire!", now, cur.reference.get(), cur.reference.get().wrapping_add(cur.dt.get()));
+                    #[cfg(feature = "trace_alarm")]
+                    if self.trace.should_trace() {
+                        #[cfg(not(test))]
+                        kernel::debug!(
+                            "virtual_alarm {}: fire, {} late",
+                            cur.id(),
+                            time::TicksDuration::<A::Ticks, A::Frequency>::new(
+                                self.alarm.now().wrapping_sub(dt_ref.reference_plus_dt())
+                            )
+                        );
+                    }
+                    self.firing_alarm.set(cur);
                     cur.alarm();
+                    self.firing_alarm.clear();
                 }
-            });
+            }
+            if cur.armed.get() {
+                enabled += 1;
+                soonest = Self::sooner(soonest, cur, self.alarm.now());
+            }
+        }
         self.firing.set(false);
-        // Find the soonest alarm client (if any) and set the "next" underlying
-        // alarm based on it.  This needs to happen after firing all expired
-        // alarms since those may have reset new alarms.
-        let now = self.alarm.now();
-        let next = self
-            .virtual_alarms
-            .iter()
-            .filter(|cur| cur.armed.get())
-            .min_by_key(|cur| {
-                let when = cur.dt_reference.get();
-                // If the alarm has already expired, then it should be
-                // considered as the earliest possible (0 ticks), so it
-                // will trigger as soon as possible. This can happen
-                // if the alarm expired *after* it was examined in the
-                // above loop.
-                if !now.within_range(when.reference, when.reference_plus_dt()) {
-                    A::Ticks::from(0u32)
-                } else {
-                    when.reference_plus_dt().wrapping_sub(now)
-                }
-            });
+
+        // This needs to happen after firing all expired alarms since those
+        // may have reset new alarms. An alarm may also have expired *after*
+        // it was examined in the above loop, in which case it is picked
+        // first.
+        let next = if !self.rescan.get() {
+            soonest
+        } else if self.enabled.get() == 0 {
+            // Callbacks disarmed everything, nothing left to look for.
+            enabled = 0;
+            None
+        } else {
+            let (rescanned, next) = self.scan_armed(self.alarm.now());
+            enabled = rescanned;
+            next
+        };
+        self.enabled.set(enabled);
 
         // Set the alarm.
         if let Some(valrm) = next {
@@ -305,6 +569,7 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::mock_alarm::{FireLog, LoggingClient, MockAlarm};
     use time::*;
 
     struct FakeAlarm<'a> {
@@ -312,6 +577,8 @@ mod tests {
         reference: Cell<Ticks32>,
         dt: Cell<Ticks32>,
         armed: Cell<bool>,
+        /// Number of times the alarm was set or disarmed.
+        programmed: Cell<usize>,
         client: OptionalCell<&'a dyn AlarmClient>,
     }
 
@@ -322,6 +589,7 @@ mod tests {
                 reference: Cell::new(0u32.into()),
                 dt: Cell::new(0u32.into()),
                 armed: Cell::new(false),
+                programmed: Cell::new(0),
                 client: OptionalCell::empty(),
             }
         }
@@ -396,14 +664,24 @@ mod tests {
             self.reference.set(reference);
             self.dt.set(dt);
             self.armed.set(true);
+            self.programmed.set(self.programmed.get() + 1);
         }
 
         fn get_alarm(&self) -> Self::Ticks {
             self.reference.get().wrapping_add(self.dt.get())
         }
 
+        fn get_alarm_reference(&self) -> Self::Ticks {
+            self.reference.get()
+        }
+
+        fn get_alarm_dt(&self) -> Self::Ticks {
+            self.dt.get()
+        }
+
         fn disarm(&self) -> Result<(), ErrorCode> {
             self.armed.set(false);
+            self.programmed.set(self.programmed.get() + 1);
             Ok(())
         }
 
@@ -485,6 +763,44 @@ mod tests {
         assert_eq!(client.count(), 3);
     }
 
+    #[test]
+    fn test_get_alarm_reference_dt() {
+        let alarm = FakeAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        let valarm = VirtualMuxAlarm::new(&mux);
+        valarm.setup();
+
+        valarm.set_alarm(10u32.into(), 100u32.into());
+        assert_eq!(valarm.get_alarm_reference().into_u32(), 10);
+        assert_eq!(valarm.get_alarm_dt().into_u32(), 100);
+        assert_eq!(valarm.get_alarm().into_u32(), 110);
+
+        // A dt that is extended internally is still reported as requested
+        valarm.set_alarm(10u32.into(), u32::MAX.into());
+        assert_eq!(valarm.get_alarm_reference().into_u32(), 10);
+        assert_eq!(valarm.get_alarm_dt().into_u32(), u32::MAX);
+        assert_eq!(valarm.get_alarm().into_u32(), 9);
+    }
+
+    #[test]
+    fn test_disarm_if_armed() {
+        let alarm = FakeAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        let valarm = VirtualMuxAlarm::new(&mux);
+        valarm.setup();
+
+        assert_eq!(valarm.disarm_if_armed(), Ok(false));
+
+        valarm.set_alarm(valarm.now(), 100u32.into());
+        assert_eq!(valarm.disarm_if_armed(), Ok(true));
+        assert!(!valarm.is_armed());
+        assert_eq!(valarm.disarm_if_armed(), Ok(false));
+    }
+
     struct SetAlarmClient<'a> {
         alarm: &'a VirtualMuxAlarm<'a, FakeAlarm<'a>>,
         dt: u32,
@@ -536,6 +852,326 @@ mod tests {
         assert!(!still_armed);
     }
 
+    #[test]
+    fn test_other_alarm_moved_earlier_during_firing() {
+        let alarm = FakeAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        // The list is in reverse setup order: 0, 1, 2, 3.
+        let v_alarms = &[
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+        ];
+        for valarm in v_alarms.iter().rev() {
+            valarm.setup();
+        }
+
+        // Alarm 2 fires first and moves alarm 1, which the firing pass has
+        // already looked at, ahead of all others.
+        let move_v1_alarm = SetAlarmClient::new(&v_alarms[1], 5);
+        v_alarms[2].set_alarm_client(&move_v1_alarm);
+        let now = alarm.now();
+        v_alarms[0].set_alarm(now, 100.into());
+        v_alarms[1].set_alarm(now, 1_000.into());
+        v_alarms[2].set_alarm(now, 10.into());
+        v_alarms[3].set_alarm(now, 500.into());
+
+        assert!(alarm.trigger_next_alarm());
+        assert!(!v_alarms[2].is_armed());
+        assert_eq!(alarm.get_alarm(), v_alarms[1].get_alarm());
+    }
+
+    struct DisarmAlarmClient<'a> {
+        alarm: &'a VirtualMuxAlarm<'a, FakeAlarm<'a>>,
+    }
+
+    impl AlarmClient for DisarmAlarmClient<'_> {
+        fn alarm(&self) {
+            let _ = self.alarm.disarm();
+        }
+    }
+
+    #[test]
+    fn test_rearm_during_firing_deferred() {
+        let alarm = FakeAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+
+        // A single alarm re-arming itself from its callback sees no enabled
+        // alarms, which used to program the hardware while still firing.
+        let valarm = VirtualMuxAlarm::new(&mux);
+        valarm.setup();
+        let rearm = SetAlarmClient::new(&valarm, 100);
+        valarm.set_alarm_client(&rearm);
+        valarm.set_alarm(0.into(), 10.into());
+
+        alarm.programmed.set(0);
+        assert!(alarm.trigger_next_alarm());
+        assert_eq!(alarm.programmed.get(), 1);
+        assert!(valarm.is_armed());
+        assert_eq!(alarm.get_alarm(), valarm.get_alarm());
+
+        // Disarming the last other armed alarm while firing must not disarm
+        // the hardware either.
+        let other = VirtualMuxAlarm::new(&mux);
+        other.setup();
+        let disarm = DisarmAlarmClient { alarm: &valarm };
+        other.set_alarm_client(&disarm);
+        other.set_alarm(alarm.now(), 0.into());
+        valarm.set_alarm(alarm.now(), 1_000.into());
+
+        alarm.programmed.set(0);
+        assert!(!alarm.trigger_next_alarm());
+        assert_eq!(alarm.programmed.get(), 1);
+        assert!(!valarm.is_armed());
+        assert!(!other.is_armed());
+    }
+
+    fn wakeups_for_alarms_at(granularity: u32, expirations: &[u32]) -> usize {
+        let alarm = FakeAlarm::new();
+        let client = ClientCounter::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        mux.set_granularity(granularity.into());
+
+        let v_alarms = &[
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+        ];
+        let now = alarm.now();
+        for (v, &dt) in v_alarms.iter().zip(expirations) {
+            v.setup();
+            v.set_alarm_client(&client);
+            v.set_alarm(now, dt.into());
+        }
+
+        let mut wakeups = 0;
+        while alarm.is_armed() && wakeups < 10 {
+            alarm.trigger_next_alarm();
+            wakeups += 1;
+        }
+        assert_eq!(client.count(), expirations.len());
+        wakeups
+    }
+
+    #[test]
+    fn test_granularity_coalesces_wakeups() {
+        assert_eq!(wakeups_for_alarms_at(0, &[100, 150, 500]), 3);
+        // The alarm at 150 fires early with the one at 100
+        assert_eq!(wakeups_for_alarms_at(60, &[100, 150, 500]), 2);
+        assert_eq!(wakeups_for_alarms_at(1_000, &[100, 150, 500]), 1);
+    }
+
+    #[test]
+    fn test_granularity_64bit_far_alarm() {
+        let alarm = MockAlarm::<Ticks64, Freq1KHz>::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        let log = FireLog::new();
+        let clients = [LoggingClient::new(0, &log), LoggingClient::new(1, &log)];
+        let v_alarms = &[VirtualMuxAlarm::new(&mux), VirtualMuxAlarm::new(&mux)];
+        for (v, client) in v_alarms.iter().zip(clients.iter()) {
+            v.setup();
+            v.set_alarm_client(client);
+        }
+
+        // The far alarm is a multiple of 2^32 ticks past the near one, so
+        // its distance truncated to 32 bits is 0 when the near one fires.
+        v_alarms[0].set_alarm(0u64.into(), 100u64.into());
+        v_alarms[1].set_alarm(0u64.into(), (100u64 + (1 << 32)).into());
+        for granularity in [None, Some(10u64)] {
+            if let Some(granularity) = granularity {
+                mux.set_granularity(granularity.into());
+                v_alarms[0].set_alarm(alarm.now(), 100u64.into());
+                v_alarms[1].set_alarm(alarm.now(), (100u64 + (1 << 32)).into());
+            }
+            alarm.advance(100u64.into());
+            assert!(alarm.fire());
+            log.assert_order(&[0]);
+            assert!(v_alarms[1].is_armed());
+        }
+    }
+
+    #[test]
+    fn test_granularity_extended_alarm() {
+        let alarm = MockAlarm::<Ticks32, Freq1KHz>::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        mux.set_granularity(10u32.into());
+        let log = FireLog::new();
+        let clients = [LoggingClient::new(0, &log), LoggingClient::new(1, &log)];
+        let v_alarms = &[VirtualMuxAlarm::new(&mux), VirtualMuxAlarm::new(&mux)];
+        for (v, client) in v_alarms.iter().zip(clients.iter()) {
+            v.setup();
+            v.set_alarm_client(client);
+        }
+
+        // The first part of the extended alarm expires at 105, within the
+        // granularity of the near alarm firing at 100.
+        let half_max = Ticks32::half_max_value().into_u32();
+        let deadline = half_max + 105;
+        v_alarms[0].set_alarm(0u32.into(), 100u32.into());
+        v_alarms[1].set_alarm(0u32.into(), deadline.into());
+        alarm.advance(100u32.into());
+        assert!(alarm.fire());
+        log.assert_order(&[0]);
+        assert!(!alarm.fire());
+        log.assert_order(&[]);
+
+        // The second part starts once the first one really expired.
+        alarm.advance(5u32.into());
+        assert!(alarm.fire());
+        log.assert_order(&[]);
+        alarm.set_now((deadline - 11).into());
+        assert!(!alarm.fire());
+        log.assert_order(&[]);
+
+        alarm.set_now(deadline.into());
+        assert!(alarm.fire());
+        log.assert_order(&[1]);
+        assert!(!v_alarms[1].is_armed());
+    }
+
+    #[test]
+    fn test_fire_order_and_rearm() {
+        let alarm = MockAlarm::<Ticks32, Freq1KHz>::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        let log = FireLog::new();
+        let clients = [
+            LoggingClient::new(0, &log),
+            LoggingClient::new(1, &log),
+            LoggingClient::new(2, &log),
+        ];
+        let v_alarms = &[
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+            VirtualMuxAlarm::new(&mux),
+        ];
+        for (v, client) in v_alarms.iter().zip(clients.iter()) {
+            v.setup();
+            v.set_alarm_client(client);
+        }
+
+        alarm.set_now(1_000.into());
+        v_alarms[0].set_alarm(1_000.into(), 300.into());
+        v_alarms[1].set_alarm(1_000.into(), 100.into());
+        v_alarms[2].set_alarm(1_000.into(), 200.into());
+        assert_eq!(alarm.expiration(), Some(1_100.into()));
+
+        // Nothing has expired yet
+        alarm.advance(99.into());
+        assert!(!alarm.fire());
+        log.assert_order(&[]);
+
+        // Each expiration fires its client and re-arms for the next one
+        alarm.advance(1.into());
+        assert!(alarm.fire());
+        log.assert_order(&[1]);
+        assert_eq!(alarm.expiration(), Some(1_200.into()));
+        assert_eq!(mux.enabled.get(), 2);
+
+        alarm.advance(100.into());
+        assert!(alarm.fire());
+        log.assert_order(&[2]);
+        assert_eq!(alarm.expiration(), Some(1_300.into()));
+
+        alarm.advance(100.into());
+        assert!(alarm.fire());
+        log.assert_order(&[0]);
+        assert_eq!(alarm.expiration(), None);
+        assert_eq!(mux.enabled.get(), 0);
+        assert_eq!(alarm.disarm_calls(), 1);
+    }
+
+    #[test]
+    fn test_check_overdue() {
+        let alarm = MockAlarm::<Ticks32, Freq1KHz>::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        let log = FireLog::new();
+        let client = LoggingClient::new(0, &log);
+        let v_alarm = VirtualMuxAlarm::new(&mux);
+        v_alarm.setup();
+        v_alarm.set_alarm_client(&client);
+        assert!(!mux.check_overdue(10.into()));
+
+        v_alarm.set_alarm(0.into(), 100.into());
+        alarm.advance(105.into());
+        // Expired, but not by more than the margin
+        assert!(!mux.check_overdue(10.into()));
+        log.assert_order(&[]);
+
+        // The interrupt was lost: recover without `alarm.fire()`
+        alarm.advance(10.into());
+        assert!(mux.check_overdue(10.into()));
+        log.assert_order(&[0]);
+        assert!(!mux.check_overdue(10.into()));
+    }
+
+    #[test]
+    fn test_may_have_wrapped() {
+        let alarm = MockAlarm::<Ticks32, Freq1KHz>::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        let log = FireLog::new();
+        let client = LoggingClient::new(0, &log);
+        let v_alarm = VirtualMuxAlarm::new(&mux);
+        v_alarm.setup();
+        v_alarm.set_alarm_client(&client);
+
+        v_alarm.set_alarm(0.into(), 100.into());
+        alarm.advance(150.into());
+        assert!(alarm.fire());
+        log.assert_order(&[0]);
+        assert!(!v_alarm.may_have_wrapped());
+
+        // Fired more than half a period late
+        v_alarm.set_alarm(alarm.now(), 100.into());
+        alarm.advance(Ticks32::half_max_value().wrapping_add(200.into()));
+        assert!(alarm.fire());
+        log.assert_order(&[0]);
+        assert!(v_alarm.may_have_wrapped());
+
+        v_alarm.set_alarm(alarm.now(), 100.into());
+        assert!(!v_alarm.may_have_wrapped());
+    }
+
+    #[test]
+    fn test_next_deadline() {
+        let alarm = FakeAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_alarm_client(&mux);
+        let v_alarms = &[VirtualMuxAlarm::new(&mux), VirtualMuxAlarm::new(&mux)];
+        for v in v_alarms {
+            v.setup();
+        }
+        assert_eq!(mux.next_deadline(), None);
+
+        // The first alarm wraps around, and fires before the second one
+        // even though its expiration is numerically smaller.
+        alarm.now.set((u32::MAX - 50).into());
+        let now = alarm.now();
+        v_alarms[0].set_alarm(now, 100.into());
+        v_alarms[1].set_alarm(now, 200.into());
+        assert_eq!(mux.next_deadline(), Some(now.wrapping_add(100.into())));
+
+        let _ = v_alarms[0].disarm();
+        assert_eq!(mux.next_deadline(), Some(now.wrapping_add(200.into())));
+
+        // An expired alarm that hasn't fired yet is due now.
+        alarm.now.set(now.wrapping_add(300.into()));
+        let deadline = mux.next_deadline().unwrap();
+        assert_eq!(deadline, alarm.now.get());
+
+        let _ = v_alarms[1].disarm();
+        assert_eq!(mux.next_deadline(), None);
+    }
+
     #[test]
     fn test_quick_alarms_not_skipped() {
         let alarm = FakeAlarm::new();
@@ -0,0 +1,130 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Share the interrupt of a single GPIO pin between multiple clients.
+//!
+//! A pin only holds one `gpio::Client`. When several independent users need
+//! to react to the same pin, e.g. a button used both by a capsule and by a
+//! power manager, the `MuxGpioInterrupt` is set as the client of the pin and
+//! forwards every `fired` callback to all of its `VirtualGpioClient`s.
+//!
+//! The mux only distributes the callbacks. The pin itself, including which
+//! edges trigger an interrupt, is still configured through the pin, so the
+//! users of a shared pin have to agree on its configuration.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let mux_button = static_init!(MuxGpioInterrupt<'static>, MuxGpioInterrupt::new());
+//! button_pin.set_client(mux_button);
+//!
+//! let button_client = static_init!(
+//!     VirtualGpioClient<'static>,
+//!     VirtualGpioClient::new(mux_button)
+//! );
+//! button_client.setup();
+//! button_client.set_client(power_manager);
+//! ```
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::gpio;
+use kernel::utilities::cells::OptionalCell;
+
+/// A `gpio::Client` that forwards interrupts to multiple
+/// `VirtualGpioClient`s.
+pub struct MuxGpioInterrupt<'a> {
+    clients: List<'a, VirtualGpioClient<'a>>,
+}
+
+impl<'a> MuxGpioInterrupt<'a> {
+    pub const fn new() -> MuxGpioInterrupt<'a> {
+        MuxGpioInterrupt {
+            clients: List::new(),
+        }
+    }
+}
+
+impl<'a> gpio::Client for MuxGpioInterrupt<'a> {
+    fn fired(&self) {
+        for node in self.clients.iter() {
+            node.client.map(|client| client.fired());
+        }
+    }
+}
+
+/// A node in the list of clients of a `MuxGpioInterrupt`.
+pub struct VirtualGpioClient<'a> {
+    mux: &'a MuxGpioInterrupt<'a>,
+    next: ListLink<'a, VirtualGpioClient<'a>>,
+    client: OptionalCell<&'a dyn gpio::Client>,
+}
+
+impl<'a> ListNode<'a, VirtualGpioClient<'a>> for VirtualGpioClient<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualGpioClient<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> VirtualGpioClient<'a> {
+    /// After calling new, always call setup()
+    pub fn new(mux: &'a MuxGpioInterrupt<'a>) -> VirtualGpioClient<'a> {
+        VirtualGpioClient {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Call this method immediately after new() to link this to the mux,
+    /// otherwise the client won't receive any interrupts. Clients are called
+    /// in the order in which they were set up.
+    pub fn setup(&'a self) {
+        self.mux.clients.push_tail(self);
+    }
+
+    pub fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.client.set(client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use kernel::hil::gpio::Client;
+
+    use super::*;
+
+    struct CountingClient(Cell<usize>);
+
+    impl gpio::Client for CountingClient {
+        fn fired(&self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_fired_reaches_all_clients() {
+        let mux = MuxGpioInterrupt::new();
+        let a = VirtualGpioClient::new(&mux);
+        let b = VirtualGpioClient::new(&mux);
+        let unset = VirtualGpioClient::new(&mux);
+        a.setup();
+        b.setup();
+        unset.setup();
+
+        let count_a = CountingClient(Cell::new(0));
+        let count_b = CountingClient(Cell::new(0));
+        a.set_client(&count_a);
+        b.set_client(&count_b);
+
+        mux.fired();
+        mux.fired();
+        assert_eq!(count_a.0.get(), 2);
+        assert_eq!(count_b.0.get(), 2);
+    }
+}
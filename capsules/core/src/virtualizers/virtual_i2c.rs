@@ -6,6 +6,8 @@
 //!
 //! `MuxI2C` provides shared access to a single I2C Master Bus for multiple
 //! users. `I2CDevice` provides access to a specific I2C address.
+//! `I2CBusScanner` probes a range of addresses on the bus to find out which
+//! devices are present.
 
 use core::cell::Cell;
 
@@ -13,6 +15,7 @@ use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::i2c::{self, Error, I2CClient, I2CHwMasterClient, NoSMBus};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
 // `NoSMBus` provides a placeholder for `SMBusMaster` in case the board doesn't have a SMBus
 pub struct MuxI2C<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a> = NoSMBus> {
     i2c: &'a I,
@@ -82,7 +85,7 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> MuxI2C<'a, I, S> {
             mnode.map(|node| {
                 node.buffer.take().map(|buf| {
                     match node.operation.get() {
-                        Op::Write(len) => match self.i2c.write(node.addr, buf, len) {
+                        Op::Write(len) => match self.i2c.write(node.addr.get(), buf, len) {
                             Ok(()) => {}
                             Err((error, buffer)) => {
                                 node.buffer.replace(buffer);
@@ -90,7 +93,7 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> MuxI2C<'a, I, S> {
                                 node.mux.do_next_op_async();
                             }
                         },
-                        Op::Read(len) => match self.i2c.read(node.addr, buf, len) {
+                        Op::Read(len) => match self.i2c.read(node.addr.get(), buf, len) {
                             Ok(()) => {}
                             Err((error, buffer)) => {
                                 node.buffer.replace(buffer);
@@ -99,7 +102,7 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> MuxI2C<'a, I, S> {
                             }
                         },
                         Op::WriteRead(wlen, rlen) => {
-                            match self.i2c.write_read(node.addr, buf, wlen, rlen) {
+                            match self.i2c.write_read(node.addr.get(), buf, wlen, rlen) {
                                 Ok(()) => {}
                                 Err((error, buffer)) => {
                                     node.buffer.replace(buffer);
@@ -205,7 +208,7 @@ enum Op {
 
 pub struct I2CDevice<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a> = NoSMBus> {
     mux: &'a MuxI2C<'a, I, S>,
-    addr: u8,
+    addr: Cell<u8>,
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
@@ -217,7 +220,7 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> I2CDevice<'a, I, S> {
     pub fn new(mux: &'a MuxI2C<'a, I, S>, addr: u8) -> I2CDevice<'a, I, S> {
         I2CDevice {
             mux,
-            addr,
+            addr: Cell::new(addr),
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
@@ -462,3 +465,109 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> i2c::SMBusDevice
         }
     }
 }
+
+/// Receives the result of an I2C bus scan.
+pub trait I2CScanClient {
+    /// Called once every address in the requested range has been probed.
+    /// Bit `n` of `present` is set if a device acknowledged address `n`.
+    fn scan_done(&self, present: u128);
+}
+
+/// Probes a range of 7-bit addresses on a shared I2C bus.
+///
+/// Each address is probed with a zero-length write, which completes with
+/// `Ok(())` if a device acknowledged its address and with
+/// `Err(Error::AddressNak)` otherwise. Probes are queued through the `MuxI2C`
+/// like any other transfer, so scanning does not disturb other users of the
+/// bus. Controllers that cannot issue zero-length writes report every address
+/// as absent.
+///
+/// The scanner needs its own `I2CDevice`, whose address is changed for each
+/// probe; that device must not be shared with a driver.
+///
+/// ```rust,ignore
+/// let scan_device = static_init!(I2CDevice<'static, I>, I2CDevice::new(mux_i2c, 0));
+/// let scanner = static_init!(
+///     I2CBusScanner<'static, I>,
+///     I2CBusScanner::new(scan_device, &mut SCAN_BUF)
+/// );
+/// scan_device.set_client(scanner);
+/// scanner.set_client(diagnostics);
+/// scanner.scan(0x08, 0x77);
+/// ```
+pub struct I2CBusScanner<'a, I: i2c::I2CMaster<'a>> {
+    device: &'a I2CDevice<'a, I>,
+    buffer: TakeCell<'static, [u8]>,
+    current: Cell<u8>,
+    end: Cell<u8>,
+    present: Cell<u128>,
+    client: OptionalCell<&'a dyn I2CScanClient>,
+}
+
+impl<'a, I: i2c::I2CMaster<'a>> I2CBusScanner<'a, I> {
+    pub fn new(device: &'a I2CDevice<'a, I>, buffer: &'static mut [u8]) -> Self {
+        Self {
+            device,
+            buffer: TakeCell::new(buffer),
+            current: Cell::new(0),
+            end: Cell::new(0),
+            present: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn I2CScanClient) {
+        self.client.set(client);
+    }
+
+    /// Start probing every address from `start` up to and including `end`.
+    /// The result is reported through `I2CScanClient::scan_done`.
+    ///
+    /// Returns `INVAL` if the range is empty or contains addresses above
+    /// 0x7F and `BUSY` if a scan is already in progress.
+    pub fn scan(&self, start: u8, end: u8) -> Result<(), ErrorCode> {
+        if start > end || end > 0x7F {
+            return Err(ErrorCode::INVAL);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.current.set(start);
+        self.end.set(end);
+        self.present.set(0);
+        i2c::I2CDevice::enable(self.device);
+        self.probe(buffer);
+        Ok(())
+    }
+
+    fn probe(&self, buffer: &'static mut [u8]) {
+        self.device.addr.set(self.current.get());
+        if let Err((_, buffer)) = i2c::I2CDevice::write(self.device, buffer, 0) {
+            // The device is idle while a scan is in progress, so queueing the
+            // probe cannot fail. Report what was found so far instead of
+            // leaving the client waiting.
+            self.finish(buffer);
+        }
+    }
+
+    fn finish(&self, buffer: &'static mut [u8]) {
+        self.buffer.replace(buffer);
+        i2c::I2CDevice::disable(self.device);
+        self.client
+            .map(|client| client.scan_done(self.present.get()));
+    }
+}
+
+impl<'a, I: i2c::I2CMaster<'a>> I2CClient for I2CBusScanner<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        let addr = self.current.get();
+        if status.is_ok() {
+            self.present.set(self.present.get() | (1 << addr));
+        }
+
+        if addr < self.end.get() {
+            self.current.set(addr + 1);
+            self.probe(buffer);
+        } else {
+            self.finish(buffer);
+        }
+    }
+}
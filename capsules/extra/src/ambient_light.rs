@@ -14,7 +14,15 @@
 //!     capsules::ambient_light::AmbientLight::new(isl29035,
 //!         board_kernel.create_grant(&grant_cap)));
 //! hil::sensors::AmbientLight::set_client(isl29035, ambient_light);
+//! ambient_light.set_thresholds(50, 100);
 //! ```
+//!
+//! Besides the readings themselves, the driver tracks whether it is dark or
+//! light, with hysteresis: it only becomes dark once a reading is below the
+//! dark threshold, and light once a reading is above the light threshold.
+//! Readings in between keep the current state, so a light level close to a
+//! single threshold doesn't flip the state back and forth. Processes are
+//! notified only when the state changes.
 
 use core::cell::Cell;
 
@@ -34,8 +42,23 @@ mod upcall {
     /// The callback signature is `fn(lux: usize)`, where `lux` is the light
     /// intensity in lux (lx).
     pub const LIGHT_INTENSITY: usize = 0;
+    /// Subscribe to transitions between dark and light.
+    ///
+    /// The callback signature is `fn(light: usize, lux: usize)`, where
+    /// `light` is 0 when it became dark and 1 when it became light, and
+    /// `lux` is the reading that caused the transition.
+    pub const LIGHT_LEVEL: usize = 1;
     /// Number of upcalls.
-    pub const COUNT: u8 = 1;
+    pub const COUNT: u8 = 2;
+}
+
+/// Light level as tracked with the hysteresis thresholds.
+#[derive(Clone, Copy, PartialEq)]
+enum LightLevel {
+    /// No reading was outside of the thresholds yet.
+    Unknown,
+    Dark,
+    Light,
 }
 
 /// Per-process metadata
@@ -47,6 +70,11 @@ pub struct App {
 pub struct AmbientLight<'a> {
     sensor: &'a dyn hil::sensors::AmbientLight<'a>,
     command_pending: Cell<bool>,
+    /// Readings below this are dark.
+    dark_below: Cell<usize>,
+    /// Readings above this are light.
+    light_above: Cell<usize>,
+    level: Cell<LightLevel>,
     apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
 }
 
@@ -58,10 +86,43 @@ impl<'a> AmbientLight<'a> {
         AmbientLight {
             sensor,
             command_pending: Cell::new(false),
+            // No reading is below 0 or above `usize::MAX` lux, so the level
+            // never changes until thresholds are set.
+            dark_below: Cell::new(0),
+            light_above: Cell::new(usize::MAX),
+            level: Cell::new(LightLevel::Unknown),
             apps: grant,
         }
     }
 
+    /// Set the hysteresis thresholds, in lux: it becomes dark when a reading
+    /// is below `dark_below`, and light when a reading is above
+    /// `light_above`. `light_above` must be larger than `dark_below`, and
+    /// the wider the gap, the more flicker is ignored.
+    pub fn set_thresholds(&self, dark_below: usize, light_above: usize) {
+        debug_assert!(light_above > dark_below);
+        self.dark_below.set(dark_below);
+        self.light_above.set(light_above);
+        self.level.set(LightLevel::Unknown);
+    }
+
+    /// Update the light level with a new reading, returning the new level if
+    /// it changed.
+    fn update_level(&self, lux: usize) -> Option<LightLevel> {
+        let level = if lux < self.dark_below.get() {
+            LightLevel::Dark
+        } else if lux > self.light_above.get() {
+            LightLevel::Light
+        } else {
+            return None;
+        };
+        if level == self.level.replace(level) {
+            None
+        } else {
+            Some(level)
+        }
+    }
+
     fn enqueue_sensor_reading(&self, processid: ProcessId) -> Result<(), ErrorCode> {
         self.apps
             .enter(processid, |app, _| {
@@ -117,6 +178,7 @@ impl SyscallDriver for AmbientLight<'_> {
 impl hil::sensors::AmbientLightClient for AmbientLight<'_> {
     fn callback(&self, lux: usize) {
         self.command_pending.set(false);
+        let transition = self.update_level(lux);
         self.apps.each(|_, app, upcalls| {
             if app.pending {
                 app.pending = false;
@@ -124,6 +186,12 @@ impl hil::sensors::AmbientLightClient for AmbientLight<'_> {
                     .schedule_upcall(upcall::LIGHT_INTENSITY, (lux, 0, 0))
                     .ok();
             }
+            if let Some(level) = transition {
+                let light = usize::from(level == LightLevel::Light);
+                upcalls
+                    .schedule_upcall(upcall::LIGHT_LEVEL, (light, lux, 0))
+                    .ok();
+            }
         });
     }
 }
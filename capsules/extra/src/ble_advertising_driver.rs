@@ -317,7 +317,7 @@ impl App {
     fn set_next_alarm<F: Frequency>(&mut self, now: u32) {
         let nonce = self.random_nonce() % 10;
 
-        let period_ms = (self.advertisement_interval_ms + nonce) * F::frequency() / 1000;
+        let period_ms = F::ticks_in_ms(self.advertisement_interval_ms + nonce);
         self.alarm_data.expiration = Expiration::Enabled(now, period_ms);
     }
 }
@@ -54,7 +54,7 @@ use core::cmp;
 
 use kernel::hil;
 use kernel::hil::buzzer::BuzzerClient;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
@@ -96,9 +96,8 @@ impl<'a, A: hil::time::Alarm<'a>, P: hil::pwm::PwmPin> hil::buzzer::Buzzer<'a>
             .start(frequency_hz, self.pwm_pin.get_maximum_duty_cycle() / 2)?;
 
         // Set an alarm for the given duration.
-        let interval = (duration_ms_cmp as u32) * <A::Frequency>::frequency() / 1000;
-        self.alarm
-            .set_alarm(self.alarm.now(), A::Ticks::from(interval));
+        let interval = self.alarm.ticks_from_ms(duration_ms_cmp as u32);
+        self.alarm.set_alarm(self.alarm.now(), interval);
         Ok(())
     }
 
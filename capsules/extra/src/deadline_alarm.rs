@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Software watchdog for kernel loops that must make progress in time.
+//!
+//! A `DeadlineAlarm` expects to be kicked at least once per deadline. Every
+//! `kick` pushes the deadline back by its full length, measured from the time
+//! of the kick. If the deadline passes without a kick, the client's
+//! `deadline_missed` callback is called and the alarm stops until it is
+//! started again.
+//!
+//! Unlike a periodic timer, a `DeadlineAlarm` doesn't fire at all while the
+//! monitored code runs on time: the callback only signals a missed kick.
+//!
+//! The deadline must be at least the `minimum_dt` of the underlying alarm,
+//! otherwise `start` fails with `INVAL`. In practice it should be much larger
+//! than that, as the callback is delivered through the alarm interrupt and the
+//! kernel loop. A deadline close to `minimum_dt` reports misses for loops that
+//! were merely delayed by the processing of other interrupts.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let deadline_virtual_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf52::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! deadline_virtual_alarm.setup();
+//! let deadline = static_init!(
+//!     DeadlineAlarm<'static, VirtualMuxAlarm<'static, nrf52::rtc::Rtc>>,
+//!     DeadlineAlarm::new(deadline_virtual_alarm)
+//! );
+//! deadline_virtual_alarm.set_alarm_client(deadline);
+//! deadline.set_client(monitor);
+//! deadline.start(deadline_virtual_alarm.ticks_from_ms(100))?;
+//!
+//! // From the monitored loop:
+//! deadline.kick()?;
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{Alarm, AlarmClient};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Receives the notification that a `DeadlineAlarm` was not kicked in time.
+pub trait DeadlineClient {
+    fn deadline_missed(&self);
+}
+
+pub struct DeadlineAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// The length of the deadline, or `None` if stopped.
+    deadline: OptionalCell<A::Ticks>,
+    /// Number of deadlines missed since the alarm was created.
+    missed: Cell<usize>,
+    client: OptionalCell<&'a dyn DeadlineClient>,
+}
+
+impl<'a, A: Alarm<'a>> DeadlineAlarm<'a, A> {
+    pub fn new(alarm: &'a A) -> DeadlineAlarm<'a, A> {
+        DeadlineAlarm {
+            alarm,
+            deadline: OptionalCell::empty(),
+            missed: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn DeadlineClient) {
+        self.client.set(client);
+    }
+
+    /// Start monitoring with a deadline of `deadline` ticks from now. If
+    /// already started, this replaces the deadline.
+    ///
+    /// Fails with `INVAL` if `deadline` is less than the `minimum_dt` of the
+    /// underlying alarm.
+    pub fn start(&self, deadline: A::Ticks) -> Result<(), ErrorCode> {
+        if deadline < self.alarm.minimum_dt() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.deadline.set(deadline);
+        self.alarm.set_alarm_from_now(deadline);
+        Ok(())
+    }
+
+    /// Push the deadline back to a full deadline from now.
+    ///
+    /// Fails with `OFF` if the alarm is not started, including after a
+    /// missed deadline.
+    pub fn kick(&self) -> Result<(), ErrorCode> {
+        let deadline = self.deadline.get().ok_or(ErrorCode::OFF)?;
+        self.alarm.set_alarm_from_now(deadline);
+        Ok(())
+    }
+
+    /// Stop monitoring.
+    pub fn stop(&self) -> Result<(), ErrorCode> {
+        self.deadline.clear();
+        self.alarm.disarm()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// Number of deadlines missed since the alarm was created.
+    pub fn missed_count(&self) -> usize {
+        self.missed.get()
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for DeadlineAlarm<'a, A> {
+    fn alarm(&self) {
+        // A callback that was already pending when the alarm got stopped is
+        // not a missed deadline.
+        if self.deadline.take().is_some() {
+            self.missed.set(self.missed.get() + 1);
+            self.client.map(|client| client.deadline_missed());
+        }
+    }
+}
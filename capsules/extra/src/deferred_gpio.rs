@@ -0,0 +1,92 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Split-phase reads of a synchronous GPIO input pin.
+//!
+//! `DeferredRead` wraps a `hil::gpio::Input` pin so that a read completes
+//! through a callback, like the operations of truly asynchronous
+//! peripherals, rather than returning the level inline. This lets a driver
+//! built as a chain of callbacks treat a GPIO read as just another step of
+//! its state machine, instead of special-casing it.
+//!
+//! The level is sampled when `read` is called, and delivered to the client
+//! from a deferred call, i.e. once the current call stack has returned to
+//! the kernel loop. Only one read can be outstanding at a time.
+//!
+//! This is opt-in: code that can use the level inline should keep calling
+//! `Input::read` on the pin directly.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let deferred_read = static_init!(
+//!     capsules_extra::deferred_gpio::DeferredRead<'static, sam4l::gpio::GPIOPin>,
+//!     capsules_extra::deferred_gpio::DeferredRead::new(&sam4l::gpio::PA[13])
+//! );
+//! deferred_read.register();
+//! deferred_read.set_client(driver);
+//! ```
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::gpio;
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Receives the result of a `DeferredRead::read`.
+pub trait DeferredReadClient {
+    /// Called with the level the pin had when `read` was called, `true`
+    /// for high.
+    fn read_done(&self, value: bool);
+}
+
+pub struct DeferredRead<'a, P: gpio::Input> {
+    pin: &'a P,
+    client: OptionalCell<&'a dyn DeferredReadClient>,
+    /// Level sampled by a pending read, not yet delivered to the client.
+    value: OptionalCell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, P: gpio::Input> DeferredRead<'a, P> {
+    pub fn new(pin: &'a P) -> Self {
+        Self {
+            pin,
+            client: OptionalCell::empty(),
+            value: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn DeferredReadClient) {
+        self.client.set(client);
+    }
+
+    /// Sample the pin, and report its level with `read_done` from a deferred
+    /// call. Fails with `BUSY` if a previous read hasn't completed yet.
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        if self.value.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.value.set(self.pin.read());
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a, P: gpio::Input> DeferredCallClient for DeferredRead<'a, P> {
+    fn handle_deferred_call(&self) {
+        // Clear the pending read before the callback, so that the client
+        // can start the next read from `read_done`.
+        if let Some(value) = self.value.take() {
+            self.client.map(|client| client.read_done(value));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -33,7 +33,9 @@ pub mod crc;
 pub mod cycle_count;
 pub mod dac;
 pub mod date_time;
+pub mod deadline_alarm;
 pub mod debug_process_restart;
+pub mod deferred_gpio;
 pub mod eui64;
 pub mod fm25cl;
 pub mod ft6x06;
@@ -52,6 +54,7 @@ pub mod kv_store_permissions;
 pub mod l3gd20;
 pub mod led_matrix;
 pub mod log;
+pub mod log_auto_sync;
 pub mod lpm013m126;
 pub mod lps22hb;
 pub mod lps25hb;
@@ -70,11 +73,13 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod polled_interrupt_pin;
 pub mod pressure;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod read_only_state;
+pub mod reset_line;
 pub mod rf233;
 pub mod rf233_const;
 pub mod screen;
@@ -103,3 +108,4 @@ pub mod tsl2561;
 pub mod usb;
 pub mod usb_hid_driver;
 pub mod virtual_kv;
+pub mod virtual_time64;
@@ -63,6 +63,7 @@
 //! ```
 
 use core::cell::Cell;
+use core::cmp::Ordering;
 use core::mem::size_of;
 use core::unreachable;
 
@@ -574,10 +575,10 @@ impl<'a, F: Flash + 'static> LogRead<'a> for Log<'a, F> {
     /// `Result<(), ErrorCode>`s used:
     ///     * `FAIL`: reached end of log, nothing to read.
     ///     * `BUSY`: log busy with another operation, try again later.
-    ///     * `INVAL`: provided client buffer is too small.
     ///     * `CANCEL`: invalid internal state, read entry ID was reset to start of log.
     ///     * `RESERVE`: client or internal pagebuffer missing.
-    ///     * `SIZE`: buffer not large enough to contain entry being read.
+    ///     * `SIZE`: provided client buffer is shorter than `length`, or not large enough to
+    ///       contain entry being read.
     /// `Result<(), ErrorCode>`s used in read_done callback:
     ///     * `Ok(())`: read succeeded.
     fn read(
@@ -591,7 +592,7 @@ impl<'a, F: Flash + 'static> LogRead<'a> for Log<'a, F> {
             return Err((ErrorCode::BUSY, buffer));
         } else if buffer.len() < length {
             // Client buffer too small for provided length.
-            return Err((ErrorCode::INVAL, buffer));
+            return Err((ErrorCode::SIZE, buffer));
         } else if self.read_entry_id.get() > self.append_entry_id.get() {
             // Read entry ID beyond append entry ID, must be invalid.
             self.read_entry_id.set(self.oldest_entry_id.get());
@@ -630,14 +631,29 @@ impl<'a, F: Flash + 'static> LogRead<'a> for Log<'a, F> {
         self.read_entry_id.get()
     }
 
+    /// Compare two entry IDs. Entry IDs keep increasing when a circular log wraps around, so they
+    /// are ordered like the entries, and only IDs past the end of the log are not comparable.
+    fn entry_id_ordering(&self, a: Self::EntryID, b: Self::EntryID) -> Option<Ordering> {
+        let append_entry_id = self.append_entry_id.get();
+        if a > append_entry_id || b > append_entry_id {
+            None
+        } else {
+            Some(a.cmp(&b))
+        }
+    }
+
     /// Seek to a new read entry ID. It is only legal to seek to entry IDs retrieved through the
     /// `log_start()`, `log_end()`, and `next_read_entry_id()` functions.
     /// Result<(), ErrorCode>s used:
     ///     * Ok(()): seek succeeded.
+    ///     * BUSY: log busy with another operation, try again later.
     ///     * INVAL: entry ID not valid seek position within current log.
     ///     * RESERVE: no log client set.
     fn seek(&self, entry_id: Self::EntryID) -> Result<(), ErrorCode> {
-        if entry_id <= self.append_entry_id.get() && entry_id >= self.oldest_entry_id.get() {
+        if self.state.get() != State::Idle {
+            // Log busy, try seeking again later.
+            Err(ErrorCode::BUSY)
+        } else if entry_id <= self.append_entry_id.get() && entry_id >= self.oldest_entry_id.get() {
             self.read_entry_id.set(entry_id);
 
             self.state.set(State::Seek);
@@ -653,6 +669,15 @@ impl<'a, F: Flash + 'static> LogRead<'a> for Log<'a, F> {
     fn get_size(&self) -> usize {
         self.capacity
     }
+
+    /// Get approximate number of bytes in the log, including the padding at the end of pages.
+    fn used_size(&self) -> usize {
+        let oldest_entry_id = self.oldest_entry_id.get();
+        let append_entry_id = self.append_entry_id.get();
+        // Exclude the headers of the pages between the oldest and the next entry.
+        let pages = append_entry_id / self.page_size - oldest_entry_id / self.page_size;
+        append_entry_id - oldest_entry_id - pages * PAGE_HEADER_SIZE
+    }
 }
 
 impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
@@ -670,9 +695,10 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
     /// `Result<(), ErrorCode>`s used:
     ///     * `FAIL`: end of non-circular log reached, cannot append any more entries.
     ///     * `BUSY`: log busy with another operation, try again later.
-    ///     * `INVAL`: provided client buffer is too small.
+    ///     * `INVAL`: entry length is 0.
     ///     * `RESERVE`: client or internal pagebuffer missing.
-    ///     * `SIZE`: entry too large to append to log.
+    ///     * `SIZE`: provided client buffer is shorter than `length`, or entry too large to append
+    ///       to log.
     /// `Result<(), ErrorCode>`s used in append_done callback:
     ///     * `Ok(())`: append succeeded.
     ///     * `FAIL`: write failed due to flash error.
@@ -688,9 +714,12 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
         if self.state.get() != State::Idle {
             // Log busy, try appending again later.
             return Err((ErrorCode::BUSY, buffer));
-        } else if length == 0 || buffer.len() < length {
+        } else if length == 0 {
             // Invalid length provided.
             return Err((ErrorCode::INVAL, buffer));
+        } else if buffer.len() < length {
+            // Client buffer too small for provided length.
+            return Err((ErrorCode::SIZE, buffer));
         } else if entry_size + PAGE_HEADER_SIZE > self.page_size {
             // Entry too big, won't fit within a single page.
             return Err((ErrorCode::SIZE, buffer));
@@ -743,12 +772,13 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
     ///     * Ok(()): append succeeded.
     ///     * FAIL: write failed due to flash error.
     fn sync(&self) -> Result<(), ErrorCode> {
-        if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
+        if self.state.get() != State::Idle {
+            // Log busy, try syncing again later. This must be checked before the pagebuffer, so
+            // syncing fails consistently while an erase is in progress.
+            return Err(ErrorCode::BUSY);
+        } else if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
             // Pagebuffer empty, don't need to flush.
             return Ok(());
-        } else if self.state.get() != State::Idle {
-            // Log busy, try appending again later.
-            return Err(ErrorCode::BUSY);
         }
 
         self.pagebuffer
@@ -763,7 +793,7 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
             })
     }
 
-    /// Erase the entire log.
+    /// Erase the entire log. All other operations fail with BUSY until the erase_done callback.
     /// Result<(), ErrorCode>s used:
     ///     * Ok(()): flush started successfully.
     ///     * BUSY: log busy, try again later.
@@ -777,7 +807,12 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
         }
 
         self.state.set(State::Erase);
-        self.erase_page()
+        let return_code = self.erase_page();
+        if return_code != Ok(()) {
+            // No erase_done callback will follow, so the log is usable right away.
+            self.state.set(State::Idle);
+        }
+        return_code
     }
 }
 
@@ -863,10 +898,12 @@ impl<'a, F: Flash + 'static> flash::Client<F> for Log<'a, F> {
                     self.client_callback();
                 } else {
                     // Not done, erase next page.
-                    self.oldest_entry_id.set(oldest_entry_id + self.page_size);
+                    let oldest_entry_id = oldest_entry_id + self.page_size;
+                    self.oldest_entry_id.set(oldest_entry_id);
                     let status = self.erase_page();
 
-                    // Abort and alert client if flash driver is busy.
+                    // Abort and alert client if flash driver is busy. The pages before the
+                    // oldest entry were erased, so don't leave the read entry ID on them.
                     if status == Err(ErrorCode::BUSY) {
                         self.read_entry_id
                             .set(core::cmp::max(self.read_entry_id.get(), oldest_entry_id));
@@ -877,6 +914,11 @@ impl<'a, F: Flash + 'static> flash::Client<F> for Log<'a, F> {
             }
             false => match result.unwrap_err() {
                 flash::Error::FlashError | flash::Error::FlashMemoryProtectionError => {
+                    // The pages before the oldest entry were erased, see above.
+                    self.read_entry_id.set(core::cmp::max(
+                        self.read_entry_id.get(),
+                        self.oldest_entry_id.get(),
+                    ));
                     self.error.set(Err(ErrorCode::FAIL));
                     self.client_callback();
                 }
@@ -0,0 +1,189 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Automatically sync a log after a number of appended bytes or a timeout.
+//!
+//! `LogWrite` implementations only guarantee that appended entries are
+//! persistent once the log has been synced, so a client that forgets to call
+//! `sync()` may lose the entries that are buffered in RAM on a reset.
+//! `LogAutoSync` wraps a `LogWrite` and syncs it on behalf of its client:
+//!
+//! - as soon as `sync_threshold` bytes have been appended since the last sync,
+//!   and
+//! - at the latest `sync_interval_ms` milliseconds after the first append
+//!   following the last sync.
+//!
+//! Small appends are therefore batched into a single flash write, while every
+//! entry becomes durable within a bounded window. If the log is busy when an
+//! automatic sync is due, it is retried after the next append or once the
+//! interval expires again. Each automatic sync is reported to the client
+//! through `LogWriteClient::sync_done`, just like syncs requested by the
+//! client itself.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let sync_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! sync_alarm.setup();
+//! let auto_sync = static_init!(
+//!     LogAutoSync<'static, Log<'static, FlashCtrl>, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     LogAutoSync::new(log, sync_alarm, 256, 5000)
+//! );
+//! log.set_append_client(auto_sync);
+//! sync_alarm.set_alarm_client(auto_sync);
+//! auto_sync.set_append_client(log_client);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::log::{LogWrite, LogWriteClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct LogAutoSync<'a, L: LogWrite<'a>, A: Alarm<'a>> {
+    log: &'a L,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn LogWriteClient>,
+    /// Number of appended bytes after which the log is synced.
+    sync_threshold: usize,
+    /// Maximum time an appended entry stays unsynced.
+    sync_interval_ms: u32,
+    /// Bytes appended since the last sync.
+    unsynced_bytes: Cell<usize>,
+    /// Whether an automatic sync is due but could not be started yet.
+    sync_pending: Cell<bool>,
+}
+
+impl<'a, L: LogWrite<'a>, A: Alarm<'a>> LogAutoSync<'a, L, A> {
+    pub fn new(
+        log: &'a L,
+        alarm: &'a A,
+        sync_threshold: usize,
+        sync_interval_ms: u32,
+    ) -> LogAutoSync<'a, L, A> {
+        LogAutoSync {
+            log,
+            alarm,
+            client: OptionalCell::empty(),
+            sync_threshold,
+            sync_interval_ms,
+            unsynced_bytes: Cell::new(0),
+            sync_pending: Cell::new(false),
+        }
+    }
+
+    fn arm_timeout(&self) {
+        if !self.alarm.is_armed() {
+            let dt = self.alarm.ticks_from_ms(self.sync_interval_ms);
+            self.alarm.set_alarm(self.alarm.now(), dt);
+        }
+    }
+
+    // Everything appended so far will be persisted by a sync that was just
+    // started, so no automatic sync is needed until the next append.
+    fn sync_started(&self) {
+        self.unsynced_bytes.set(0);
+        self.sync_pending.set(false);
+        let _ = self.alarm.disarm();
+    }
+
+    fn try_auto_sync(&self) {
+        if !self.sync_pending.get() {
+            return;
+        }
+        match self.log.sync() {
+            Ok(()) => self.sync_started(),
+            Err(_) => {
+                // Most likely the log is busy with another operation. Retry
+                // after the next append, or once the timeout expires if no
+                // append follows.
+                let _ = self.alarm.disarm();
+                self.arm_timeout();
+            }
+        }
+    }
+}
+
+impl<'a, L: LogWrite<'a>, A: Alarm<'a>> LogWrite<'a> for LogAutoSync<'a, L, A> {
+    fn set_append_client(&'a self, append_client: &'a dyn LogWriteClient) {
+        self.client.set(append_client);
+    }
+
+    fn append(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.log.append(buffer, length)
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        let result = self.log.sync();
+        if result.is_ok() {
+            self.sync_started();
+        }
+        result
+    }
+
+    fn erase(&self) -> Result<(), ErrorCode> {
+        self.log.erase()
+    }
+}
+
+impl<'a, L: LogWrite<'a>, A: Alarm<'a>> LogWriteClient for LogAutoSync<'a, L, A> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        if error.is_ok() {
+            let unsynced_bytes = self.unsynced_bytes.get() + length;
+            self.unsynced_bytes.set(unsynced_bytes);
+            if unsynced_bytes >= self.sync_threshold {
+                self.sync_pending.set(true);
+            } else {
+                self.arm_timeout();
+            }
+        }
+
+        // Let the client issue its next append first, so an automatic sync
+        // doesn't make it fail with BUSY. The sync is then retried after that
+        // append completes.
+        self.client.map(move |client| {
+            client.append_done(buffer, length, records_lost, error);
+        });
+        self.try_auto_sync();
+    }
+
+    fn sync_done(&self, error: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(error));
+        self.try_auto_sync();
+    }
+
+    fn erase_done(&self, error: Result<(), ErrorCode>) {
+        if error.is_ok() {
+            // Nothing left to persist.
+            self.sync_started();
+        }
+        self.client.map(|client| client.erase_done(error));
+    }
+}
+
+impl<'a, L: LogWrite<'a>, A: Alarm<'a>> time::AlarmClient for LogAutoSync<'a, L, A> {
+    fn alarm(&self) {
+        if self.unsynced_bytes.get() > 0 {
+            self.sync_pending.set(true);
+            self.try_auto_sync();
+        }
+    }
+}
@@ -10,6 +10,9 @@ use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::UDPHeader;
+use crate::net::util;
+
+use core::fmt;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum MacAddr {
@@ -30,6 +33,23 @@ pub mod ip6_nh {
     pub const MOBILITY: u8 = 135;
 }
 
+/// Scope of an IPv6 address, as given by its leading bits (RFC 4291
+/// section 2.4, and RFC 4193 for unique local addresses).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddrScope {
+    /// `::1`
+    Loopback,
+    /// Unicast `fe80::/10`
+    LinkLocal,
+    /// `ff00::/8`, of any multicast scope
+    Multicast,
+    /// `fc00::/7`
+    UniqueLocal,
+    /// Any other address. This includes the unspecified address `::`, which
+    /// is never a valid destination.
+    Global,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct IPAddr(pub [u8; 16]);
 
@@ -42,6 +62,9 @@ impl PartialEq for IPAddr {
 impl Eq for IPAddr {}
 
 impl IPAddr {
+    /// The loopback address, `::1`.
+    pub const LOOPBACK: IPAddr = IPAddr([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
     pub fn new() -> IPAddr {
         // Defaults to the unspecified address
         IPAddr([0; 16])
@@ -112,8 +135,105 @@ impl IPAddr {
         }
     }
 
+    /// Returns whether the first `prefix_bits` bits of this address and
+    /// `other` are equal, i.e. whether both are in the same `/prefix_bits`
+    /// subnet. A prefix that does not end on a byte boundary only compares
+    /// the leading bits of its last byte. Prefixes longer than 128 bits never
+    /// match.
+    pub fn matches_prefix(&self, other: &IPAddr, prefix_bits: usize) -> bool {
+        prefix_bits <= 128 && util::matches_prefix(&self.0, &other.0, prefix_bits as u8)
+    }
+
     pub fn is_multicast(&self) -> bool {
-        self.0[0] == 0xff
+        self.scope() == AddrScope::Multicast
+    }
+
+    /// Classify this address by its leading bits.
+    pub fn scope(&self) -> AddrScope {
+        if *self == IPAddr::LOOPBACK {
+            AddrScope::Loopback
+        } else if self.0[0] == 0xff {
+            AddrScope::Multicast
+        } else if self.0[0] == 0xfe && (self.0[1] & 0xc0) == 0x80 {
+            AddrScope::LinkLocal
+        } else if (self.0[0] & 0xfe) == 0xfc {
+            AddrScope::UniqueLocal
+        } else {
+            AddrScope::Global
+        }
+    }
+
+    /// Returns a wrapper that formats this address in colon-hex notation.
+    pub fn display(&self) -> IPAddrDisplay<'_> {
+        IPAddrDisplay {
+            addr: self,
+            prefix_len: None,
+        }
+    }
+
+    /// Returns a wrapper that formats this address in colon-hex notation,
+    /// followed by `/prefix_len`.
+    pub fn display_prefix(&self, prefix_len: usize) -> IPAddrDisplay<'_> {
+        IPAddrDisplay {
+            addr: self,
+            prefix_len: Some(prefix_len),
+        }
+    }
+}
+
+/// Formats an `IPAddr` as described in RFC 5952: lowercase hex groups without
+/// leading zeros, with the longest run of two or more zero groups compressed
+/// to `::`, e.g. `fe80::1`. If a prefix length is set it is appended, e.g.
+/// `fd00::/64`. Created with `IPAddr::display` or `IPAddr::display_prefix`.
+pub struct IPAddrDisplay<'a> {
+    addr: &'a IPAddr,
+    prefix_len: Option<usize>,
+}
+
+impl fmt::Display for IPAddrDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut groups = [0u16; 8];
+        for (i, group) in groups.iter_mut().enumerate() {
+            *group = u16::from_be_bytes([self.addr.0[2 * i], self.addr.0[2 * i + 1]]);
+        }
+
+        // Find the first longest run of zero groups.
+        let (mut zeros_start, mut zeros_len) = (0, 0);
+        let mut i = 0;
+        while i < groups.len() {
+            let start = i;
+            while i < groups.len() && groups[i] == 0 {
+                i += 1;
+            }
+            if i - start > zeros_len {
+                zeros_start = start;
+                zeros_len = i - start;
+            }
+            i += 1;
+        }
+        // A single zero group is not compressed.
+        if zeros_len < 2 {
+            zeros_len = 0;
+        }
+
+        let mut i = 0;
+        while i < groups.len() {
+            if zeros_len > 0 && i == zeros_start {
+                write!(f, "::")?;
+                i += zeros_len;
+                continue;
+            }
+            if i > 0 && !(zeros_len > 0 && i == zeros_start + zeros_len) {
+                write!(f, ":")?;
+            }
+            write!(f, "{:x}", groups[i])?;
+            i += 1;
+        }
+
+        if let Some(prefix_len) = self.prefix_len {
+            write!(f, "/{}", prefix_len)?;
+        }
+        Ok(())
     }
 }
 
@@ -65,6 +65,10 @@ pub trait IP6Sender<'a> {
     /// from this instance of `IP6Sender`
     fn set_addr(&self, src_addr: IPAddr);
 
+    /// Returns the source address of the packets sent from this instance,
+    /// as set with `set_addr`.
+    fn addr(&self) -> IPAddr;
+
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
     /// instance.
     ///
@@ -123,6 +127,10 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.src_addr.set(src_addr);
     }
 
+    fn addr(&self) -> IPAddr {
+        self.src_addr.get()
+    }
+
     fn set_gateway(&self, gateway: MacAddress) {
         self.gateway.set(gateway);
     }
@@ -139,7 +147,12 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         payload: &SubSliceMut<'static, u8>,
         net_cap: &'static NetworkCapability,
     ) -> Result<(), ErrorCode> {
-        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis)
+            || !net_cap.local_addr_valid(self.src_addr.get(), self.ip_vis)
+        {
+            return Err(ErrorCode::FAIL);
+        }
+        if dst.is_multicast() && !net_cap.broadcast_allowed(self.ip_vis) {
             return Err(ErrorCode::FAIL);
         }
 
@@ -6,9 +6,13 @@
 //!
 //! A network capability specifies (1) with what IP addresses the holder of the
 //! capability may communicate, (2) from which UDP ports the holder may send,
-//! and (3) to which UDP ports the holder may send. In order to express various
-//! ranges of IP addresses, one uses the AddrRange enum. One specifies ranges of
-//! ports using the PortRange enum.
+//! (3) to which UDP ports the holder may send, and (4) from which local IP
+//! addresses the holder may send, which matters on a node with several
+//! addresses, e.g. a link-local and a global one. The last defaults to any
+//! address. In order to express various ranges of IP addresses, one uses the
+//! AddrRange enum, which can also match all addresses of an `AddrScope`, e.g.
+//! only link-local peers. One specifies ranges of ports using the PortRange
+//! enum.
 //!
 //! Capsules must obtain static references to network capabilities from trusted
 //! code (i.e. code that must use the unsafe keyword) since the constructor of
@@ -22,42 +26,87 @@
 //! bind has a capability to send from that port. Therefore, we check the
 //! network capability of the caller. In order to check the UDP-specific aspect
 //! of the network capability, the port table must posses a UdpVisibilityCapability reference.
+//!
+//! Sending to a multicast address (IPv6 has no broadcast, multicast to e.g.
+//! all nodes `ff02::1` takes its place) is granted separately from the
+//! address range, as a capability that permits communicating with any
+//! address should not implicitly permit flooding the network. Broadcast is
+//! disallowed by default and is enabled with `NetworkCapability::with_broadcast`.
+pub use crate::net::ipv6::ip_utils::AddrScope;
 use crate::net::ipv6::ip_utils::IPAddr;
 
+use core::fmt;
+
 const MAX_ADDR_SET_SIZE: usize = 8;
 const MAX_PORT_SET_SIZE: usize = 8;
 
 use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::debug;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddrRange {
     Any, // Any address
     NoAddrs,
-    AddrSet([IPAddr; MAX_ADDR_SET_SIZE]),
+    AddrSet([IPAddr; MAX_ADDR_SET_SIZE], usize), // addresses, number in use
     Addr(IPAddr),
     Subnet(IPAddr, usize), // address, prefix length (max 128)
+    Scope(AddrScope),      // any address of the scope, see `IPAddr::scope`
 }
 
 impl AddrRange {
+    /// Create an `AddrRange::AddrSet` of the addresses in `addrs`. Returns
+    /// `None` if there are more than `MAX_ADDR_SET_SIZE` of them. Only the
+    /// given addresses are granted: the unused slots of the set, which hold
+    /// the unspecified address `::`, are never matched.
+    pub const fn from_slice(addrs: &[IPAddr]) -> Option<AddrRange> {
+        if addrs.len() > MAX_ADDR_SET_SIZE {
+            return None;
+        }
+        let mut set = [IPAddr([0; 16]); MAX_ADDR_SET_SIZE];
+        let mut i = 0;
+        while i < addrs.len() {
+            set[i] = addrs[i];
+            i += 1;
+        }
+        Some(AddrRange::AddrSet(set, addrs.len()))
+    }
+
     pub fn is_addr_valid(&self, addr: IPAddr) -> bool {
         match self {
             AddrRange::Any => true,
             AddrRange::NoAddrs => false,
-            AddrRange::AddrSet(allowed_addrs) => allowed_addrs.iter().any(|&a| a == addr),
+            AddrRange::AddrSet(allowed_addrs, len) => {
+                allowed_addrs.iter().take(*len).any(|&a| a == addr)
+            }
             AddrRange::Addr(allowed_addr) => addr == *allowed_addr, //TODO: refs?
             AddrRange::Subnet(allowed_addr, prefix_len) => {
-                let full_bytes: usize = prefix_len / 8;
-                let remainder_bits: usize = prefix_len % 8;
-                // initial bytes -- TODO: edge case
-                if allowed_addr.0[0..full_bytes] != addr.0[0..full_bytes] {
-                    false
-                } else if remainder_bits == 0 {
-                    true //this case is necessary bc right shifting a u8 by 8 bits is UB
-                } else {
-                    addr.0[full_bytes] >> (8 - remainder_bits)
-                        == allowed_addr.0[full_bytes] >> (8 - remainder_bits)
+                allowed_addr.matches_prefix(&addr, *prefix_len)
+            }
+            AddrRange::Scope(scope) => addr.scope() == *scope,
+        }
+    }
+}
+
+impl fmt::Display for AddrRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddrRange::Any => write!(f, "any"),
+            AddrRange::NoAddrs => write!(f, "none"),
+            AddrRange::AddrSet(addrs, len) => {
+                write!(f, "{{")?;
+                for (i, addr) in addrs.iter().take(*len).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", addr.display())?;
                 }
+                write!(f, "}}")
+            }
+            AddrRange::Addr(addr) => write!(f, "{}", addr.display()),
+            AddrRange::Subnet(addr, prefix_len) => {
+                write!(f, "{}", addr.display_prefix(*prefix_len))
             }
+            AddrRange::Scope(scope) => write!(f, "{:?}", scope),
         }
     }
 }
@@ -72,6 +121,18 @@ pub enum PortRange {
 }
 
 impl PortRange {
+    /// Create a `PortRange::Range` of the ports from `low` to `high`,
+    /// inclusive. Returns `None` if `low > high`, as such a range would
+    /// silently match no port at all, which is most likely due to transposed
+    /// arguments. Use `PortRange::NoPorts` to grant no ports.
+    pub const fn range(low: u16, high: u16) -> Option<PortRange> {
+        if low > high {
+            None
+        } else {
+            Some(PortRange::Range(low, high))
+        }
+    }
+
     pub fn is_port_valid(&self, port: u16) -> bool {
         match self {
             PortRange::Any => true,
@@ -83,6 +144,27 @@ impl PortRange {
     }
 }
 
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortRange::Any => write!(f, "any"),
+            PortRange::NoPorts => write!(f, "none"),
+            PortRange::PortSet(ports) => {
+                write!(f, "{{")?;
+                for (i, port) in ports.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", port)?;
+                }
+                write!(f, "}}")
+            }
+            PortRange::Range(low, high) => write!(f, "{}-{}", low, high),
+            PortRange::Port(port) => write!(f, "{}", port),
+        }
+    }
+}
+
 /// The UdpVisibilityCapability and IpVisibilityCapability has an empty private
 /// field to make it so the only way to create these structs is via a call to
 /// `new` which requires a NetworkCapabilityCreationCapability.
@@ -94,6 +176,14 @@ pub struct IpVisibilityCapability {
     _priv: (), // an empty private field
 }
 
+/// The UdpLoopbackCapability allows the holder to enable the loopback path of
+/// the UDP send mux, which delivers packets to local ports without sending
+/// them over the network. It can only be created with a
+/// NetworkCapabilityCreationCapability.
+pub struct UdpLoopbackCapability {
+    _priv: (), // an empty private field
+}
+
 impl UdpVisibilityCapability {
     pub fn new(
         _create_net_cap: &dyn NetworkCapabilityCreationCapability,
@@ -102,6 +192,12 @@ impl UdpVisibilityCapability {
     }
 }
 
+impl UdpLoopbackCapability {
+    pub fn new(_create_net_cap: &dyn NetworkCapabilityCreationCapability) -> UdpLoopbackCapability {
+        UdpLoopbackCapability { _priv: () }
+    }
+}
+
 impl IpVisibilityCapability {
     pub fn new(
         _create_net_cap: &dyn NetworkCapabilityCreationCapability,
@@ -110,6 +206,23 @@ impl IpVisibilityCapability {
     }
 }
 
+/// The part of a `NetworkCapability` that denied a send, so that the holder
+/// can tell which of its ranges is missing the destination or source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendDenied {
+    /// The remote address is not in the address range.
+    RemoteAddr,
+    /// The remote address is a multicast address, and the capability doesn't
+    /// allow broadcast.
+    Broadcast,
+    /// The remote port is not in the remote port range.
+    RemotePort,
+    /// The local port is not in the local port range.
+    LocalPort,
+    /// The source address is not in the local address range.
+    LocalAddr,
+}
+
 /// The NetworkCapability specifies access to network resourcess across the UDP
 /// and IP layers. Access to layer-specific information is mediated by the
 /// UdpVsibilityCapability and the IpVisibilityCapability.
@@ -118,6 +231,8 @@ pub struct NetworkCapability {
     remote_addrs: AddrRange, // IP addresses with which the holder may communicate
     remote_ports: PortRange, // ports to which the holder may send
     local_ports: PortRange,  // ports from which the holder may send
+    local_addrs: AddrRange,  // source IP addresses from which the holder may send
+    allow_broadcast: bool,   // whether the holder may send to multicast addresses
 }
 
 impl NetworkCapability {
@@ -131,6 +246,75 @@ impl NetworkCapability {
             remote_addrs,
             remote_ports,
             local_ports,
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        }
+    }
+
+    /// A capability to communicate with any address and port, for trusted
+    /// capsules such as the userspace UDP driver.
+    pub const fn unrestricted(
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        NetworkCapability {
+            remote_addrs: AddrRange::Any,
+            remote_ports: PortRange::Any,
+            local_ports: PortRange::Any,
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        }
+    }
+
+    /// A capability that grants no access at all.
+    pub const fn none(
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        NetworkCapability {
+            remote_addrs: AddrRange::NoAddrs,
+            remote_ports: PortRange::NoPorts,
+            local_ports: PortRange::NoPorts,
+            local_addrs: AddrRange::NoAddrs,
+            allow_broadcast: false,
+        }
+    }
+
+    /// A capability to communicate with any port on the local node through
+    /// the loopback address `::1` only.
+    pub const fn loopback_only(
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        NetworkCapability {
+            remote_addrs: AddrRange::Addr(IPAddr::LOOPBACK),
+            remote_ports: PortRange::Any,
+            local_ports: PortRange::Any,
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        }
+    }
+
+    /// Restrict the source addresses the holder may send from to
+    /// `local_addrs`, e.g. to the link-local address of a node that also has
+    /// a global one. Capabilities allow any source address otherwise.
+    pub const fn with_local_addrs(
+        self,
+        local_addrs: AddrRange,
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        NetworkCapability {
+            local_addrs,
+            ..self
+        }
+    }
+
+    /// Additionally allow the holder to send to multicast addresses in the
+    /// address range.
+    pub const fn with_broadcast(
+        self,
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        NetworkCapability {
+            allow_broadcast: true,
+            ..self
         }
     }
 
@@ -146,6 +330,20 @@ impl NetworkCapability {
         self.remote_addrs.is_addr_valid(remote_addr)
     }
 
+    /// Returns whether the holder may send packets with `local_addr` as
+    /// their source address.
+    pub fn local_addr_valid(
+        &self,
+        local_addr: IPAddr,
+        _ip_cap: &'static IpVisibilityCapability,
+    ) -> bool {
+        self.local_addrs.is_addr_valid(local_addr)
+    }
+
+    pub fn broadcast_allowed(&self, _ip_cap: &'static IpVisibilityCapability) -> bool {
+        self.allow_broadcast
+    }
+
     pub fn get_remote_ports(&self, _udp_cap: &'static UdpVisibilityCapability) -> PortRange {
         self.remote_ports
     }
@@ -169,4 +367,271 @@ impl NetworkCapability {
     ) -> bool {
         self.local_ports.is_port_valid(local_port)
     }
+
+    /// Check that the holder may send to `remote_addr`, including the
+    /// broadcast permission for multicast addresses.
+    pub fn check_remote_addr(
+        &self,
+        remote_addr: IPAddr,
+        ip_cap: &'static IpVisibilityCapability,
+    ) -> Result<(), SendDenied> {
+        if !self.remote_addr_valid(remote_addr, ip_cap) {
+            Err(SendDenied::RemoteAddr)
+        } else if remote_addr.is_multicast() && !self.broadcast_allowed(ip_cap) {
+            Err(SendDenied::Broadcast)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that the holder may send with `local_addr` as the source
+    /// address.
+    pub fn check_local_addr(
+        &self,
+        local_addr: IPAddr,
+        ip_cap: &'static IpVisibilityCapability,
+    ) -> Result<(), SendDenied> {
+        if self.local_addr_valid(local_addr, ip_cap) {
+            Ok(())
+        } else {
+            Err(SendDenied::LocalAddr)
+        }
+    }
+
+    /// Check that the holder may send from `local_port` to `remote_port`.
+    pub fn check_ports(
+        &self,
+        remote_port: u16,
+        local_port: u16,
+        udp_cap: &'static UdpVisibilityCapability,
+    ) -> Result<(), SendDenied> {
+        if !self.remote_port_valid(remote_port, udp_cap) {
+            Err(SendDenied::RemotePort)
+        } else if !self.local_port_valid(local_port, udp_cap) {
+            Err(SendDenied::LocalPort)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Print the address and port ranges this capability grants to the
+    /// debug console, to help diagnose why a send or bind was denied.
+    pub fn debug_dump(&self) {
+        debug!(
+            "NetworkCapability: remote addrs: {}, remote ports: {}, local ports: {}, local addrs: {}, broadcast: {}",
+            self.remote_addrs, self.remote_ports, self.local_ports, self.local_addrs, self.allow_broadcast
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBNET: IPAddr = IPAddr([
+        0xfd, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01,
+    ]);
+
+    fn with_byte(index: usize, value: u8) -> IPAddr {
+        let mut addr = SUBNET;
+        addr.0[index] = value;
+        addr
+    }
+
+    #[test]
+    fn subnet_prefix_0() {
+        let range = AddrRange::Subnet(SUBNET, 0);
+        assert!(range.is_addr_valid(SUBNET));
+        assert!(range.is_addr_valid(IPAddr([0xff; 16])));
+    }
+
+    #[test]
+    fn subnet_prefix_8() {
+        let range = AddrRange::Subnet(SUBNET, 8);
+        assert!(range.is_addr_valid(SUBNET));
+        assert!(range.is_addr_valid(with_byte(1, 0xff)));
+        assert!(!range.is_addr_valid(with_byte(0, 0xfc)));
+    }
+
+    #[test]
+    fn subnet_prefix_64() {
+        let range = AddrRange::Subnet(SUBNET, 64);
+        assert!(range.is_addr_valid(SUBNET));
+        assert!(range.is_addr_valid(with_byte(8, 0xff)));
+        assert!(!range.is_addr_valid(with_byte(7, 0xbd)));
+    }
+
+    #[test]
+    fn subnet_prefix_128() {
+        let range = AddrRange::Subnet(SUBNET, 128);
+        assert!(range.is_addr_valid(SUBNET));
+        assert!(!range.is_addr_valid(with_byte(15, 0x02)));
+    }
+
+    #[test]
+    fn subnet_partial_byte() {
+        let range = AddrRange::Subnet(SUBNET, 12);
+        assert!(range.is_addr_valid(with_byte(1, 0x0f)));
+        assert!(!range.is_addr_valid(with_byte(1, 0x10)));
+    }
+
+    #[test]
+    fn subnet_prefix_too_long() {
+        let range = AddrRange::Subnet(SUBNET, 129);
+        assert!(!range.is_addr_valid(SUBNET));
+    }
+
+    #[test]
+    fn addr_matches_prefix() {
+        let other = with_byte(2, 0x13);
+        // 0x12 and 0x13 only differ in the last bit of the third byte
+        assert!(SUBNET.matches_prefix(&other, 23));
+        assert!(other.matches_prefix(&SUBNET, 23));
+        assert!(!SUBNET.matches_prefix(&other, 24));
+        assert!(!SUBNET.matches_prefix(&other, 128));
+        assert!(SUBNET.matches_prefix(&SUBNET, 128));
+        assert!(!SUBNET.matches_prefix(&SUBNET, 129));
+    }
+
+    #[test]
+    fn addr_set_ignores_unused_slots() {
+        let other = with_byte(15, 0x02);
+        let set = AddrRange::from_slice(&[SUBNET, other]).unwrap();
+        assert!(set.is_addr_valid(SUBNET));
+        assert!(set.is_addr_valid(other));
+        assert!(!set.is_addr_valid(IPAddr::new()));
+        assert!(!set.is_addr_valid(with_byte(15, 0x03)));
+
+        assert!(AddrRange::from_slice(&[SUBNET; MAX_ADDR_SET_SIZE]).is_some());
+        assert!(AddrRange::from_slice(&[SUBNET; MAX_ADDR_SET_SIZE + 1]).is_none());
+    }
+
+    #[test]
+    fn addr_scope() {
+        let addr = |first: u8, second: u8| {
+            let mut addr = SUBNET;
+            addr.0[0] = first;
+            addr.0[1] = second;
+            addr
+        };
+        assert_eq!(IPAddr::LOOPBACK.scope(), AddrScope::Loopback);
+        assert_eq!(addr(0xfe, 0x80).scope(), AddrScope::LinkLocal);
+        assert_eq!(addr(0xfe, 0xbf).scope(), AddrScope::LinkLocal);
+        assert_eq!(addr(0xfe, 0xc0).scope(), AddrScope::Global);
+        assert_eq!(addr(0xff, 0x02).scope(), AddrScope::Multicast);
+        assert_eq!(addr(0xfc, 0x00).scope(), AddrScope::UniqueLocal);
+        assert_eq!(SUBNET.scope(), AddrScope::UniqueLocal);
+        assert_eq!(addr(0x20, 0x01).scope(), AddrScope::Global);
+        assert_eq!(IPAddr::new().scope(), AddrScope::Global);
+
+        let range = AddrRange::Scope(AddrScope::LinkLocal);
+        assert!(range.is_addr_valid(addr(0xfe, 0x80)));
+        assert!(!range.is_addr_valid(SUBNET));
+        assert!(!range.is_addr_valid(IPAddr::LOOPBACK));
+    }
+
+    #[test]
+    fn port_range_checked() {
+        assert!(PortRange::range(5000, 4000).is_none());
+
+        let range = PortRange::range(4000, 5000).unwrap();
+        assert!(range.is_port_valid(4000));
+        assert!(range.is_port_valid(5000));
+        assert!(!range.is_port_valid(3999));
+        assert!(!range.is_port_valid(5001));
+
+        let single = PortRange::range(4000, 4000).unwrap();
+        assert!(single.is_port_valid(4000));
+        assert!(!single.is_port_valid(4001));
+    }
+
+    #[test]
+    fn send_denied_reasons() {
+        static IP_VIS: IpVisibilityCapability = IpVisibilityCapability { _priv: () };
+        static UDP_VIS: UdpVisibilityCapability = UdpVisibilityCapability { _priv: () };
+        let cap = NetworkCapability {
+            remote_addrs: AddrRange::Subnet(SUBNET, 64),
+            remote_ports: PortRange::Port(15000),
+            local_ports: PortRange::Port(14000),
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        };
+        assert_eq!(cap.check_remote_addr(SUBNET, &IP_VIS), Ok(()));
+        assert_eq!(
+            cap.check_remote_addr(with_byte(0, 0xfc), &IP_VIS),
+            Err(SendDenied::RemoteAddr)
+        );
+        assert_eq!(cap.check_ports(15000, 14000, &UDP_VIS), Ok(()));
+        assert_eq!(
+            cap.check_ports(15001, 14000, &UDP_VIS),
+            Err(SendDenied::RemotePort)
+        );
+        assert_eq!(
+            cap.check_ports(15000, 14001, &UDP_VIS),
+            Err(SendDenied::LocalPort)
+        );
+        assert_eq!(cap.check_local_addr(SUBNET, &IP_VIS), Ok(()));
+
+        let cap = NetworkCapability {
+            local_addrs: AddrRange::Addr(SUBNET),
+            ..cap
+        };
+        assert_eq!(
+            cap.check_local_addr(IPAddr::LOOPBACK, &IP_VIS),
+            Err(SendDenied::LocalAddr)
+        );
+
+        let cap = NetworkCapability {
+            remote_addrs: AddrRange::Any,
+            ..cap
+        };
+        assert_eq!(
+            cap.check_remote_addr(IPAddr([0xff; 16]), &IP_VIS),
+            Err(SendDenied::Broadcast)
+        );
+    }
+
+    #[test]
+    fn local_addr_restricted() {
+        static IP_VIS: IpVisibilityCapability = IpVisibilityCapability { _priv: () };
+        let cap = NetworkCapability {
+            remote_addrs: AddrRange::Any,
+            remote_ports: PortRange::Any,
+            local_ports: PortRange::Any,
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        };
+        assert!(cap.local_addr_valid(SUBNET, &IP_VIS));
+        assert!(cap.local_addr_valid(with_byte(0, 0xfe), &IP_VIS));
+
+        let cap = NetworkCapability {
+            local_addrs: AddrRange::Addr(SUBNET),
+            ..cap
+        };
+        assert!(cap.local_addr_valid(SUBNET, &IP_VIS));
+        assert!(!cap.local_addr_valid(with_byte(0, 0xfe), &IP_VIS));
+    }
+
+    #[test]
+    fn broadcast_allowed() {
+        static IP_VIS: IpVisibilityCapability = IpVisibilityCapability { _priv: () };
+        // Capsules cannot implement the creation capability, so build the
+        // capability like `new` does.
+        let cap = NetworkCapability {
+            remote_addrs: AddrRange::Any,
+            remote_ports: PortRange::Any,
+            local_ports: PortRange::Any,
+            local_addrs: AddrRange::Any,
+            allow_broadcast: false,
+        };
+        assert!(cap.remote_addr_valid(IPAddr([0xff; 16]), &IP_VIS));
+        assert!(!cap.broadcast_allowed(&IP_VIS));
+
+        let cap = NetworkCapability {
+            allow_broadcast: true,
+            ..cap
+        };
+        assert!(cap.broadcast_allowed(&IP_VIS));
+    }
 }
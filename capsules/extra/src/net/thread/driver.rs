@@ -711,7 +711,7 @@ impl<'a, A: time::Alarm<'a>> CCMClient for ThreadNetworkDriver<'a, A> {
                         self.driver_send_cap,
                         self.net_cap,
                     )
-                    .map_err(|buf| {
+                    .map_err(|(_, buf)| {
                         // if the sending fails prior to transmission, replace
                         // the buffer and pass error accordingly to terminate_child_join
                         // in following unwrap statement
@@ -258,7 +258,7 @@ impl<'a> UDPDriver<'a> {
                                     self.net_cap,
                                 ) {
                                     Ok(()) => Ok(()),
-                                    Err(mut buf) => {
+                                    Err((_, mut buf)) => {
                                         buf.reset();
                                         self.kernel_buffer.replace(buf);
                                         Err(ErrorCode::FAIL)
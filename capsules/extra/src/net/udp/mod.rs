@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 pub mod driver;
+pub mod udp_loopback;
 pub mod udp_port_table;
 pub mod udp_recv;
 pub mod udp_send;
@@ -0,0 +1,133 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Loopback path for the UDP send mux, for on-target testing without a
+//! second node.
+//!
+//! Once enabled on a `MuxUdpSender` with `MuxUdpSender::enable_loopback`,
+//! packets sent to the local address or to the IPv6 loopback address `::1`,
+//! and destined to a port that is bound on the local `MuxUdpReceiver`, are
+//! handed to the receiver directly instead of being passed to the IP layer.
+//! Packets are queued in the send mux like any other packet, and the sender
+//! gets its `send_done` callback once the packet has been delivered.
+//!
+//! Delivery happens from a deferred call, so the receiving client may send a
+//! reply from its `receive` callback. The payload is copied into a buffer
+//! owned by the loopback, as the sender's buffer is only lent to the send
+//! path for the duration of the call. Packets with a larger payload are
+//! rejected with `SIZE`.
+//!
+//! Enabling the loopback requires a `UdpLoopbackCapability`, so only trusted
+//! code can make a node accept packets that never went over the network.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let loopback = static_init!(
+//!     UdpLoopback<'static>,
+//!     UdpLoopback::new(udp_recv_mux, local_ip_ifaces[0], &mut LOOPBACK_BUF)
+//! );
+//! loopback.register();
+//! udp_send_mux.enable_loopback(loopback, &LOOPBACK_CAP);
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::IP6SendClient;
+use crate::net::udp::udp_recv::MuxUdpReceiver;
+use crate::net::udp::UDPHeader;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub struct UdpLoopback<'a> {
+    receiver: &'a MuxUdpReceiver<'a>,
+    local_addr: IPAddr,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    /// Destination, header and payload length of the packet in `buffer`
+    /// waiting for delivery.
+    pending: OptionalCell<(IPAddr, UDPHeader, usize)>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> UdpLoopback<'a> {
+    pub fn new(
+        receiver: &'a MuxUdpReceiver<'a>,
+        local_addr: IPAddr,
+        buffer: &'static mut [u8],
+    ) -> UdpLoopback<'a> {
+        UdpLoopback {
+            receiver,
+            local_addr,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            pending: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    /// The source address of the looped back packets.
+    pub fn local_addr(&self) -> IPAddr {
+        self.local_addr
+    }
+
+    /// Returns whether a packet to `dest` and `dst_port` should be looped
+    /// back rather than sent over the network.
+    pub fn accepts(&self, dest: IPAddr, dst_port: u16) -> bool {
+        (dest == self.local_addr || dest == IPAddr::LOOPBACK)
+            && self.receiver.is_port_bound(dst_port)
+    }
+
+    /// Queue a packet for delivery to the local receiver. The result of the
+    /// delivery is reported through `IP6SendClient::send_done`.
+    pub fn send(
+        &self,
+        dest: IPAddr,
+        udp_header: UDPHeader,
+        payload: &[u8],
+    ) -> Result<(), ErrorCode> {
+        if self.pending.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.buffer.map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer
+                .get_mut(..payload.len())
+                .ok_or(ErrorCode::SIZE)?
+                .copy_from_slice(payload);
+            Ok(())
+        })?;
+        self.pending.set((dest, udp_header, payload.len()));
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a> DeferredCallClient for UdpLoopback<'a> {
+    fn handle_deferred_call(&self) {
+        if let Some((dest, udp_header, len)) = self.pending.take() {
+            self.buffer.map(|buffer| {
+                self.receiver.deliver(
+                    self.local_addr,
+                    dest,
+                    udp_header.get_src_port(),
+                    udp_header.get_dst_port(),
+                    &buffer[..len],
+                );
+            });
+            self.client.map(|client| client.send_done(Ok(())));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -38,6 +38,7 @@
 
 use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 
+use core::cell::Cell;
 use core::fmt;
 
 use kernel::capabilities::{CreatePortTableCapability, UdpDriverCapability};
@@ -52,6 +53,12 @@ use kernel::ErrorCode;
 // is.
 pub const MAX_NUM_BOUND_PORTS: usize = 16;
 
+/// First port of the range `bind_ephemeral` picks ports from. This is the
+/// dynamic port range assigned by IANA (RFC 6335).
+pub const EPHEMERAL_PORT_FIRST: u16 = 49152;
+/// Last port of the range `bind_ephemeral` picks ports from.
+pub const EPHEMERAL_PORT_LAST: u16 = 65535;
+
 /// The SocketBindingEntry struct is stored in the PORT_TABLE and conveys what port is bound
 /// at the given index if one is bound. If no port is bound, the value stored
 /// at that location in the table is Unbound.
@@ -83,6 +90,8 @@ pub struct UdpPortManager {
     port_array: TakeCell<'static, [Option<SocketBindingEntry>]>,
     user_ports: OptionalCell<&'static dyn PortQuery>,
     udp_vis: &'static UdpVisibilityCapability,
+    /// Ephemeral port `bind_ephemeral` starts searching from.
+    next_ephemeral: Cell<u16>,
 }
 
 impl fmt::Debug for UdpPortManager {
@@ -155,6 +164,7 @@ impl UdpPortManager {
             port_array: TakeCell::new(used_kernel_ports),
             user_ports: OptionalCell::empty(),
             udp_vis,
+            next_ephemeral: Cell::new(EPHEMERAL_PORT_FIRST),
         }
     }
 
@@ -274,6 +284,99 @@ impl UdpPortManager {
         }
     }
 
+    /// Bind the socket to any free port in the ephemeral range
+    /// `EPHEMERAL_PORT_FIRST..=EPHEMERAL_PORT_LAST` which is allowed by
+    /// `net_cap`, for clients that don't need a particular source port.
+    ///
+    /// The search starts after the port picked by the previous call, so that
+    /// a port isn't reused right after being unbound while packets for its
+    /// previous user may still arrive. Fails if no such port is free,
+    /// returning the socket.
+    pub fn bind_ephemeral(
+        &self,
+        socket: UdpSocket,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(UdpPortBindingTx, UdpPortBindingRx), UdpSocket> {
+        let start = self.next_ephemeral.get();
+        let next = |port: u16| {
+            if port == EPHEMERAL_PORT_LAST {
+                EPHEMERAL_PORT_FIRST
+            } else {
+                port + 1
+            }
+        };
+        let mut port = start;
+        loop {
+            if net_cap.local_port_valid(port, self.udp_vis) && self.is_bound(port) == Ok(false) {
+                self.next_ephemeral.set(next(port));
+                return self.bind(socket, port, net_cap);
+            }
+            port = next(port);
+            if port == start {
+                return Err(socket);
+            }
+        }
+    }
+
+    /// Bind the `N` sockets passed to the `N` consecutive ports starting at
+    /// `first_port`, e.g. for a capsule handling several flows. The i-th
+    /// socket is bound to port `first_port + i`.
+    ///
+    /// Either all ports are bound or none: if any port of the range is
+    /// already bound, not allowed by `net_cap`, or beyond `u16::MAX`, all
+    /// sockets are returned unchanged.
+    pub fn bind_range<const N: usize>(
+        &self,
+        sockets: [UdpSocket; N],
+        first_port: u16,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<[(UdpPortBindingTx, UdpPortBindingRx); N], [UdpSocket; N]> {
+        if first_port as usize + N > u16::MAX as usize + 1 {
+            return Err(sockets);
+        }
+        // Check the whole range before binding anything
+        let ports = || (0..N).map(|i| first_port + i as u16);
+        for port in ports() {
+            if !net_cap.local_port_valid(port, self.udp_vis) || self.is_bound(port) != Ok(false) {
+                return Err(sockets);
+            }
+        }
+        let mut ports = ports();
+        Ok(sockets.map(|socket| {
+            // The iterator yields exactly N ports
+            let port = ports.next().unwrap_or(0);
+            self.port_array.map(|table| {
+                table[socket.idx] = Some(SocketBindingEntry::Port(port));
+            });
+            (
+                UdpPortBindingTx::new(socket.idx, port),
+                UdpPortBindingRx::new(socket.idx, port),
+            )
+        }))
+    }
+
+    /// Unbind all of the `bindings`, e.g. obtained from `bind_range`, and
+    /// return their sockets in the same order.
+    ///
+    /// Either all bindings are released or none: if the send and receive
+    /// binding of any pair don't belong together, all bindings are returned
+    /// unchanged.
+    pub fn unbind_all<const N: usize>(
+        &'static self,
+        bindings: [(UdpPortBindingTx, UdpPortBindingRx); N],
+    ) -> Result<[UdpSocket; N], [(UdpPortBindingTx, UdpPortBindingRx); N]> {
+        if bindings.iter().any(|(tx, rx)| tx.idx != rx.idx) {
+            return Err(bindings);
+        }
+        Ok(bindings.map(|(sender_binding, _)| {
+            let idx = sender_binding.idx;
+            self.port_array.map(|table| {
+                table[idx] = Some(SocketBindingEntry::Unbound);
+            });
+            UdpSocket::new(idx, self)
+        }))
+    }
+
     /// Disassociate the port from the given binding. Return the socket associated
     /// with the passed bindings. On Err, return the passed bindings.
     pub fn unbind(
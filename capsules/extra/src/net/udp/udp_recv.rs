@@ -40,6 +40,53 @@ impl<'a> MuxUdpReceiver<'a> {
     pub fn set_driver(&self, driver_ref: &'static UDPDriver) {
         self.driver.replace(driver_ref);
     }
+
+    /// Returns whether a kernel capsule or the userspace driver is bound to
+    /// `port`, i.e. whether a packet sent to `port` would be delivered.
+    pub fn is_port_bound(&self, port: u16) -> bool {
+        self.rcvr_list.iter().any(|rcvr| {
+            rcvr.binding
+                .map_or(false, |binding| binding.get_port() == port)
+        }) || self.driver.map_or(false, |driver| driver.is_bound(port))
+    }
+
+    /// Deliver the payload of a UDP packet to the receiver bound to
+    /// `dst_port`, if any.
+    pub fn deliver(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        for rcvr in self.rcvr_list.iter() {
+            match rcvr.binding.take() {
+                Some(binding) => {
+                    if binding.get_port() == dst_port {
+                        rcvr.client.map(|client| {
+                            client.receive(src_addr, dst_addr, src_port, dst_port, payload);
+                        });
+                        rcvr.binding.replace(binding);
+                        break;
+                    }
+                    rcvr.binding.replace(binding);
+                }
+                // The UDPReceiver used by the driver will not have a binding
+                None => match self.driver.take() {
+                    Some(driver) => {
+                        if driver.is_bound(dst_port) {
+                            driver.receive(src_addr, dst_addr, src_port, dst_port, payload);
+                            self.driver.replace(driver);
+                            break;
+                        }
+                        self.driver.replace(driver);
+                    }
+                    None => {}
+                },
+            }
+        }
+    }
 }
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
@@ -47,49 +94,17 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
-                let dst_port = udp_header.get_dst_port();
                 if len > payload.len() {
                     debug!("[UDP_RECV] Error: Received UDP length too long");
                     return;
                 }
-                for rcvr in self.rcvr_list.iter() {
-                    match rcvr.binding.take() {
-                        Some(binding) => {
-                            if binding.get_port() == dst_port {
-                                rcvr.client.map(|client| {
-                                    client.receive(
-                                        ip_header.get_src_addr(),
-                                        ip_header.get_dst_addr(),
-                                        udp_header.get_src_port(),
-                                        udp_header.get_dst_port(),
-                                        &payload[offset..],
-                                    );
-                                });
-                                rcvr.binding.replace(binding);
-                                break;
-                            }
-                            rcvr.binding.replace(binding);
-                        }
-                        // The UDPReceiver used by the driver will not have a binding
-                        None => match self.driver.take() {
-                            Some(driver) => {
-                                if driver.is_bound(dst_port) {
-                                    driver.receive(
-                                        ip_header.get_src_addr(),
-                                        ip_header.get_dst_addr(),
-                                        udp_header.get_src_port(),
-                                        udp_header.get_dst_port(),
-                                        &payload[offset..],
-                                    );
-                                    self.driver.replace(driver);
-                                    break;
-                                }
-                                self.driver.replace(driver);
-                            }
-                            None => {}
-                        },
-                    }
-                }
+                self.deliver(
+                    ip_header.get_src_addr(),
+                    ip_header.get_dst_addr(),
+                    udp_header.get_src_port(),
+                    udp_header.get_dst_port(),
+                    &payload[offset..],
+                );
             }
             None => {}
         }
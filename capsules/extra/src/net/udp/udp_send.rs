@@ -22,7 +22,11 @@
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
 use crate::net::ipv6::TransportHeader;
-use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
+use crate::net::network_capabilities::{
+    IpVisibilityCapability, NetworkCapability, SendDenied, UdpLoopbackCapability,
+    UdpVisibilityCapability,
+};
+use crate::net::udp::udp_loopback::UdpLoopback;
 use crate::net::udp::udp_port_table::UdpPortBindingTx;
 use crate::net::udp::UDPHeader;
 
@@ -35,18 +39,101 @@ use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::ErrorCode;
 
+/// Why a `UDPSender` refused to send a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The `NetworkCapability` passed with the packet doesn't permit the
+    /// send. `SendDenied` tells which of its ranges rejected it.
+    Denied(SendDenied),
+    /// Any other failure, e.g. the sender is not bound or already has a
+    /// packet outstanding, or the lower layer refused the packet.
+    Fail(ErrorCode),
+}
+
 pub struct MuxUdpSender<'a, T: IP6Sender<'a>> {
     sender_list: List<'a, UDPSendStruct<'a, T>>,
     ip_sender: &'a dyn IP6Sender<'a>,
+    loopback: OptionalCell<&'a UdpLoopback<'a>>,
+    ip_vis: &'static IpVisibilityCapability,
 }
 
 impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
-    pub fn new(ip6_sender: &'a dyn IP6Sender<'a>) -> MuxUdpSender<'a, T> {
+    pub fn new(
+        ip6_sender: &'a dyn IP6Sender<'a>,
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> MuxUdpSender<'a, T> {
         // similar to UdpSendStruct new()
         MuxUdpSender {
             sender_list: List::new(),
             ip_sender: ip6_sender,
+            loopback: OptionalCell::empty(),
+            ip_vis,
+        }
+    }
+
+    /// Deliver packets addressed to a locally bound port directly to the
+    /// local receiver through `loopback`, instead of sending them over the
+    /// network. See `udp_loopback.rs`.
+    pub fn enable_loopback(
+        &'a self,
+        loopback: &'a UdpLoopback<'a>,
+        _loopback_cap: &'static UdpLoopbackCapability,
+    ) {
+        loopback.set_client(self);
+        self.loopback.set(loopback);
+    }
+
+    // Returns the loopback and the UDP header of a packet that is looped
+    // back rather than passed to the IP layer.
+    fn loopback_for(
+        &self,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+    ) -> Option<(&'a UdpLoopback<'a>, UDPHeader)> {
+        match transport_header {
+            TransportHeader::UDP(udp_header) => self
+                .loopback
+                .get()
+                .filter(|loopback| loopback.accepts(dest, udp_header.get_dst_port()))
+                .map(|loopback| (loopback, udp_header)),
+            _ => None,
+        }
+    }
+
+    // Check that `net_cap` permits sending to `dest`, from the source
+    // address the packet gets on its route: the local address of the
+    // loopback, or the address of the IP layer.
+    fn check_send(
+        &self,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), SendDenied> {
+        let src = self.loopback_for(dest, transport_header).map_or_else(
+            || self.ip_sender.addr(),
+            |(loopback, _)| loopback.local_addr(),
+        );
+        net_cap.check_remote_addr(dest, self.ip_vis)?;
+        net_cap.check_local_addr(src, self.ip_vis)
+    }
+
+    // Pass a packet to the loopback if it is addressed to a local port, and
+    // to the IP layer otherwise, once `net_cap` has been checked. Both report
+    // completion through `send_done`.
+    fn transmit(
+        &self,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+        buf: &SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), SendError> {
+        self.check_send(dest, transport_header, net_cap)
+            .map_err(SendError::Denied)?;
+        match self.loopback_for(dest, transport_header) {
+            Some((loopback, udp_header)) => loopback.send(dest, udp_header, &buf[..]),
+            None => self.ip_sender.send_to(dest, transport_header, buf, net_cap),
         }
+        .map_err(SendError::Fail)
     }
 
     fn send_to(
@@ -55,7 +142,13 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
         transport_header: TransportHeader,
         caller: &'a UDPSendStruct<'a, T>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode> {
+    ) -> Result<(), SendError> {
+        // Check the addresses before queueing the packet, so that a queued
+        // packet is not dropped later for lack of permission, and the denial
+        // reaches the caller. `transmit` checks again when the packet leaves
+        // the queue.
+        self.check_send(dest, transport_header, net_cap)
+            .map_err(SendError::Denied)?;
         // Add this sender to the tail of the sender_list
         let list_empty = self.sender_list.head().is_none();
         self.add_client(caller);
@@ -65,15 +158,13 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
         if list_empty {
             ret = match caller.tx_buffer.take() {
                 Some(buf) => {
-                    let ret = self
-                        .ip_sender
-                        .send_to(dest, transport_header, &buf, net_cap);
+                    let ret = self.transmit(dest, transport_header, &buf, net_cap);
                     caller.tx_buffer.replace(buf); //Replace buffer as soon as sent.
                     ret
                 }
                 None => {
                     debug!("No buffer available to take.");
-                    Err(ErrorCode::FAIL)
+                    Err(SendError::Fail(ErrorCode::FAIL))
                 }
             }
         } else {
@@ -85,6 +176,13 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
     fn add_client(&self, sender: &'a UDPSendStruct<'a, T>) {
         self.sender_list.push_tail(sender);
     }
+
+    /// Returns the number of packets currently queued in the mux, including
+    /// the one being transmitted. A newly submitted packet will be sent after
+    /// all of these have completed.
+    pub fn queue_depth(&self) -> usize {
+        self.sender_list.iter().count()
+    }
 }
 
 /// This function implements the `IP6SendClient` trait for the `UDPSendStruct`,
@@ -116,17 +214,13 @@ impl<'a, T: IP6Sender<'a>> IP6SendClient for MuxUdpSender<'a, T> {
                     Some(buf) => match next_sender.next_th.take() {
                         Some(th) => match next_sender.net_cap.take() {
                             Some(net_cap) => {
-                                let ret = self.ip_sender.send_to(
-                                    next_sender.next_dest.get(),
-                                    th,
-                                    &buf,
-                                    net_cap,
-                                );
+                                let ret =
+                                    self.transmit(next_sender.next_dest.get(), th, &buf, net_cap);
                                 next_sender.tx_buffer.replace(buf);
                                 if ret != Ok(()) {
                                     debug!("IP send_to failed: {:?}", ret);
                                 }
-                                ret
+                                ret.map_err(|_| ErrorCode::FAIL)
                             }
                             None => Err(ErrorCode::FAIL),
                         },
@@ -180,8 +274,9 @@ pub trait UDPSender<'a> {
     /// `binding` - type that specifies what port the sender is bound to.
     ///
     /// # Return Value
-    /// Any synchronous errors are returned via the returned `Result<(), ErrorCode>`
-    /// value; asynchronous errors are delivered via the callback.
+    /// Any synchronous errors are returned together with the buffer, with
+    /// `SendError::Denied` if `net_cap` doesn't permit the send;
+    /// asynchronous errors are delivered via the callback.
     fn send_to(
         &'a self,
         dest: IPAddr,
@@ -189,7 +284,7 @@ pub trait UDPSender<'a> {
         //src_port: u16,
         buf: SubSliceMut<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>>;
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)>;
 
     /// This function is identical to `send_to()` except that it takes in
     /// an explicit src_port instead of a binding. This allows it to be used
@@ -202,8 +297,9 @@ pub trait UDPSender<'a> {
     /// `buf` - UDP payload
     ///
     /// # Return Value
-    /// Any synchronous errors are returned via the returned `Result<(), ErrorCode>`
-    /// value; asynchronous errors are delivered via the callback.
+    /// Any synchronous errors are returned together with the buffer, with
+    /// `SendError::Denied` if `net_cap` doesn't permit the send;
+    /// asynchronous errors are delivered via the callback.
     fn driver_send_to(
         &'a self,
         dest: IPAddr,
@@ -212,7 +308,7 @@ pub trait UDPSender<'a> {
         buf: SubSliceMut<'static, u8>,
         driver_send_cap: &dyn UdpDriverCapability,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>>;
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)>;
 
     /// This function constructs an IP packet from the completed `UDPHeader`
     /// and buffer, and sends it to the provided IP address
@@ -223,20 +319,31 @@ pub trait UDPSender<'a> {
     /// `buf` - A byte array containing the UDP payload
     ///
     /// # Return Value
-    /// Returns any synchronous errors or success. Note that any asynchrounous
-    /// errors are returned via the callback.
+    /// Returns any synchronous errors, together with the buffer, or success.
+    /// Note that any asynchrounous errors are returned via the callback.
     fn send(
         &'a self,
         dest: IPAddr,
         udp_header: UDPHeader,
         buf: SubSliceMut<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>>;
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)>;
 
     fn get_binding(&self) -> Option<UdpPortBindingTx>;
 
     fn is_bound(&self) -> bool;
 
+    /// Returns whether this sender can accept a new packet, i.e. it has no
+    /// packet outstanding. Each sender may only have a single packet queued
+    /// or in transmission at a time, so sends should only be issued while
+    /// this returns `true`.
+    fn is_ready(&self) -> bool;
+
+    /// Returns the number of packets, from all senders, that are queued or in
+    /// transmission in the underlying send mux. A packet sent while this is
+    /// nonzero is queued behind them rather than transmitted immediately.
+    fn queue_depth(&self) -> usize;
+
     fn set_binding(&self, binding: UdpPortBindingTx) -> Option<UdpPortBindingTx>;
 }
 
@@ -274,26 +381,25 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         dst_port: u16,
         buf: SubSliceMut<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>> {
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)> {
         let mut udp_header = UDPHeader::new();
         udp_header.set_dst_port(dst_port);
         match self.binding.take() {
             Some(binding) => {
-                if !net_cap.remote_port_valid(dst_port, self.udp_vis)
-                    || !net_cap.local_port_valid(binding.get_port(), self.udp_vis)
+                if let Err(denied) = net_cap.check_ports(dst_port, binding.get_port(), self.udp_vis)
                 {
                     self.binding.replace(binding);
-                    Err(buf)
+                    Err((SendError::Denied(denied), buf))
                 } else if binding.get_port() == 0 {
                     self.binding.replace(binding);
-                    Err(buf)
+                    Err((SendError::Fail(ErrorCode::FAIL), buf))
                 } else {
                     udp_header.set_src_port(binding.get_port());
                     self.binding.replace(binding);
                     self.send(dest, udp_header, buf, net_cap)
                 }
             }
-            None => Err(buf),
+            None => Err((SendError::Fail(ErrorCode::FAIL), buf)),
         }
     }
 
@@ -306,7 +412,7 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         buf: SubSliceMut<'static, u8>,
         _driver_send_cap: &dyn UdpDriverCapability,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>> {
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)> {
         let mut udp_header = UDPHeader::new();
         udp_header.set_dst_port(dst_port);
         udp_header.set_src_port(src_port);
@@ -319,7 +425,11 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         mut udp_header: UDPHeader,
         buf: SubSliceMut<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), SubSliceMut<'static, u8>> {
+    ) -> Result<(), (SendError, SubSliceMut<'static, u8>)> {
+        if !self.is_ready() {
+            // Only a single outstanding packet per sender is supported.
+            return Err((SendError::Fail(ErrorCode::BUSY), buf));
+        }
         udp_header.set_len((buf.len() + udp_header.get_hdr_size()) as u16);
         let transport_header = TransportHeader::UDP(udp_header);
         self.tx_buffer.replace(buf);
@@ -330,7 +440,7 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
             .send_to(dest, transport_header, self, net_cap)
         {
             Ok(()) => Ok(()),
-            _ => Err(self.tx_buffer.take().unwrap()),
+            Err(error) => Err((error, self.tx_buffer.take().unwrap())),
         }
     }
 
@@ -342,6 +452,16 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         self.binding.is_some()
     }
 
+    fn is_ready(&self) -> bool {
+        // The buffer is held from the time a packet is submitted until it is
+        // returned to the client in `send_done`.
+        self.tx_buffer.is_none()
+    }
+
+    fn queue_depth(&self) -> usize {
+        self.udp_mux_sender.queue_depth()
+    }
+
     fn set_binding(&self, binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
         self.binding.replace(binding)
     }
@@ -0,0 +1,135 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! GPIO interrupts emulated by polling, for pins without interrupt hardware.
+//!
+//! `PolledInterruptPin` implements the `gpio::Interrupt` HIL on top of a
+//! plain `gpio::Input` pin: while interrupts are enabled, it samples the pin
+//! every poll interval and calls the client's `fired` when the level changed
+//! in the direction selected by the `InterruptEdge`. Capsules that expect an
+//! interrupt pin, like the button capsule, can then run unmodified on pins
+//! that can't generate interrupts.
+//!
+//! This trades latency and power for compatibility: an edge is only noticed
+//! at the next poll, and the alarm wakes the chip at every interval while
+//! interrupts are enabled. Pulses shorter than the poll interval may be
+//! missed entirely, as the pin may be back at its previous level by the time
+//! it is sampled, and several edges between two polls are reported as at
+//! most one. The interval should therefore be short compared to the pulses
+//! to detect, e.g. 10 to 20ms for a push button.
+//!
+//! The alarm must be dedicated to this pin, typically a `VirtualMuxAlarm`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let poll_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, msp432::timer::TimerA<'static>>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! poll_alarm.setup();
+//! let button_pin = static_init!(
+//!     PolledInterruptPin<'static, msp432::gpio::Pin, VirtualMuxAlarm<'static, msp432::timer::TimerA>>,
+//!     PolledInterruptPin::new(&peripherals.gpio.pins[PinNr::P07_0 as usize], poll_alarm,
+//!                             poll_alarm.ticks_from_ms(20))
+//! );
+//! poll_alarm.set_alarm_client(button_pin);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Alarm};
+use kernel::utilities::cells::OptionalCell;
+
+pub struct PolledInterruptPin<'a, I: gpio::Input, A: Alarm<'a>> {
+    pin: &'a I,
+    alarm: &'a A,
+    /// Time between two samples of the pin.
+    interval: Cell<A::Ticks>,
+    /// Edge to report, `None` while interrupts are disabled.
+    mode: OptionalCell<gpio::InterruptEdge>,
+    /// Level of the pin at the previous sample.
+    level: Cell<bool>,
+    client: OptionalCell<&'a dyn gpio::Client>,
+}
+
+impl<'a, I: gpio::Input, A: Alarm<'a>> PolledInterruptPin<'a, I, A> {
+    pub fn new(pin: &'a I, alarm: &'a A, interval: A::Ticks) -> Self {
+        Self {
+            pin,
+            alarm,
+            interval: Cell::new(interval),
+            mode: OptionalCell::empty(),
+            level: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Change the time between two samples of the pin. This takes effect
+    /// after the next sample if interrupts are enabled.
+    pub fn set_interval(&self, interval: A::Ticks) {
+        self.interval.set(interval);
+    }
+}
+
+impl<'a, I: gpio::Input, A: Alarm<'a>> gpio::Input for PolledInterruptPin<'a, I, A> {
+    fn read(&self) -> bool {
+        self.pin.read()
+    }
+}
+
+impl<'a, I: gpio::Input, A: Alarm<'a>> gpio::Interrupt<'a> for PolledInterruptPin<'a, I, A> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.client.set(client);
+    }
+
+    /// Start polling the pin. Edges are detected relative to the level of
+    /// the pin when this is called.
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.level.set(self.pin.read());
+        self.mode.set(mode);
+        if !self.alarm.is_armed() {
+            self.alarm.set_alarm(self.alarm.now(), self.interval.get());
+        }
+    }
+
+    fn disable_interrupts(&self) {
+        self.mode.clear();
+        let _ = self.alarm.disarm();
+    }
+
+    /// Edges are reported as soon as they are sampled, so an interrupt is
+    /// never pending.
+    fn is_pending(&self) -> bool {
+        false
+    }
+
+    fn is_interrupt_enabled(&self) -> bool {
+        self.mode.is_some()
+    }
+}
+
+impl<'a, I: gpio::Input, A: Alarm<'a>> time::AlarmClient for PolledInterruptPin<'a, I, A> {
+    fn alarm(&self) {
+        if let Some(mode) = self.mode.get() {
+            let level = self.pin.read();
+            let previous = self.level.replace(level);
+            let fired = match mode {
+                gpio::InterruptEdge::RisingEdge => !previous && level,
+                gpio::InterruptEdge::FallingEdge => previous && !level,
+                gpio::InterruptEdge::EitherEdge => previous != level,
+            };
+            // Schedule the next sample before calling the client, which may
+            // disable the interrupts from its callback.
+            self.alarm.set_alarm(self.alarm.now(), self.interval.get());
+            if fired {
+                self.client.map(|client| client.fired());
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Reset pulse on a GPIO line, for peripherals with a reset pin.
+//!
+//! `ResetLine` asserts a pin, waits for the configured pulse width with an
+//! alarm, deasserts the pin and then calls the client's `reset_done`. The
+//! line can be active high or active low, as selected by the
+//! `ActivationMode` passed to `new`.
+//!
+//! The pulse is at least as wide as configured: the alarm is set one tick
+//! longer than the width, since the line may be asserted at the very end of
+//! a tick, and a late alarm, e.g. because of other interrupts, only makes
+//! the pulse longer.
+//!
+//! The board should drive the pin inactive before the first reset, e.g.
+//! with `Configure::make_output_with_level`. The alarm must be dedicated to
+//! this line, typically a `VirtualMuxAlarm`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let reset_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! reset_alarm.setup();
+//! let reset_line = static_init!(
+//!     ResetLine<'static, sam4l::gpio::GPIOPin, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     ResetLine::new(&sam4l::gpio::PA[14], reset_alarm,
+//!                    gpio::ActivationMode::ActiveLow, reset_alarm.ticks_from_us(100))
+//! );
+//! reset_alarm.set_alarm_client(reset_line);
+//! reset_line.set_client(sensor);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Notified when a reset pulse completes.
+pub trait ResetLineClient {
+    /// Called once the line has been deasserted.
+    fn reset_done(&self);
+}
+
+pub struct ResetLine<'a, P: gpio::Output, A: Alarm<'a>> {
+    pin: &'a P,
+    alarm: &'a A,
+    mode: gpio::ActivationMode,
+    /// Minimum time the line is held asserted.
+    width: Cell<A::Ticks>,
+    /// Whether the line is asserted, waiting for the alarm.
+    resetting: Cell<bool>,
+    client: OptionalCell<&'a dyn ResetLineClient>,
+}
+
+impl<'a, P: gpio::Output, A: Alarm<'a>> ResetLine<'a, P, A> {
+    pub fn new(pin: &'a P, alarm: &'a A, mode: gpio::ActivationMode, width: A::Ticks) -> Self {
+        Self {
+            pin,
+            alarm,
+            mode,
+            width: Cell::new(width),
+            resetting: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ResetLineClient) {
+        self.client.set(client);
+    }
+
+    /// Change the minimum width of the pulse. This takes effect at the next
+    /// reset.
+    pub fn set_pulse_width(&self, width: A::Ticks) {
+        self.width.set(width);
+    }
+
+    /// Assert the line, and deassert it once the pulse width has elapsed,
+    /// calling `reset_done`. Fails with `BUSY` if a reset is in progress.
+    pub fn reset(&self) -> Result<(), ErrorCode> {
+        if self.resetting.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.resetting.set(true);
+        self.pin
+            .write_activation(gpio::ActivationState::Active, self.mode);
+        // The line was asserted before `now`, but possibly at the end of the
+        // previous tick, so wait one more tick.
+        let dt = self.width.get().wrapping_add(A::Ticks::from(1));
+        self.alarm.set_alarm(self.alarm.now(), dt);
+        Ok(())
+    }
+
+    pub fn is_resetting(&self) -> bool {
+        self.resetting.get()
+    }
+}
+
+impl<'a, P: gpio::Output, A: Alarm<'a>> time::AlarmClient for ResetLine<'a, P, A> {
+    fn alarm(&self) {
+        if self.resetting.get() {
+            self.pin
+                .write_activation(gpio::ActivationState::Inactive, self.mode);
+            // Clear the reset before the callback, so that the client can
+            // start another one from `reset_done`.
+            self.resetting.set(false);
+            self.client.map(|client| client.reset_done());
+        }
+    }
+}
@@ -17,7 +17,7 @@ use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use core::cell::Cell;
 
 use kernel::debug;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
 use kernel::utilities::cells::MapCell;
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::ErrorCode;
@@ -31,6 +31,46 @@ pub const DST_ADDR: IPAddr = IPAddr([
 pub const PAYLOAD_LEN: usize = 192;
 pub const SEND_INTERVAL_SECONDS: u32 = 5;
 
+/// Contents of the payload bytes following the 2 byte value sent by
+/// `MockUdp::send`.
+///
+/// The byte at payload offset `i` only depends on `i`, so a receiver can
+/// verify a packet byte-for-byte without any shared state, regardless of how
+/// the packet was fragmented on the way.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PayloadPattern {
+    /// Every byte is 0.
+    Zeros,
+    /// Byte `i` is `i` truncated to 8 bits.
+    Incrementing,
+    /// Byte `i` is a hash of `i` and the seed.
+    PseudoRandom(u8),
+}
+
+impl PayloadPattern {
+    /// The byte this pattern places at payload offset `index`.
+    pub fn byte(&self, index: usize) -> u8 {
+        match *self {
+            PayloadPattern::Zeros => 0,
+            PayloadPattern::Incrementing => index as u8,
+            PayloadPattern::PseudoRandom(seed) => {
+                ((index as u32 ^ seed as u32).wrapping_mul(0x9E37_79B1) >> 24) as u8
+            }
+        }
+    }
+
+    /// Check `payload` against this pattern, skipping the leading 2 byte
+    /// value. Returns the offset of the first mismatching byte on failure.
+    pub fn verify(&self, payload: &[u8]) -> Result<(), usize> {
+        payload
+            .iter()
+            .enumerate()
+            .skip(2)
+            .find(|(i, b)| **b != self.byte(*i))
+            .map_or(Ok(()), |(i, _)| Err(i))
+    }
+}
+
 pub struct MockUdp<'a, A: Alarm<'a>> {
     id: u16,
     pub alarm: &'a A,
@@ -39,9 +79,15 @@ pub struct MockUdp<'a, A: Alarm<'a>> {
     port_table: &'static UdpPortManager,
     udp_dgram: MapCell<SubSliceMut<'static, u8>>,
     src_port: Cell<u16>,
+    dst_addr: Cell<IPAddr>,
     dst_port: Cell<u16>,
     send_loop: Cell<bool>,
     net_cap: Cell<&'static NetworkCapability>,
+    send_done_count: Cell<usize>,
+    payload_pattern: Cell<PayloadPattern>,
+    payload_len: Cell<usize>,
+    verify_rx_payload: Cell<bool>,
+    verify_rx_len: Cell<bool>,
 }
 
 impl<'a, A: Alarm<'a>> MockUdp<'a, A> {
@@ -63,9 +109,15 @@ impl<'a, A: Alarm<'a>> MockUdp<'a, A> {
             port_table,
             udp_dgram: MapCell::new(udp_dgram),
             src_port: Cell::new(0), // invalid initial value
+            dst_addr: Cell::new(DST_ADDR),
             dst_port: Cell::new(dst_port),
             send_loop: Cell::new(false),
             net_cap: Cell::new(net_cap),
+            send_done_count: Cell::new(0),
+            payload_pattern: Cell::new(PayloadPattern::Zeros),
+            payload_len: Cell::new(2),
+            verify_rx_payload: Cell::new(false),
+            verify_rx_len: Cell::new(false),
         }
     }
 
@@ -73,9 +125,22 @@ impl<'a, A: Alarm<'a>> MockUdp<'a, A> {
     pub fn start_sending(&self) {
         // Set alarm bc if you try to send immediately there are initialization issues
         self.send_loop.set(true);
-        let delay = <A::Frequency>::frequency() * SEND_INTERVAL_SECONDS;
-        self.alarm
-            .set_alarm(self.alarm.now(), A::Ticks::from(delay));
+        let delay = self.alarm.ticks_from_seconds(SEND_INTERVAL_SECONDS);
+        self.alarm.set_alarm(self.alarm.now(), delay);
+    }
+
+    // Sets the pattern and the total length of the payloads sent from now on.
+    // The length is capped to the size of the datagram buffer, and the first
+    // 2 bytes always hold the value passed to `send`.
+    pub fn set_payload_pattern(&self, pattern: PayloadPattern, len: usize) {
+        self.payload_pattern.set(pattern);
+        self.payload_len.set(len.max(2));
+    }
+
+    // When enabled, received payloads are checked against the configured
+    // payload pattern and any mismatch is reported.
+    pub fn set_verify_rx_payload(&self, verify: bool) {
+        self.verify_rx_payload.set(verify);
     }
 
     pub fn update_capability(&self, new_cap: &'static NetworkCapability) {
@@ -151,27 +216,64 @@ impl<'a, A: Alarm<'a>> MockUdp<'a, A> {
         }
     }
 
+    // Whether the underlying sender can accept a new packet.
+    pub fn is_ready(&self) -> bool {
+        self.udp_sender.is_ready()
+    }
+
+    // Number of packets queued in the UDP send mux.
+    pub fn queue_depth(&self) -> usize {
+        self.udp_sender.queue_depth()
+    }
+
+    // Number of `send_done` callbacks received so far, so that tests can
+    // check that every accepted send eventually completed.
+    pub fn send_done_count(&self) -> usize {
+        self.send_done_count.get()
+    }
+
+    // When enabled, the 2 byte value at the start of received payloads is
+    // taken as the length the packet was sent with, and a packet of any other
+    // length is reported. Senders enable this by passing the payload length
+    // to `send`.
+    pub fn set_verify_rx_len(&self, verify: bool) {
+        self.verify_rx_len.set(verify);
+    }
+
     pub fn set_dst(&self, dst_port: u16) {
         self.dst_port.set(dst_port);
     }
 
-    // Sends a packet containing a single 2 byte number.
+    // Sets the address packets are sent to, `DST_ADDR` by default.
+    pub fn set_dst_addr(&self, dst_addr: IPAddr) {
+        self.dst_addr.set(dst_addr);
+    }
+
+    // Sends a packet containing a 2 byte number, followed by the configured
+    // payload pattern.
     pub fn send(&self, value: u16) -> Result<(), ErrorCode> {
         match self.udp_dgram.take() {
             Some(mut dgram) => {
+                let len = self.payload_len.get().min(dgram.len());
+                let pattern = self.payload_pattern.get();
                 dgram[0] = (value >> 8) as u8;
                 dgram[1] = (value & 0x00ff) as u8;
-                dgram.slice(0..2);
+                for i in 2..len {
+                    dgram[i] = pattern.byte(i);
+                }
+                dgram.slice(0..len);
                 match self.udp_sender.send_to(
-                    DST_ADDR,
+                    self.dst_addr.get(),
                     self.dst_port.get(),
                     dgram,
                     self.net_cap.get(),
                 ) {
                     Ok(()) => Ok(()),
-                    Err(mut buf) => {
+                    Err((error, mut buf)) => {
                         buf.reset();
                         self.udp_dgram.replace(buf);
+                        debug!("[MOCK_UDP {:?}] Send rejected: {:?}", self.id, error);
+                        self.net_cap.get().debug_dump();
                         Err(ErrorCode::RESERVE)
                     }
                 }
@@ -195,12 +297,12 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MockUdp<'a, A> {
 impl<'a, A: Alarm<'a>> UDPSendClient for MockUdp<'a, A> {
     fn send_done(&self, result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
         debug!("Mock UDP done sending. Result: {:?}", result);
+        self.send_done_count.set(self.send_done_count.get() + 1);
         dgram.reset();
         self.udp_dgram.replace(dgram);
         debug!("");
-        let delay = <A::Frequency>::frequency() * SEND_INTERVAL_SECONDS;
-        self.alarm
-            .set_alarm(self.alarm.now(), A::Ticks::from(delay));
+        let delay = self.alarm.ticks_from_seconds(SEND_INTERVAL_SECONDS);
+        self.alarm.set_alarm(self.alarm.now(), delay);
     }
 }
 
@@ -217,5 +319,32 @@ impl<'a, A: Alarm<'a>> UDPRecvClient for MockUdp<'a, A> {
             "[MOCK_UDP {:?}] Received packet from {:?}:{:?}, contents: {:?}\n",
             self.id, src_addr, src_port, payload
         );
+        if self.verify_rx_payload.get() {
+            match self.payload_pattern.get().verify(payload) {
+                Ok(()) => debug!(
+                    "[MOCK_UDP {:?}] Payload of {} bytes matches pattern",
+                    self.id,
+                    payload.len()
+                ),
+                Err(offset) => debug!(
+                    "[MOCK_UDP {:?}] TEST FAIL: payload mismatch at byte {}",
+                    self.id, offset
+                ),
+            }
+        }
+        if self.verify_rx_len.get() {
+            let sent_len = match payload {
+                [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]) as usize,
+                _ => 0,
+            };
+            if payload.len() != sent_len {
+                debug!(
+                    "[MOCK_UDP {:?}] TEST FAIL: received {} bytes of a {} byte payload",
+                    self.id,
+                    payload.len(),
+                    sent_len
+                );
+            }
+        }
     }
 }
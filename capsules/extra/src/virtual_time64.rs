@@ -0,0 +1,120 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Extend an alarm to a monotonic 64-bit time.
+//!
+//! `VirtualTime64` counts the wraparounds of the counter of an underlying
+//! `Alarm` to provide a 64-bit tick count that does not wrap in practice. To
+//! notice every wraparound, it keeps the alarm armed to fire every half of the
+//! counter range and compares consecutive readings of `now()`: a reading that
+//! is smaller than the previous one means the counter wrapped in between.
+//! Delayed or missed alarm callbacks are therefore harmless, as long as two
+//! consecutive readings are less than a full counter range apart.
+//!
+//! If the alarm callback fires more than half of the counter range late, the
+//! two readings around it may be more than a full range apart, and wraparounds
+//! can be lost. This cannot be corrected from the counter alone, so it is
+//! reported through `lost_sync()`.
+//!
+//! The underlying alarm should be a `VirtualMuxAlarm`, so the alarm can still
+//! be shared with other capsules. Its ticks must be at most 32 bits wide.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let time64_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! time64_alarm.setup();
+//! let time64 = static_init!(
+//!     VirtualTime64<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     VirtualTime64::new(time64_alarm)
+//! );
+//! time64_alarm.set_alarm_client(time64);
+//! time64.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, Alarm, Ticks, Ticks64, Time};
+
+pub struct VirtualTime64<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// Number of times the counter of `alarm` wrapped around.
+    wraps: Cell<u32>,
+    /// The most recent reading of the counter of `alarm`.
+    last_now: Cell<A::Ticks>,
+    /// Whether wraparounds may have been missed.
+    lost_sync: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> VirtualTime64<'a, A> {
+    pub fn new(alarm: &'a A) -> VirtualTime64<'a, A> {
+        VirtualTime64 {
+            alarm,
+            wraps: Cell::new(0),
+            last_now: Cell::new(A::Ticks::from(0)),
+            lost_sync: Cell::new(false),
+        }
+    }
+
+    /// Start tracking wraparounds of the underlying counter. The 64-bit time
+    /// starts at the current value of the counter.
+    pub fn start(&self) {
+        let now = self.alarm.now();
+        self.last_now.set(now);
+        self.alarm.set_alarm(now, A::Ticks::half_max_value());
+    }
+
+    /// Returns the current time as a 64-bit tick count.
+    pub fn now64(&self) -> u64 {
+        let now = self.update();
+        ((self.wraps.get() as u64) << A::Ticks::width()) | now.into_u32() as u64
+    }
+
+    /// Returns whether the alarm callback was delayed by so much that
+    /// wraparounds of the underlying counter may have been missed, in which
+    /// case `now64()` may lag behind by a multiple of the counter range.
+    pub fn lost_sync(&self) -> bool {
+        self.lost_sync.get()
+    }
+
+    // Read the underlying counter, counting a wraparound if it went
+    // backwards since the last reading.
+    fn update(&self) -> A::Ticks {
+        let now = self.alarm.now();
+        if now < self.last_now.get() {
+            self.wraps.set(self.wraps.get().wrapping_add(1));
+        }
+        self.last_now.set(now);
+        now
+    }
+}
+
+impl<'a, A: Alarm<'a>> Time for VirtualTime64<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = Ticks64;
+
+    fn now(&self) -> Ticks64 {
+        Ticks64::from(self.now64())
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for VirtualTime64<'a, A> {
+    fn alarm(&self) {
+        let expiration = self.alarm.get_alarm();
+        let now = self.update();
+        // The previous reading was taken at the latest when the alarm was
+        // armed, half a range before `expiration`. Firing more than another
+        // half range late means the readings may be a full range apart.
+        if now.wrapping_sub(expiration) > A::Ticks::half_max_value() {
+            self.lost_sync.set(true);
+        }
+        self.alarm.set_alarm(now, A::Ticks::half_max_value());
+    }
+}
@@ -0,0 +1,34 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Read-modify-write of registers shared by several pins or drivers.
+//!
+//! Many msp432 registers hold the configuration of several independent
+//! users, e.g. `PxOUT` holds the output level of all eight pins of a port.
+//! Lacking set/clear registers, changing the bits of one pin needs a
+//! read-modify-write. If an interrupt handler changes another pin of the same
+//! port between the read and the write, its change is lost.
+//!
+//! `modify_atomic` performs the read-modify-write with interrupts disabled.
+//! This delays all interrupts by the few instructions it takes, so it should
+//! only be used for registers that are actually shared. Registers owned by a
+//! single driver can use the plain `modify` of the register interface.
+
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+
+/// Replace the value of `reg` with `f` applied to its current value, with
+/// interrupts disabled, and return the new value.
+pub fn modify_atomic<REG, T>(reg: &REG, f: impl FnOnce(T) -> T) -> T
+where
+    REG: Readable<T = T> + Writeable<T = T>,
+    T: Copy,
+{
+    unsafe {
+        cortexm4::support::atomic(|| {
+            let val = f(reg.get());
+            reg.set(val);
+            val
+        })
+    }
+}
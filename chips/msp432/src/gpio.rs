@@ -11,6 +11,9 @@ use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadOnly, ReadWrite};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+use crate::atomic::modify_atomic;
 
 const GPIO_BASES: [StaticRef<GpioRegisters>; 6] = [
     unsafe { StaticRef::new(0x4000_4C00u32 as *const GpioRegisters) }, // PORT 1&2
@@ -200,6 +203,31 @@ pub enum PinNr {
     PJ_0,  PJ_1,  PJ_2,  PJ_3,  PJ_4,  PJ_5,  PJ_6,  PJ_7,
 }
 
+impl IntPinNr {
+    /// Index of the port the pin belongs to, starting with 0 for P1.
+    pub const fn port(self) -> u8 {
+        (self as u8) / PINS_PER_PORT
+    }
+
+    /// Bit position of the pin within its port register.
+    pub const fn bit(self) -> u8 {
+        (self as u8) % PINS_PER_PORT
+    }
+}
+
+impl PinNr {
+    /// Index of the port the pin belongs to, starting with 0 for P1. Since
+    /// `PinNr` starts at P7, the returned value is 6 for P7 and 10 for PJ.
+    pub const fn port(self) -> u8 {
+        (self as u8) / PINS_PER_PORT + 6
+    }
+
+    /// Bit position of the pin within its port register.
+    pub const fn bit(self) -> u8 {
+        (self as u8) % PINS_PER_PORT
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum ModuleFunction {
     Gpio,
@@ -319,6 +347,7 @@ pub struct IntPin<'a> {
     reg_idx: usize,
     detect_both_edges: Cell<bool>,
     client: OptionalCell<&'a dyn gpio::Client>,
+    level_client: OptionalCell<&'a dyn gpio::ClientWithLevel>,
 }
 
 /// Does not support interrupts
@@ -333,27 +362,42 @@ pub struct Pin<'a> {
 
 impl<'a> Pin<'a> {
     const fn new(pin: PinNr) -> Pin<'a> {
-        let pin_nr = (pin as u8) % PINS_PER_PORT;
-        let p = (pin as u8) / PINS_PER_PORT;
+        let p = pin.port();
         Pin {
-            pin: pin_nr,
-            registers: GPIO_BASES[3 + ((p / 2) as usize)],
+            pin: pin.bit(),
+            registers: GPIO_BASES[(p / 2) as usize],
             reg_idx: (p % 2) as usize,
             phantom: PhantomData,
         }
     }
+
+    /// Create a pin from a raw port and bit number, for pins that are not
+    /// available through `GpioManager`. `port` is numbered like
+    /// `PinNr::port()`, i.e. 0 for P1 up to 9 for P10, and 10 for PJ.
+    ///
+    /// Prefer using the pins of `GpioManager` where possible, as this doesn't
+    /// prevent multiple handles to the same pin.
+    pub const fn from_port_bit(port: u8, bit: u8) -> Pin<'a> {
+        debug_assert!(port <= 10 && bit < PINS_PER_PORT);
+        Pin {
+            pin: bit,
+            registers: GPIO_BASES[(port / 2) as usize],
+            reg_idx: (port % 2) as usize,
+            phantom: PhantomData,
+        }
+    }
 }
 
 impl<'a> IntPin<'a> {
     pub const fn new(pin: IntPinNr) -> IntPin<'a> {
-        let pin_nr = (pin as u8) % PINS_PER_PORT;
-        let p = (pin as u8) / PINS_PER_PORT;
+        let p = pin.port();
         IntPin {
-            pin: pin_nr,
+            pin: pin.bit(),
             registers: GPIO_BASES[(p / 2) as usize],
             reg_idx: (p % 2) as usize,
             detect_both_edges: Cell::new(false),
             client: OptionalCell::empty(),
+            level_client: OptionalCell::empty(),
         }
     }
 
@@ -374,7 +418,12 @@ impl<'a> IntPin<'a> {
     }
 
     fn handle_interrupt(&self) {
+        // Sample PxIN before anything else, in particular before the edge
+        // detection is switched below.
+        let level = self.read_level();
+
         self.client.map(|client| client.fired());
+        self.level_client.map(|client| client.fired(level));
 
         if self.detect_both_edges.get() {
             self.switch_detecting_edge();
@@ -389,6 +438,12 @@ macro_rules! pin_implementation {
                 (self.registers.input[self.reg_idx].get() & (1 << self.pin)) > 0
             }
 
+            // PxOUT is shared by all pins of a port and has no set/clear
+            // registers, so updating one pin is a read-modify-write.
+            fn modify_out(&self, f: impl FnOnce(u8) -> u8) -> u8 {
+                modify_atomic(&self.registers.out[self.reg_idx], f)
+            }
+
             fn enable_module_function(&self, mode: ModuleFunction) {
                 let mut sel0 = self.registers.sel0[self.reg_idx].get();
                 let mut sel1 = self.registers.sel1[self.reg_idx].get();
@@ -427,6 +482,38 @@ macro_rules! pin_implementation {
             pub fn enable_tertiary_function(&self) {
                 self.enable_module_function(ModuleFunction::Tertiary);
             }
+
+            /// Force the pin into a known state: a GPIO input without pull
+            /// resistor, regardless of the function it was configured for.
+            ///
+            /// The port registers are not reset by a software reboot, so a
+            /// pin may still be muxed to a peripheral by the previous run.
+            pub fn reset_to_gpio_input(&self) {
+                let bit = 1 << self.pin;
+                let sel0 = self.registers.sel0[self.reg_idx].get() & bit;
+                let sel1 = self.registers.sel1[self.reg_idx].get() & bit;
+                if sel0 != 0 && sel1 != 0 {
+                    // Clearing both bits one after the other would briefly
+                    // select another function, toggle them together instead.
+                    self.registers.selc[self.reg_idx].set(bit);
+                } else if sel0 != 0 {
+                    modify_atomic(&self.registers.sel0[self.reg_idx], |sel0| sel0 & !bit);
+                } else if sel1 != 0 {
+                    modify_atomic(&self.registers.sel1[self.reg_idx], |sel1| sel1 & !bit);
+                }
+                modify_atomic(&self.registers.dir[self.reg_idx], |dir| dir & !bit);
+                modify_atomic(&self.registers.ren[self.reg_idx], |ren| ren & !bit);
+            }
+        }
+
+        impl<'a> GroupPin for $pin_type<'a> {
+            fn location(&self) -> PinLocation {
+                PinLocation {
+                    registers: self.registers,
+                    reg_idx: self.reg_idx,
+                    bit: self.pin,
+                }
+            }
         }
 
         impl<'a> gpio::Input for $pin_type<'a> {
@@ -437,21 +524,15 @@ macro_rules! pin_implementation {
 
         impl<'a> gpio::Output for $pin_type<'a> {
             fn set(&self) {
-                let mut val = self.registers.out[self.reg_idx].get();
-                val |= 1 << self.pin;
-                self.registers.out[self.reg_idx].set(val);
+                self.modify_out(|val| val | (1 << self.pin));
             }
 
             fn clear(&self) {
-                let mut val = self.registers.out[self.reg_idx].get();
-                val &= !(1 << self.pin);
-                self.registers.out[self.reg_idx].set(val);
+                self.modify_out(|val| val & !(1 << self.pin));
             }
 
             fn toggle(&self) -> bool {
-                let mut val = self.registers.out[self.reg_idx].get();
-                val ^= 1 << self.pin;
-                self.registers.out[self.reg_idx].set(val);
+                let val = self.modify_out(|val| val ^ (1 << self.pin));
                 (val & (1 << self.pin)) > 0
             }
         }
@@ -463,7 +544,12 @@ macro_rules! pin_implementation {
                 let mut sel = ((regs.sel0[self.reg_idx].get() & (1 << self.pin)) > 0) as u8;
                 sel |= (((regs.sel1[self.reg_idx].get() & (1 << self.pin)) > 0) as u8) << 1;
 
-                if sel > 0 {
+                if sel == 3 {
+                    // The tertiary module function selects the analog
+                    // function of a pin, see the port function tables in
+                    // the datasheet
+                    gpio::Configuration::Analog
+                } else if sel > 0 {
                     gpio::Configuration::Function
                 } else {
                     if (dir & (1 << self.pin)) > 0 {
@@ -487,6 +573,19 @@ macro_rules! pin_implementation {
                 gpio::Configuration::Output
             }
 
+            fn make_output_with_level(&self, high: bool) -> gpio::Configuration {
+                use gpio::Output;
+                // Write PxOUT while the pin is still an input, so that it
+                // drives `high` as soon as the direction is switched
+                self.set_to(high);
+                self.enable_module_function(ModuleFunction::Gpio);
+
+                let mut val = self.registers.dir[self.reg_idx].get();
+                val |= 1 << self.pin;
+                self.registers.dir[self.reg_idx].set(val);
+                gpio::Configuration::Output
+            }
+
             fn disable_output(&self) -> gpio::Configuration {
                 self.make_input()
             }
@@ -501,11 +600,30 @@ macro_rules! pin_implementation {
             }
 
             fn disable_input(&self) -> gpio::Configuration {
-                // it's not possible to deactivate the pin at all, so just return the
-                // current configuration
+                // It's not possible to disconnect the input buffer, but a
+                // floating input wastes power, so pull it down instead. Output
+                // and function pins are left untouched, as the pull direction
+                // of a pin is selected by its output register.
+                if let gpio::Configuration::Input = self.configuration() {
+                    self.set_floating_state(gpio::FloatingState::PullDown);
+                }
                 self.configuration()
             }
 
+            fn make_input_output(&self) -> gpio::Configuration {
+                // The input buffer is always connected, so PxIN reads the
+                // level of the pin also when it is driven as an output. The
+                // hardware has no separate input-output state, so
+                // `configuration` reports such a pin as an output.
+                self.make_output();
+                gpio::Configuration::InputOutput
+            }
+
+            fn make_analog(&self) -> gpio::Configuration {
+                self.enable_tertiary_function();
+                gpio::Configuration::Analog
+            }
+
             fn deactivate_to_low_power(&self) {
                 // the chip doesn't support any low-power, so set it to input with
                 // a pullup resistor which should not consume much current
@@ -514,27 +632,43 @@ macro_rules! pin_implementation {
             }
 
             fn set_floating_state(&self, state: gpio::FloatingState) {
-                let regs = self.registers;
-                let mut ren = regs.ren[self.reg_idx].get();
-                let mut out = regs.out[self.reg_idx].get();
+                // The pull direction is selected by the output register,
+                // which is also used by some peripheral functions. Changing
+                // it while the pin is muxed to a peripheral could disturb
+                // the peripheral, so this is ignored for function and analog
+                // pins.
+                match self.configuration() {
+                    gpio::Configuration::Function | gpio::Configuration::Analog => return,
+                    _ => {}
+                }
+
+                // Select the direction first, so enabling the resistor
+                // doesn't briefly pull the wrong way.
                 match state {
                     gpio::FloatingState::PullDown => {
-                        ren |= 1 << self.pin;
-                        out &= !(1 << self.pin);
+                        self.modify_out(|out| out & !(1 << self.pin));
                     }
                     gpio::FloatingState::PullUp => {
-                        ren |= 1 << self.pin;
-                        out |= 1 << self.pin;
-                    }
-                    gpio::FloatingState::PullNone => {
-                        ren &= !(1 << self.pin);
+                        self.modify_out(|out| out | (1 << self.pin));
                     }
+                    gpio::FloatingState::PullNone => {}
                 }
-                regs.ren[self.reg_idx].set(ren);
-                regs.out[self.reg_idx].set(out);
+                modify_atomic(&self.registers.ren[self.reg_idx], |ren| match state {
+                    gpio::FloatingState::PullNone => ren & !(1 << self.pin),
+                    _ => ren | (1 << self.pin),
+                });
             }
 
             fn floating_state(&self) -> gpio::FloatingState {
+                // The resistor is not used by the pin in these modes, see
+                // `set_floating_state`
+                match self.configuration() {
+                    gpio::Configuration::Function | gpio::Configuration::Analog => {
+                        return gpio::FloatingState::PullNone
+                    }
+                    _ => {}
+                }
+
                 let ren = self.registers.ren[self.reg_idx].get();
                 let out = self.registers.out[self.reg_idx].get();
 
@@ -608,26 +742,423 @@ impl<'a> gpio::Interrupt<'a> for IntPin<'a> {
     fn is_pending(&self) -> bool {
         (self.registers.ifg[self.reg_idx].get() & (1 << self.pin)) > 0
     }
+
+    fn is_interrupt_enabled(&self) -> bool {
+        (self.registers.ie[self.reg_idx].get() & (1 << self.pin)) > 0
+    }
+}
+
+impl<'a> gpio::InterruptWithLevel<'a> for IntPin<'a> {
+    fn set_level_client(&self, client: &'a dyn gpio::ClientWithLevel) {
+        self.level_client.set(client);
+    }
 }
 
 impl<'a> GpioManager<'a> {
+    /// Reset all pins to GPIO inputs without pull resistors and disable and
+    /// clear their interrupts, see `reset_to_gpio_input`.
+    ///
+    /// This is meant to be called early during board initialization, before
+    /// any pin is configured, so that the board starts from a clean state
+    /// even if the previous run rebooted without a reset of the chip. PJ.4
+    /// and PJ.5 are left untouched, as they are used by the debug interface.
+    pub fn reset_all(&self) {
+        for pin in self.int_pins.iter() {
+            gpio::Interrupt::disable_interrupts(pin);
+            pin.reset_to_gpio_input();
+            modify_atomic(&pin.registers.ifg[pin.reg_idx], |ifg| ifg & !(1 << pin.pin));
+        }
+        for (i, pin) in self.pins.iter().enumerate() {
+            if i == PinNr::PJ_4 as usize || i == PinNr::PJ_5 as usize {
+                continue;
+            }
+            pin.reset_to_gpio_input();
+        }
+    }
+
     pub fn handle_interrupt(&self, port_idx: usize) {
-        let regs: StaticRef<GpioRegisters> = GPIO_BASES[port_idx / 2];
-        let ifgs: [u8; 2] = [regs.ifg[0].get(), regs.ifg[1].get()];
+        let port = Port::new(port_idx as u8);
+
+        // Handle at most `PINS_PER_PORT` interrupts in one pass, so a pin
+        // that keeps triggering can't starve the rest of the kernel. As
+        // `PxIV` always reports the lowest pending pin first, such a pin may
+        // be handled several times in the pass, delaying higher pins until
+        // the next one. Any remaining flag keeps the port interrupt pending
+        // and we'll get called again.
+        for _ in 0..PINS_PER_PORT {
+            let pin = match port.next_pending_interrupt() {
+                Some(pin) => pin,
+                None => break,
+            };
+            self.int_pins[(port_idx * PINS_PER_PORT as usize) + pin as usize].handle_interrupt();
+            // Switching the detected edge for `EitherEdge` may set the flag
+            // again, so clear it once more after the pin was handled.
+            port.clear_pending(1 << pin);
+        }
+    }
+}
 
-        let handle_int = |ifg_idx: usize, i: usize| {
-            let bit = 1 << i;
-            if (ifgs[ifg_idx] & bit) > 0 {
-                self.int_pins[(port_idx * 8) + i].handle_interrupt();
-                // read back the current register value to avoid loosing interrupts which occured
-                // within this function
-                regs.ifg[ifg_idx].set(regs.ifg[ifg_idx].get() & !bit);
+/// Put all pins into a low-power default state: GPIO inputs with pull-down
+/// resistors, which neither float nor drive anything.
+///
+/// This writes the registers of each port directly rather than configuring
+/// the pins one by one, so it is meant to be called once early during board
+/// initialization, before any pin is configured, as it overrides the
+/// configuration of all pins. Unlike `GpioManager::reset_all`, pins are
+/// pulled down rather than left floating, and interrupts are not touched.
+/// PJ.4 and PJ.5 are left untouched, as they are used by the debug
+/// interface.
+pub fn init_all_inputs_pulldown() {
+    for (base, regs) in GPIO_BASES.iter().enumerate() {
+        // PJ is the only port of its register block
+        let (ports, keep) = if base == GPIO_BASES.len() - 1 {
+            (1, 0b0011_0000)
+        } else {
+            (2, 0)
+        };
+        for idx in 0..ports {
+            // Bits in `keep` retain their old value.
+            let masked = |old: u8, value: u8| (old & keep) | (value & !keep);
+            // Stop driving the pins first.
+            regs.dir[idx].set(masked(regs.dir[idx].get(), 0));
+            // The output register selects the direction of the pull, so
+            // clear it before enabling the resistors.
+            regs.out[idx].set(masked(regs.out[idx].get(), 0));
+            regs.ren[idx].set(masked(regs.ren[idx].get(), 0xff));
+            // Pins with both select bits set are switched to GPIO with PxSELC
+            // in one write, so that no other function is selected in between.
+            let both = regs.sel0[idx].get() & regs.sel1[idx].get() & !keep;
+            regs.selc[idx].set(both);
+            regs.sel0[idx].set(masked(regs.sel0[idx].get(), 0));
+            regs.sel1[idx].set(masked(regs.sel1[idx].get(), 0));
+        }
+    }
+}
+
+/// Check that the GPIO registers of every port respond at their configured
+/// address, for board bring-up. Each entry of `safe_pins` is a `(port, bit)`
+/// pair, numbered as for `Pin::from_port_bit`, of a pin that may be driven
+/// briefly without harm, e.g. an unconnected one. Pass one pin per port to
+/// check.
+///
+/// For each pin, patterns are written to `PxDIR` and `PxOUT` and read back,
+/// and the same registers of all other ports are checked to be unaffected.
+/// This detects register groups that are unmapped, read-only, or where two
+/// ports share an address. It can't detect two ports whose addresses are
+/// swapped, as both still behave like GPIO registers. The registers are
+/// restored afterwards.
+///
+/// Mismatches are printed with `debug!`. Returns the number of mismatches.
+#[cfg(feature = "gpio_selftest")]
+pub fn selftest(safe_pins: &[(u8, u8)]) -> usize {
+    let mut mismatches = 0;
+    for &(port, bit) in safe_pins {
+        let regs = GPIO_BASES[(port / 2) as usize];
+        let idx = (port % 2) as usize;
+        let mask = 1 << bit;
+
+        let saved = (regs.dir[idx].get(), regs.out[idx].get());
+        let others = |port_other: u8| {
+            let regs = GPIO_BASES[(port_other / 2) as usize];
+            let idx = (port_other % 2) as usize;
+            (regs.dir[idx].get(), regs.out[idx].get())
+        };
+        let mut before = [(0, 0); 11];
+        for (p, val) in before.iter_mut().enumerate() {
+            *val = others(p as u8);
+        }
+
+        // Set the output level before making the pin an output, then drive
+        // it high and low.
+        for (dir, out) in [
+            (saved.0, saved.1 | mask),
+            (saved.0 | mask, saved.1 | mask),
+            (saved.0 | mask, saved.1 & !mask),
+        ] {
+            regs.out[idx].set(out);
+            regs.dir[idx].set(dir);
+            let read = (regs.dir[idx].get(), regs.out[idx].get());
+            if read != (dir, out) {
+                kernel::debug!(
+                    "gpio selftest: port {} pin {}: wrote {:?}, read {:?}",
+                    port,
+                    bit,
+                    (dir, out),
+                    read
+                );
+                mismatches += 1;
+            }
+            for (p, val) in before.iter().enumerate() {
+                if p != port as usize && others(p as u8) != *val {
+                    kernel::debug!(
+                        "gpio selftest: port {} pin {}: write changed port {}",
+                        port,
+                        bit,
+                        p
+                    );
+                    mismatches += 1;
+                }
             }
+        }
+
+        regs.dir[idx].set(saved.0);
+        regs.out[idx].set(saved.1);
+    }
+    mismatches
+}
+
+/// Access to the registers shared by all pins of a port.
+pub struct Port {
+    registers: StaticRef<GpioRegisters>,
+    reg_idx: usize,
+}
+
+impl Port {
+    /// `port` is numbered like `IntPinNr::port()`, i.e. 0 for P1. Only the
+    /// ports P1 to P6 support interrupts.
+    pub const fn new(port: u8) -> Port {
+        debug_assert!(port < 6);
+        Port {
+            registers: GPIO_BASES[(port / 2) as usize],
+            reg_idx: (port % 2) as usize,
+        }
+    }
+
+    /// Returns the interrupt flags (`PxIFG`) of all pins of this port, with
+    /// bit `n` set if pin `n` has a pending interrupt. This allows handling
+    /// several pins that triggered at the same time in one pass.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.registers.ifg[self.reg_idx].get()
+    }
+
+    /// Returns the number of the pending pin with the highest priority, i.e.
+    /// the lowest pin number, and clears its interrupt flag.
+    ///
+    /// This reads `PxIV`, which holds `2 * (pin + 1)` for the pending pin
+    /// with the highest priority, or 0 if no interrupt is pending. Reading it
+    /// clears the flag of that pin in hardware, so an interrupt handler can
+    /// call this until it returns `None` to service all pending pins in
+    /// priority order, without a read-modify-write of `PxIFG` that could
+    /// lose a flag set in between.
+    pub fn next_pending_interrupt(&self) -> Option<u8> {
+        let iv = match self.reg_idx {
+            0 => &self.registers.iv1,
+            _ => &self.registers.iv2,
         };
+        match iv.read(PxIV::IV) {
+            0 => None,
+            offset => Some((offset / 2 - 1) as u8),
+        }
+    }
 
-        for i in 0..8 {
-            handle_int(0, i);
-            handle_int(1, i);
+    /// Clear the interrupt flags of the pins in `mask`. The flags are read
+    /// back right before clearing them, so flags of other pins that were set
+    /// in the meantime are kept.
+    pub fn clear_pending(&self, mask: u8) {
+        let ifg = &self.registers.ifg[self.reg_idx];
+        ifg.set(ifg.get() & !mask);
+    }
+
+    /// Disable the interrupts of all pins of this port with a single write
+    /// of `PxIE`, e.g. before entering a low power mode or reconfiguring a
+    /// bus. Returns the previous value of `PxIE`, to be passed to
+    /// `restore_interrupts` afterwards.
+    ///
+    /// The interrupt flags are not cleared, so edges detected while the
+    /// interrupts are disabled are still handled once they are restored.
+    pub fn disable_all_interrupts(&self) -> u8 {
+        let ie = &self.registers.ie[self.reg_idx];
+        let saved = ie.get();
+        ie.set(0);
+        saved
+    }
+
+    /// Set the interrupt enables (`PxIE`) of all pins of this port at once
+    /// to `mask`, typically the value returned by `disable_all_interrupts`.
+    pub fn restore_interrupts(&self, mask: u8) {
+        self.registers.ie[self.reg_idx].set(mask);
+    }
+}
+
+/// Where a pin is in the port registers, used to group the pins of a
+/// `PinGroup` by port.
+#[derive(Clone, Copy)]
+pub struct PinLocation {
+    registers: StaticRef<GpioRegisters>,
+    reg_idx: usize,
+    bit: u8,
+}
+
+impl PinLocation {
+    fn same_port(&self, other: &PinLocation) -> bool {
+        core::ptr::eq(&*self.registers, &*other.registers) && self.reg_idx == other.reg_idx
+    }
+}
+
+/// A pin that can be part of a `PinGroup`, i.e. a `Pin` or an `IntPin`.
+pub trait GroupPin: gpio::Configure {
+    fn location(&self) -> PinLocation;
+}
+
+/// A group of pins that are accessed together as one logical bus, e.g. the
+/// data lines of a parallel LCD or camera interface. The pins may be spread
+/// over several ports, don't need to be contiguous, and can mix `Pin`s and
+/// `IntPin`s.
+///
+/// Bit `i` of the values passed to `write` and returned by `read` maps to
+/// `pins[i]`, i.e. the first pin of the slice is the least significant bit.
+/// A group can therefore hold at most 32 pins.
+///
+/// Pins that share a port register are accessed together, so `write` and
+/// `read` perform one register access per port involved rather than one per
+/// pin. Pins on different ports are still updated one port after the other.
+pub struct PinGroup<'a> {
+    pins: &'a [&'a dyn GroupPin],
+}
+
+impl<'a> PinGroup<'a> {
+    /// Create a group of `pins`. Fails with `SIZE` if there are more than 32
+    /// pins, and with `INVAL` if a pin appears more than once.
+    pub fn new(pins: &'a [&'a dyn GroupPin]) -> Result<PinGroup<'a>, ErrorCode> {
+        if pins.len() > 32 {
+            return Err(ErrorCode::SIZE);
+        }
+        for (i, pin) in pins.iter().enumerate() {
+            let location = pin.location();
+            let duplicate = pins[..i].iter().any(|other| {
+                let other = other.location();
+                other.same_port(&location) && other.bit == location.bit
+            });
+            if duplicate {
+                return Err(ErrorCode::INVAL);
+            }
         }
+        Ok(PinGroup { pins })
+    }
+
+    /// Number of pins, i.e. bits, in this group.
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
     }
+
+    pub fn make_output(&self) {
+        for pin in self.pins {
+            pin.make_output();
+        }
+    }
+
+    pub fn make_input(&self) {
+        for pin in self.pins {
+            pin.make_input();
+        }
+    }
+
+    /// Drive the pins of this group to `value`. Bits above `len()` are
+    /// ignored.
+    pub fn write(&self, value: u32) {
+        self.write_masked(u32::MAX, value);
+    }
+
+    // Drive the pins whose bits are set in `mask` to `value`.
+    fn write_masked(&self, mask: u32, value: u32) {
+        self.for_each_port(|port, bits| {
+            let port_mask = scatter(mask, &bits);
+            if port_mask == 0 {
+                return;
+            }
+            let port_bits = scatter(value & mask, &bits);
+            // Interrupts are disabled for the same reason as for the
+            // `gpio::Output` functions of a single pin.
+            modify_atomic(&port.registers.out[port.reg_idx], |out| {
+                (out & !port_mask) | port_bits
+            });
+        });
+    }
+
+    /// Read the input level of the pins of this group.
+    pub fn read(&self) -> u32 {
+        let mut value = 0;
+        self.for_each_port(|port, bits| {
+            let input = port.registers.input[port.reg_idx].get();
+            value |= gather(input, &bits);
+        });
+        value
+    }
+
+    // Call `f` once for every port with pins in this group, with the
+    // location of the first pin of that port, and for each pin the bit in
+    // the port registers as `(group_bit, port_bit)`.
+    fn for_each_port(&self, mut f: impl FnMut(PinLocation, PortBits)) {
+        for (i, first) in self.pins.iter().enumerate() {
+            let first = first.location();
+            if self.pins[..i]
+                .iter()
+                .any(|pin| pin.location().same_port(&first))
+            {
+                // Already handled together with an earlier pin.
+                continue;
+            }
+            let mut bits = PortBits::default();
+            for (j, pin) in self.pins.iter().enumerate().skip(i) {
+                let location = pin.location();
+                if location.same_port(&first) {
+                    bits.push(j as u8, location.bit);
+                }
+            }
+            f(first, bits);
+        }
+    }
+}
+
+impl gpio::PortExt for PinGroup<'_> {
+    type PortToken = u32;
+
+    fn token(&self, mask: u32) -> u32 {
+        mask
+    }
+
+    fn write_all(&self, token: u32, value: u32) {
+        self.write_masked(token, value);
+    }
+
+    fn read_all(&self, token: u32) -> u32 {
+        self.read() & token
+    }
+}
+
+/// The pins of a `PinGroup` that are in one port register.
+#[derive(Default)]
+struct PortBits {
+    /// `(group_bit, port_bit)` for each pin.
+    bits: [(u8, u8); PINS_PER_PORT as usize],
+    len: usize,
+}
+
+impl PortBits {
+    // `PinGroup::new` rejects duplicate pins, so a port never has more than
+    // `PINS_PER_PORT` of them.
+    fn push(&mut self, group_bit: u8, port_bit: u8) {
+        self.bits[self.len] = (group_bit, port_bit);
+        self.len += 1;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &(u8, u8)> {
+        self.bits[..self.len].iter()
+    }
+}
+
+fn scatter(value: u32, bits: &PortBits) -> u8 {
+    bits.iter()
+        .filter(|(group_bit, _)| value & (1 << group_bit) != 0)
+        .fold(0, |acc, (_, port_bit)| acc | (1 << port_bit))
+}
+
+fn gather(port: u8, bits: &PortBits) -> u32 {
+    bits.iter()
+        .filter(|(_, port_bit)| port & (1 << port_bit) != 0)
+        .fold(0, |acc, (group_bit, _)| acc | (1 << group_bit))
 }
@@ -9,6 +9,7 @@
 use cortexm4::{initialize_ram_jump_to_main, unhandled_interrupt, CortexM4, CortexMVariant};
 
 pub mod adc;
+pub mod atomic;
 pub mod chip;
 pub mod cs;
 pub mod dma;
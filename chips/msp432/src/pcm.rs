@@ -3,8 +3,30 @@
 // Copyright Tock Contributors 2022.
 
 //! Power Control Manager (PCM)
+//!
+//! Low-power modes
+//! ---------------
+//!
+//! The modes differ in which interrupt sources remain able to wake the chip
+//! (see the "Operating Modes" section of the datasheet):
+//!
+//! - LPM0: Only the CPU is halted. All clocks and peripherals keep running,
+//!   so any enabled interrupt wakes the chip.
+//! - LPM3: The high-frequency clocks (MCLK, HSMCLK, SMCLK) are stopped. Only
+//!   peripherals clocked from ACLK or BCLK (e.g. RTC_C, WDT_A, timers on
+//!   ACLK) and port interrupts, which do not need a clock to detect an edge,
+//!   can wake the chip. All state is retained.
+//! - LPM3.5 and LPM4.5: Only the RTC, the watchdog and the wake-up capable
+//!   pins of ports P1 to P6 remain, and waking up goes through a reset. The
+//!   kernel does not survive this, so these modes are never entered here.
+//!
+//! Drivers whose interrupts must be able to wake the chip register as a
+//! `WakeupSource`, and `enter_low_power` falls back to the deepest mode
+//! that keeps all registered sources alive.
 
-use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use core::cell::Cell;
+
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
     register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
 };
@@ -169,15 +191,104 @@ register_bitfields![u32,
     ]
 ];
 
+/// Low-power modes the kernel can enter while idle, from shallowest to
+/// deepest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LowPowerMode {
+    Lpm0,
+    Lpm3,
+}
+
+/// Interrupt sources that must be able to wake the chip from a low-power
+/// mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeupSource {
+    /// Port interrupts, these are detected without a clock.
+    Gpio,
+    /// Peripherals clocked from ACLK or BCLK.
+    LowFrequencyClock,
+    /// Peripherals clocked from MCLK, HSMCLK or SMCLK.
+    HighFrequencyClock,
+}
+
+impl WakeupSource {
+    const COUNT: usize = 3;
+
+    /// The deepest low-power mode from which this source can still wake the
+    /// chip.
+    fn deepest_mode(self) -> LowPowerMode {
+        match self {
+            WakeupSource::Gpio | WakeupSource::LowFrequencyClock => LowPowerMode::Lpm3,
+            WakeupSource::HighFrequencyClock => LowPowerMode::Lpm0,
+        }
+    }
+}
+
 pub struct Pcm {
     registers: StaticRef<PcmRegisters>,
+    /// Number of registrations of each `WakeupSource`, indexed by its
+    /// discriminant.
+    wakeup_sources: [Cell<u8>; WakeupSource::COUNT],
 }
 
 impl Pcm {
     pub const fn new() -> Pcm {
         Pcm {
             registers: PCM_BASE,
+            wakeup_sources: [Cell::new(0), Cell::new(0), Cell::new(0)],
+        }
+    }
+
+    /// Require `source` to be able to wake the chip until a matching call to
+    /// `unregister_wakeup_source`. Registrations of the same source are
+    /// counted, so several drivers can rely on it independently.
+    pub fn register_wakeup_source(&self, source: WakeupSource) {
+        let count = &self.wakeup_sources[source as usize];
+        count.set(count.get().saturating_add(1));
+    }
+
+    pub fn unregister_wakeup_source(&self, source: WakeupSource) {
+        let count = &self.wakeup_sources[source as usize];
+        count.set(count.get().saturating_sub(1));
+    }
+
+    /// The deepest low-power mode that keeps all registered wakeup sources
+    /// able to wake the chip.
+    pub fn deepest_allowed_mode(&self) -> LowPowerMode {
+        [
+            WakeupSource::Gpio,
+            WakeupSource::LowFrequencyClock,
+            WakeupSource::HighFrequencyClock,
+        ]
+        .iter()
+        .filter(|&&source| self.wakeup_sources[source as usize].get() > 0)
+        .map(|source| source.deepest_mode())
+        .fold(LowPowerMode::Lpm3, core::cmp::min)
+    }
+
+    /// Sleep in `requested`, or in a shallower mode if a registered wakeup
+    /// source could not wake the chip from it, until the next interrupt.
+    /// Returns the mode that was entered.
+    pub fn enter_low_power(&self, requested: LowPowerMode) -> LowPowerMode {
+        let mode = core::cmp::min(requested, self.deepest_allowed_mode());
+        match mode {
+            LowPowerMode::Lpm0 => unsafe {
+                cortexm4::support::wfi();
+            },
+            LowPowerMode::Lpm3 => {
+                while self.registers.ctl1.is_set(PCMCTL1::PMR_BUSY) {}
+                self.registers.ctl0.modify(
+                    PCMCTL0::PCMKEY.val(PCMKEY)
+                        + PCMCTL0::LPMR::LPM3CoreVoltageSettingIsSimilarToTheModeFromWhichLPM3IsEntered,
+                );
+                unsafe {
+                    cortexm4::scb::set_sleepdeep();
+                    cortexm4::support::wfi();
+                    cortexm4::scb::unset_sleepdeep();
+                }
+            }
         }
+        mode
     }
     // currently not sure about the interface, so just implement a simple
     // method for activating AM_LDO_VCORE1 to provide enough power for 48MHz
@@ -8,15 +8,21 @@
 //! * Philip Levis <pal@cs.stanford.edu>
 //! * Date: August 18, 2016
 
+#[cfg(feature = "test_gpio")]
+use core::cell::Cell;
 use core::ops::{Index, IndexMut};
 use enum_primitive::cast::FromPrimitive;
 use enum_primitive::enum_from_primitive;
 use kernel::debug;
+#[cfg(feature = "test_gpio")]
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, ReadWrite};
 use kernel::utilities::StaticRef;
+#[cfg(feature = "test_gpio")]
+use kernel::ErrorCode;
 
 #[cfg(feature = "nrf51")]
 const NUM_GPIOTE: usize = 4;
@@ -365,6 +371,10 @@ pub struct GPIOPin<'a> {
     gpiote_registers: StaticRef<GpioteRegisters>,
     gpio_registers: StaticRef<GpioRegisters>,
     allocated_channel: OptionalCell<usize>,
+    #[cfg(feature = "test_gpio")]
+    forced: Cell<bool>,
+    #[cfg(feature = "test_gpio")]
+    forced_deferred_call: OptionalCell<&'a DeferredCall>,
 }
 
 impl<'a> GPIOPin<'a> {
@@ -381,6 +391,10 @@ impl<'a> GPIOPin<'a> {
             },
             gpiote_registers: GPIOTE_BASE,
             allocated_channel: OptionalCell::empty(),
+            #[cfg(feature = "test_gpio")]
+            forced: Cell::new(false),
+            #[cfg(feature = "test_gpio")]
+            forced_deferred_call: OptionalCell::empty(),
         }
     }
 
@@ -485,9 +499,15 @@ impl hil::gpio::Output for GPIOPin<'_> {
     }
 
     fn toggle(&self) -> bool {
-        let result = (1 << self.pin) ^ self.gpio_registers.out.get();
-        self.gpio_registers.out.set(result);
-        result & (1 << self.pin) != 0
+        // Use OUTSET/OUTCLR rather than writing OUT, so a concurrent update
+        // of another pin of the port isn't overwritten.
+        if self.gpio_registers.out.get() & (1 << self.pin) != 0 {
+            self.gpio_registers.outclr.set(1 << self.pin);
+            false
+        } else {
+            self.gpio_registers.outset.set(1 << self.pin);
+            true
+        }
     }
 }
 
@@ -543,6 +563,18 @@ impl<'a> hil::gpio::Interrupt<'a> for GPIOPin<'a> {
             self.allocated_channel.clear();
         }
     }
+
+    #[cfg(feature = "test_gpio")]
+    fn force_fire(&self) -> Result<(), ErrorCode> {
+        // Only possible once a `ForcedInterrupts` has been registered for
+        // the port this pin belongs to.
+        self.forced_deferred_call
+            .map(|deferred_call| {
+                self.forced.set(true);
+                deferred_call.set();
+            })
+            .ok_or(ErrorCode::OFF)
+    }
 }
 
 impl GPIOPin<'_> {
@@ -604,3 +636,40 @@ impl<'a, const N: usize> Port<'a, N> {
         }
     }
 }
+
+/// Delivers interrupts forced with `Interrupt::force_fire` to the pins of a
+/// `Port`. All pins of a port share the deferred call of this struct, so it
+/// has to be created and registered once per port for `force_fire` to work.
+#[cfg(feature = "test_gpio")]
+pub struct ForcedInterrupts<'a, const N: usize> {
+    port: &'a Port<'a, N>,
+    deferred_call: DeferredCall,
+}
+
+#[cfg(feature = "test_gpio")]
+impl<'a, const N: usize> ForcedInterrupts<'a, N> {
+    pub fn new(port: &'a Port<'a, N>) -> Self {
+        Self {
+            port,
+            deferred_call: DeferredCall::new(),
+        }
+    }
+}
+
+#[cfg(feature = "test_gpio")]
+impl<const N: usize> DeferredCallClient for ForcedInterrupts<'static, N> {
+    fn handle_deferred_call(&self) {
+        for pin in self.port.pins.iter() {
+            if pin.forced.take() {
+                pin.handle_interrupt();
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+        for pin in self.port.pins.iter() {
+            pin.forced_deferred_call.set(&self.deferred_call);
+        }
+    }
+}
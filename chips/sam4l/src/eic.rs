@@ -18,6 +18,8 @@
 // Author: Josh Zhang <jiashuoz@cs.princeton.edu>
 // Last modified July 22, 2019
 
+use core::cell::Cell;
+
 use crate::pm::{self, Clock, PBDClock};
 use kernel::hil;
 use kernel::platform::chip::ClockInterface;
@@ -26,6 +28,7 @@ use kernel::utilities::peripheral_management::PeripheralManagement;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 /// Enum for enabling or disabling spurious event filtering (i.e. de-bouncing control).
 pub enum FilterMode {
@@ -58,6 +61,13 @@ pub enum Line {
     Ext8 = 256,
 }
 
+impl Line {
+    /// Index of the line, the position of its bit in the EIC registers.
+    fn index(&self) -> usize {
+        (*self as u32).trailing_zeros() as usize
+    }
+}
+
 #[repr(C)]
 pub struct EicRegisters {
     /// Enables propagation from eic to nvic
@@ -158,12 +168,47 @@ impl PeripheralManagement<pm::Clock> for Eic<'_> {
 
 pub struct Eic<'a> {
     callbacks: [OptionalCell<&'a dyn hil::eic::Client>; 9],
+    /// Number of outstanding `line_enable` calls per line. The hardware is
+    /// only touched when this changes from or to 0.
+    enable_counts: [Cell<u8>; 9],
+    /// Mode each line was enabled with, valid while its count is non-zero.
+    modes: [Cell<hil::eic::InterruptMode>; 9],
 }
 
-impl hil::eic::ExternalInterruptController for Eic<'_> {
+impl<'a> hil::eic::ExternalInterruptController<'a> for Eic<'a> {
     type Line = Line;
 
-    fn line_enable(&self, line: &Self::Line, interrupt_mode: hil::eic::InterruptMode) {
+    fn set_client(&self, client: &'a dyn hil::eic::Client, line: &Self::Line) {
+        self.callbacks[line.index()].set(client);
+    }
+
+    fn line_enable(
+        &self,
+        line: &Self::Line,
+        interrupt_mode: hil::eic::InterruptMode,
+    ) -> Result<(), ErrorCode> {
+        // Lines are always configured as asynchronous, in which the EIC
+        // detects edge interrupts as level interrupts.
+        match interrupt_mode {
+            hil::eic::InterruptMode::RisingEdge | hil::eic::InterruptMode::FallingEdge => {
+                return Err(ErrorCode::INVAL);
+            }
+            hil::eic::InterruptMode::HighLevel | hil::eic::InterruptMode::LowLevel => {}
+        }
+
+        let count = &self.enable_counts[line.index()];
+        let mode = &self.modes[line.index()];
+        if count.get() > 0 {
+            if mode.get() != interrupt_mode {
+                return Err(ErrorCode::INVAL);
+            }
+            let next = count.get().checked_add(1).ok_or(ErrorCode::BUSY)?;
+            count.set(next);
+            return Ok(());
+        }
+        count.set(1);
+        mode.set(interrupt_mode);
+
         let regs = self.get_registers();
 
         // enables interrupt line, sets ctrl register
@@ -178,9 +223,20 @@ impl hil::eic::ExternalInterruptController for Eic<'_> {
 
         // enables propagation from eic to nvic, sets imr register
         regs.ier.write(Interrupt::INT.val(*line as u32));
+        Ok(())
     }
 
-    fn line_disable(&self, line: &Self::Line) {
+    fn line_disable(&self, line: &Self::Line) -> Result<(), ErrorCode> {
+        let count = &self.enable_counts[line.index()];
+        match count.get() {
+            0 => return Err(ErrorCode::ALREADY),
+            1 => count.set(0),
+            n => {
+                count.set(n - 1);
+                return Ok(());
+            }
+        }
+
         let regs = self.get_registers();
 
         // disables interrupt line, sets ctrl register
@@ -188,6 +244,7 @@ impl hil::eic::ExternalInterruptController for Eic<'_> {
 
         // disables propagation from eic to nvic, sets imr register
         regs.idr.write(Interrupt::INT.val(*line as u32));
+        Ok(())
     }
 }
 
@@ -255,21 +312,38 @@ impl<'a> Eic<'a> {
                 OptionalCell::empty(),
                 OptionalCell::empty(),
             ],
+            enable_counts: [
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+            ],
+            modes: [
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+                Cell::new(hil::eic::InterruptMode::LowLevel),
+            ],
         }
     }
 
-    /// Registers a client associated with a line.
-    pub fn set_client(&self, client: &'a dyn hil::eic::Client, line: &Line) {
-        self.callbacks.get(*line as usize).map(|c| c.set(client));
-    }
-
     /// Executes client function when an interrupt is triggered.
     pub fn handle_interrupt(&self, line: &Line) {
         // Clears interrupt bit and then handle interrupt
         let regs = self.get_registers();
         regs.icr.write(Interrupt::INT.val(*line as u32));
 
-        self.callbacks[*line as usize].map(|cb| {
+        self.callbacks[line.index()].map(|cb| {
             cb.fired();
         });
     }
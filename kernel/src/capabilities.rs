@@ -109,3 +109,9 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `AlarmDiagnosticsCapability` allows the holder to let processes read
+/// the internal scheduling state of the alarm driver, i.e. the next
+/// expiration it programmed, which is shared by all processes. Boards should
+/// only grant it in debug builds.
+pub unsafe trait AlarmDiagnosticsCapability {}
@@ -78,4 +78,10 @@ impl<'a, T: ?Sized + ListNode<'a, T>> List<'a, T> {
             cur: self.head.0.get(),
         }
     }
+
+    /// Returns the number of nodes for which `predicate` is true, e.g. to
+    /// recompute a counter that is otherwise maintained incrementally.
+    pub fn count_where<F: FnMut(&'a T) -> bool>(&self, mut predicate: F) -> usize {
+        self.iter().filter(|&node| predicate(node)).count()
+    }
 }
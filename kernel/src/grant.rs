@@ -618,6 +618,25 @@ impl<'a> GrantKernelData<'a> {
         )
     }
 
+    /// Remove upcalls for `subscribe_num` which have been scheduled but not
+    /// yet delivered to the process.
+    ///
+    /// Capsules which signal an event that is superseded by a later
+    /// occurrence of the same event can call this before `schedule_upcall`,
+    /// so that a process which does not service its upcalls only ever has
+    /// the most recent one pending instead of filling its task queue with
+    /// stale ones.
+    pub fn remove_pending_upcalls(&self, subscribe_num: usize) -> Result<(), UpcallError> {
+        if subscribe_num >= self.upcalls.len() {
+            return Err(UpcallError::InvalidSubscribeNum);
+        }
+        self.process.remove_pending_upcalls(UpcallId {
+            subscribe_num,
+            driver_num: self.driver_num,
+        });
+        Ok(())
+    }
+
     /// Returns a lifetime limited reference to the requested
     /// [`ReadOnlyProcessBuffer`].
     ///
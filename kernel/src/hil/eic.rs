@@ -12,10 +12,12 @@
 //! wake-up the part from sleep modes where the CLK_SYNC clock has been disabled.
 //!
 //! A basic use case:
-//! A user button is configured for falling edge trigger and async mode.
+//! A user button is configured for low level trigger and async mode.
+
+use crate::ErrorCode;
 
 /// Enum for selecting which edge to trigger interrupts on.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterruptMode {
     RisingEdge,
     FallingEdge,
@@ -24,17 +26,51 @@ pub enum InterruptMode {
 }
 
 /// Interface for EIC.
-pub trait ExternalInterruptController {
+pub trait ExternalInterruptController<'a> {
     /// The chip-dependent type of an EIC line. Number of lines available depends on the chip.
     type Line;
 
+    /// Set the client for interrupt events on `line`. If there was a previously installed
+    /// client for the line, this call replaces it. The client must live as long as the
+    /// controller; as controllers are typically statically allocated chip peripherals, this
+    /// means that clients are `'static` in practice.
+    fn set_client(&self, client: &'a dyn Client, line: &Self::Line);
+
     /// Enables external interrupt on the given 'line'
-    /// In asynchronous mode, all edge interrupts will be
-    /// interpreted as level interrupts and the filter is disabled.
-    fn line_enable(&self, line: &Self::Line, interrupt_mode: InterruptMode);
+    /// In asynchronous mode, all edge interrupts would be
+    /// interpreted as level interrupts and the filter is disabled,
+    /// so edge modes are rejected for asynchronous lines.
+    ///
+    /// Enabling is counted per line, so several users can enable the same
+    /// line, and it stays enabled until each of them has called
+    /// `line_disable`. Only the first enable configures the line; later
+    /// ones must request the same `interrupt_mode`.
+    ///
+    /// Return values:
+    ///
+    /// - `Ok(())`: The line was enabled with the requested `interrupt_mode`,
+    ///   or was already enabled with that mode.
+    /// - `INVAL`: The line cannot trigger on `interrupt_mode` in its current
+    ///   configuration, or it is already enabled with a different mode. The
+    ///   line is left unchanged.
+    /// - `BUSY`: The line has been enabled too many times without being
+    ///   disabled. The line is left unchanged.
+    fn line_enable(
+        &self,
+        line: &Self::Line,
+        interrupt_mode: InterruptMode,
+    ) -> Result<(), ErrorCode>;
 
-    /// Disables external interrupt on the given 'line'
-    fn line_disable(&self, line: &Self::Line);
+    /// Disables external interrupt on the given 'line', once it has been
+    /// disabled as many times as it was enabled.
+    ///
+    /// Return values:
+    ///
+    /// - `Ok(())`: The call was counted. The line is disabled if no other
+    ///   enable is outstanding.
+    /// - `ALREADY`: The line is not enabled, so a user disabled it more often
+    ///   than it enabled it. The line is left unchanged.
+    fn line_disable(&self, line: &Self::Line) -> Result<(), ErrorCode>;
 }
 
 /// Interface for users of EIC. In order
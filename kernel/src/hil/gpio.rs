@@ -41,6 +41,9 @@ pub enum Configuration {
     InputOutput,
     /// Chip-specific, requires chip-specific API for more detail,
     Function,
+    /// The digital input and output buffers are disconnected and the pin is
+    /// routed to an analog peripheral, such as an ADC or comparator.
+    Analog,
     /// In a state not covered by other values.
     Other,
 }
@@ -90,6 +93,25 @@ pub trait Configure {
     /// which should be either `Configuration::Output` or
     /// `Configuration::InputOutput`.
     fn make_output(&self) -> Configuration;
+
+    /// Make the pin an output driving `high` (`true` for high) from the
+    /// start, returning the current configuration like `make_output`.
+    ///
+    /// The level is written before the direction is switched, so the pin
+    /// never drives a stale level in between, e.g. an active-low reset line
+    /// that must come up held high. The default sets the level with
+    /// `Output::set_to` and then calls `make_output`, which is correct as
+    /// long as `make_output` leaves the output level unchanged; chips whose
+    /// `make_output` resets the level must override it. This is not
+    /// available through `dyn` pins.
+    fn make_output_with_level(&self, high: bool) -> Configuration
+    where
+        Self: Output + Sized,
+    {
+        self.set_to(high);
+        self.make_output()
+    }
+
     /// Disable the pin as an output, returning the current configuration.
     fn disable_output(&self) -> Configuration;
 
@@ -100,6 +122,27 @@ pub trait Configure {
     /// Disable the pin as an input, returning the current configuration.
     fn disable_input(&self) -> Configuration;
 
+    /// Make the pin simultaneously an input and an output, e.g. for an
+    /// open-drain line of a bit-banged bus that must be driven and sensed,
+    /// returning the current configuration, which should be
+    /// `Configuration::InputOutput`. Pins that cannot be configured this
+    /// way leave the configuration unchanged and return it, which is the
+    /// default.
+    fn make_input_output(&self) -> Configuration {
+        self.configuration()
+    }
+
+    /// Route the pin to its analog function (e.g. an ADC channel), returning
+    /// the current configuration, which should be `Configuration::Analog`.
+    ///
+    /// On chips where analog operation is one of several peripheral function
+    /// selections (e.g. the tertiary module function on the MSP432), this
+    /// selects that function. Pins without analog support leave the
+    /// configuration unchanged and return it, which is the default.
+    fn make_analog(&self) -> Configuration {
+        self.configuration()
+    }
+
     /// Put a pin into its lowest power state, with no guarantees on
     /// if it is enabled or not. Implementations are free to use any
     /// state (e.g. input, output, disable, etc.) the hardware pin
@@ -138,14 +181,22 @@ pub trait Configure {
 
 /// Configuration trait for pins that can be simultaneously
 /// input and output. Having this trait allows an implementation
-/// to statically verify this is possible.
+/// to statically verify this is possible. Implementations must
+/// override `Configure::make_input_output` to always return
+/// `Configuration::InputOutput`.
 pub trait ConfigureInputOutput: Configure {
-    /// Make the pin a simultaneously input and output; should always
-    /// return `Configuration::InputOutput`.
-    fn make_input_output(&self) -> Configuration;
     fn is_input_output(&self) -> bool;
 }
 
+/// Drive the level of a GPIO pin.
+///
+/// Pins of one port typically share an output register, and other pins of
+/// the same port may be updated from interrupt context. Implementations of
+/// `set`, `clear` and `toggle` must therefore only change the level of their
+/// own pin, even if another pin of the port is written concurrently. Chips
+/// should use hardware set/clear/toggle registers where available, and
+/// otherwise perform the read-modify-write of the shared register with
+/// interrupts disabled.
 pub trait Output {
     /// Set the GPIO pin high. If the pin is not an output or
     /// input/output, this call is ignored.
@@ -161,6 +212,16 @@ pub trait Output {
     /// of the pin.
     fn toggle(&self) -> bool;
 
+    /// Set the GPIO pin high if `value` is true, and low otherwise. If the
+    /// pin is not an output or input/output, this call is ignored.
+    fn set_to(&self, value: bool) {
+        if value {
+            self.set();
+        } else {
+            self.clear();
+        }
+    }
+
     /// Activate or deactivate a GPIO pin, for a given activation mode.
     fn write_activation(&self, state: ActivationState, mode: ActivationMode) {
         match (state, mode) {
@@ -194,6 +255,75 @@ pub trait Input {
             }
         }
     }
+
+    /// Read the pin `samples` times in a row and return the value read
+    /// most often, to filter out glitches when polling a noisy input such as
+    /// a switch. Use an odd number of samples, as a tie reads as `false`. A
+    /// `samples` of 0 is treated as 1.
+    ///
+    /// This busy-reads the pin and returns after `samples` reads, without
+    /// waiting in between. It only filters glitches shorter than the time
+    /// these reads take, so it suits fast, memory-mapped GPIO in polling
+    /// loops. Debouncing the bouncing of a mechanical contact, which lasts
+    /// milliseconds, requires sampling over time with an alarm instead.
+    fn read_majority(&self, samples: usize) -> bool {
+        let samples = samples.max(1);
+        let high = (0..samples).filter(|_| self.read()).count();
+        high * 2 > samples
+    }
+}
+
+/// Access to a group of up to 32 pins as one value, e.g. the data lines of a
+/// parallel bus or the rows of an LED matrix.
+///
+/// Bit `i` of the values written and read maps to the `i`-th pin of the
+/// group. Accesses only affect the pins selected by a `PortToken`, which is
+/// created once from a mask of these bits, so that implementations can
+/// prepare the per-port register masks ahead of time.
+///
+/// Chips that can access the pins of a port through one register implement
+/// this for their pin groups. Any slice of pins implements it by accessing
+/// the pins one after the other, so capsules can use this trait without
+/// depending on hardware support. Only hardware implementations can update
+/// several pins at the same time, and only for pins of the same port.
+pub trait PortExt {
+    /// Opaque handle selecting some of the pins of this group.
+    type PortToken: Copy;
+
+    /// Create a token selecting the pins whose bits are set in `mask`.
+    fn token(&self, mask: u32) -> Self::PortToken;
+
+    /// Drive the pins selected by `token` to the levels of the
+    /// corresponding bits of `value`. Other pins are left unchanged.
+    fn write_all(&self, token: Self::PortToken, value: u32);
+
+    /// Read the levels of the pins selected by `token`. The bits of other
+    /// pins read as 0.
+    fn read_all(&self, token: Self::PortToken) -> u32;
+}
+
+impl<P: Input + Output + ?Sized> PortExt for [&P] {
+    type PortToken = u32;
+
+    fn token(&self, mask: u32) -> u32 {
+        mask
+    }
+
+    fn write_all(&self, token: u32, value: u32) {
+        for (i, pin) in self.iter().enumerate().take(32) {
+            if token & (1 << i) != 0 {
+                pin.set_to(value & (1 << i) != 0);
+            }
+        }
+    }
+
+    fn read_all(&self, token: u32) -> u32 {
+        self.iter()
+            .enumerate()
+            .take(32)
+            .filter(|(i, pin)| token & (1 << i) != 0 && pin.read())
+            .fold(0, |acc, (i, _)| acc | (1 << i))
+    }
 }
 
 pub trait Interrupt<'a>: Input {
@@ -210,6 +340,28 @@ pub trait Interrupt<'a>: Input {
 
     /// Return whether this interrupt is pending
     fn is_pending(&self) -> bool;
+
+    /// Return whether interrupts are currently enabled for the GPIO pin.
+    /// Implementations that cannot read this back return `false`.
+    fn is_interrupt_enabled(&self) -> bool {
+        false
+    }
+
+    /// Trigger a `Client::fired` callback without a real edge on the pin,
+    /// so interrupt-driven capsules can be tested on hardware without
+    /// toggling the pin. The callback is issued from a deferred call, never
+    /// from within this function.
+    ///
+    /// This bypasses the hardware entirely: `is_pending` does not report a
+    /// forced interrupt, and the interrupt does not need to be enabled.
+    /// Returns `NOSUPPORT` if the implementation cannot force interrupts.
+    ///
+    /// Only available with the `test_gpio` feature, and must not be relied
+    /// on by production code.
+    #[cfg(feature = "test_gpio")]
+    fn force_fire(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Interface for users of synchronous GPIO interrupts. In order
@@ -222,6 +374,30 @@ pub trait Client {
     fn fired(&self);
 }
 
+/// Interrupt source that also reports the level of the pin on each
+/// interrupt, e.g. for a quadrature decoder which needs to know which edge
+/// occurred.
+///
+/// The level is captured by the implementation when handling the
+/// interrupt, before edge detection is re-armed, rather than by the client
+/// calling `read` from `fired`, by which time the pin may have changed
+/// again. It is still sampled after the edge, so a pulse shorter than the
+/// interrupt latency may be reported with the level from before the edge.
+pub trait InterruptWithLevel<'a>: Interrupt<'a> {
+    /// Set the client receiving the level of the pin on interrupts. This is
+    /// independent from the `Client` set with `Interrupt::set_client`, and
+    /// both are called if set.
+    fn set_level_client(&self, client: &'a dyn ClientWithLevel);
+}
+
+/// Interface for users of GPIO interrupts which need the level of the pin
+/// at the time of the interrupt, see `InterruptWithLevel`.
+pub trait ClientWithLevel {
+    /// Called when an interrupt occurs, with the level of the pin captured
+    /// while handling it: `true` if high.
+    fn fired(&self, level: bool);
+}
+
 /// Interface that wraps an interrupt to pass a value when it
 /// triggers. The standard use case for this trait is when several
 /// interrupts call the same callback function and it needs to
@@ -247,6 +423,12 @@ pub trait InterruptWithValue<'a>: Input {
     /// Return whether this interrupt is pending
     fn is_pending(&self) -> bool;
 
+    /// Return whether interrupts are currently enabled for the GPIO pin.
+    /// Implementations that cannot read this back return `false`.
+    fn is_interrupt_enabled(&self) -> bool {
+        false
+    }
+
     /// Set the value that will be passed to clients on an
     /// interrupt.
     fn set_value(&self, value: u32);
@@ -274,13 +456,22 @@ pub struct InterruptValueWrapper<'a, IP: InterruptPin<'a>> {
 
 impl<'a, IP: InterruptPin<'a>> InterruptValueWrapper<'a, IP> {
     pub fn new(pin: &'a IP) -> Self {
+        Self::new_with_value(pin, 0)
+    }
+
+    /// Create a wrapper that passes `value` to its client when `pin` fires,
+    /// so that board code doesn't need a separate `set_value` call. Capsules
+    /// that number their pins, like the GPIO and button drivers, overwrite
+    /// the value with the index of the pin.
+    pub fn new_with_value(pin: &'a IP, value: u32) -> Self {
         Self {
-            value: Cell::new(0),
+            value: Cell::new(value),
             client: OptionalCell::empty(),
             source: pin,
         }
     }
 
+    /// Set this wrapper as the client of the pin, returning it.
     pub fn finalize(&'static self) -> &'static Self {
         self.source.set_client(self);
         self
@@ -304,6 +495,10 @@ impl<'a, IP: InterruptPin<'a>> InterruptWithValue<'a> for InterruptValueWrapper<
         self.source.is_pending()
     }
 
+    fn is_interrupt_enabled(&self) -> bool {
+        self.source.is_interrupt_enabled()
+    }
+
     fn enable_interrupts(&self, edge: InterruptEdge) -> Result<(), ErrorCode> {
         self.source.enable_interrupts(edge);
         Ok(())
@@ -329,6 +524,13 @@ impl<'a, IP: InterruptPin<'a>> Configure for InterruptValueWrapper<'a, IP> {
         self.source.make_output()
     }
 
+    fn make_output_with_level(&self, high: bool) -> Configuration
+    where
+        Self: Output + Sized,
+    {
+        self.source.make_output_with_level(high)
+    }
+
     fn disable_output(&self) -> Configuration {
         self.source.disable_output()
     }
@@ -341,6 +543,14 @@ impl<'a, IP: InterruptPin<'a>> Configure for InterruptValueWrapper<'a, IP> {
         self.source.disable_input()
     }
 
+    fn make_input_output(&self) -> Configuration {
+        self.source.make_input_output()
+    }
+
+    fn make_analog(&self) -> Configuration {
+        self.source.make_analog()
+    }
+
     fn deactivate_to_low_power(&self) {
         self.source.deactivate_to_low_power();
     }
@@ -381,3 +591,311 @@ impl<'a, IP: InterruptPin<'a>> Client for InterruptValueWrapper<'a, IP> {
         self.client.map(|c| c.fired(self.value()));
     }
 }
+
+/// Wrapper around an `InterruptPin` that counts the interrupts it delivers,
+/// e.g. to quantify how much a noisy button bounces. All calls are forwarded
+/// to the wrapped pin, and each `Client::fired` from the pin increments the
+/// count before being passed on to this wrapper's client. As it is itself an
+/// `InterruptPin`, it can be placed below an `InterruptValueWrapper`.
+pub struct CountingInterruptPin<'a, IP: InterruptPin<'a>> {
+    count: Cell<u32>,
+    client: OptionalCell<&'a dyn Client>,
+    source: &'a IP,
+}
+
+impl<'a, IP: InterruptPin<'a>> CountingInterruptPin<'a, IP> {
+    pub fn new(pin: &'a IP) -> Self {
+        Self {
+            count: Cell::new(0),
+            client: OptionalCell::empty(),
+            source: pin,
+        }
+    }
+
+    pub fn finalize(&'static self) -> &'static Self {
+        self.source.set_client(self);
+        self
+    }
+
+    /// Number of interrupts since creation or the last `reset_count`. Wraps
+    /// around on overflow.
+    pub fn count(&self) -> u32 {
+        self.count.get()
+    }
+
+    pub fn reset_count(&self) {
+        self.count.set(0);
+    }
+}
+
+impl<'a, IP: InterruptPin<'a>> Interrupt<'a> for CountingInterruptPin<'a, IP> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.replace(client);
+    }
+
+    fn enable_interrupts(&self, mode: InterruptEdge) {
+        self.source.enable_interrupts(mode);
+    }
+
+    fn disable_interrupts(&self) {
+        self.source.disable_interrupts();
+    }
+
+    fn is_pending(&self) -> bool {
+        self.source.is_pending()
+    }
+
+    fn is_interrupt_enabled(&self) -> bool {
+        self.source.is_interrupt_enabled()
+    }
+
+    #[cfg(feature = "test_gpio")]
+    fn force_fire(&self) -> Result<(), ErrorCode> {
+        self.source.force_fire()
+    }
+}
+
+impl<'a, IP: InterruptPin<'a>> Input for CountingInterruptPin<'a, IP> {
+    fn read(&self) -> bool {
+        self.source.read()
+    }
+}
+
+impl<'a, IP: InterruptPin<'a>> Configure for CountingInterruptPin<'a, IP> {
+    fn configuration(&self) -> Configuration {
+        self.source.configuration()
+    }
+
+    fn make_output(&self) -> Configuration {
+        self.source.make_output()
+    }
+
+    fn make_output_with_level(&self, high: bool) -> Configuration
+    where
+        Self: Output + Sized,
+    {
+        self.source.make_output_with_level(high)
+    }
+
+    fn disable_output(&self) -> Configuration {
+        self.source.disable_output()
+    }
+
+    fn make_input(&self) -> Configuration {
+        self.source.make_input()
+    }
+
+    fn disable_input(&self) -> Configuration {
+        self.source.disable_input()
+    }
+
+    fn make_input_output(&self) -> Configuration {
+        self.source.make_input_output()
+    }
+
+    fn make_analog(&self) -> Configuration {
+        self.source.make_analog()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.source.deactivate_to_low_power();
+    }
+
+    fn set_floating_state(&self, state: FloatingState) {
+        self.source.set_floating_state(state);
+    }
+
+    fn floating_state(&self) -> FloatingState {
+        self.source.floating_state()
+    }
+
+    fn is_input(&self) -> bool {
+        self.source.is_input()
+    }
+
+    fn is_output(&self) -> bool {
+        self.source.is_output()
+    }
+}
+
+impl<'a, IP: InterruptPin<'a>> Output for CountingInterruptPin<'a, IP> {
+    fn set(&self) {
+        self.source.set();
+    }
+
+    fn clear(&self) {
+        self.source.clear();
+    }
+
+    fn toggle(&self) -> bool {
+        self.source.toggle()
+    }
+}
+
+impl<'a, IP: InterruptPin<'a>> Client for CountingInterruptPin<'a, IP> {
+    fn fired(&self) {
+        self.count.set(self.count.get().wrapping_add(1));
+        self.client.map(|c| c.fired());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Reads the bits of `levels`, least significant first.
+    struct SequenceInput {
+        levels: Cell<u32>,
+    }
+
+    impl Input for SequenceInput {
+        fn read(&self) -> bool {
+            let levels = self.levels.get();
+            self.levels.set(levels >> 1);
+            levels & 1 != 0
+        }
+    }
+
+    struct CellPin(Cell<bool>);
+
+    impl Input for CellPin {
+        fn read(&self) -> bool {
+            self.0.get()
+        }
+    }
+
+    impl Output for CellPin {
+        fn set(&self) {
+            self.0.set(true);
+        }
+
+        fn clear(&self) {
+            self.0.set(false);
+        }
+
+        fn toggle(&self) -> bool {
+            self.0.set(!self.0.get());
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn test_set_to() {
+        let pin = CellPin(Cell::new(false));
+        pin.set_to(true);
+        assert!(pin.read());
+        pin.set_to(true);
+        assert!(pin.read());
+        pin.set_to(false);
+        assert!(!pin.read());
+    }
+
+    /// Records the level of the pin when it was made an output.
+    struct ConfigPin {
+        level: Cell<bool>,
+        level_at_output: Cell<Option<bool>>,
+    }
+
+    impl Output for ConfigPin {
+        fn set(&self) {
+            self.level.set(true);
+        }
+
+        fn clear(&self) {
+            self.level.set(false);
+        }
+
+        fn toggle(&self) -> bool {
+            self.level.set(!self.level.get());
+            self.level.get()
+        }
+    }
+
+    impl Configure for ConfigPin {
+        fn configuration(&self) -> Configuration {
+            match self.level_at_output.get() {
+                Some(_) => Configuration::Output,
+                None => Configuration::Input,
+            }
+        }
+
+        fn make_output(&self) -> Configuration {
+            self.level_at_output.set(Some(self.level.get()));
+            Configuration::Output
+        }
+
+        fn disable_output(&self) -> Configuration {
+            self.level_at_output.set(None);
+            Configuration::Input
+        }
+
+        fn make_input(&self) -> Configuration {
+            self.disable_output()
+        }
+
+        fn disable_input(&self) -> Configuration {
+            self.configuration()
+        }
+
+        fn deactivate_to_low_power(&self) {}
+
+        fn set_floating_state(&self, _state: FloatingState) {}
+
+        fn floating_state(&self) -> FloatingState {
+            FloatingState::PullNone
+        }
+    }
+
+    #[test]
+    fn test_make_output_with_level() {
+        for high in [false, true] {
+            let pin = ConfigPin {
+                level: Cell::new(!high),
+                level_at_output: Cell::new(None),
+            };
+            assert!(matches!(
+                pin.make_output_with_level(high),
+                Configuration::Output
+            ));
+            // The level was already written when the direction changed
+            assert_eq!(pin.level_at_output.get(), Some(high));
+        }
+    }
+
+    #[test]
+    fn test_port_ext_pin_slice() {
+        let pins = [
+            CellPin(Cell::new(false)),
+            CellPin(Cell::new(true)),
+            CellPin(Cell::new(false)),
+        ];
+        let group: &[&CellPin] = &[&pins[0], &pins[1], &pins[2]];
+
+        let all = group.token(0b111);
+        assert_eq!(group.read_all(all), 0b010);
+
+        // Only the selected pins are written and read
+        group.write_all(group.token(0b101), 0b111);
+        assert_eq!(group.read_all(all), 0b111);
+        group.write_all(group.token(0b001), 0b000);
+        assert_eq!(group.read_all(all), 0b110);
+        assert_eq!(group.read_all(group.token(0b011)), 0b010);
+    }
+
+    #[test]
+    fn test_read_majority() {
+        let pin = |levels| SequenceInput {
+            levels: Cell::new(levels),
+        };
+        assert!(pin(0b01011).read_majority(5));
+        assert!(!pin(0b10100).read_majority(5));
+        // Only the requested number of samples is read
+        let p = pin(0b11100);
+        assert!(!p.read_majority(3));
+        assert!(p.read_majority(3));
+        // A tie reads as low, 0 samples as 1
+        assert!(!pin(0b10).read_majority(2));
+        assert!(pin(0b1).read_majority(0));
+    }
+}
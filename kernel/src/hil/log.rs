@@ -6,6 +6,19 @@
 //!
 //! Log entries are appended to the end of a log and read back sequentially. Log data persists
 //! across device reboots.
+//!
+//! Erasing the log is a long-running operation that invalidates all entries and read positions.
+//! Once `erase` returns `Ok(())`, the log is busy until the client receives
+//! `LogWriteClient::erase_done`: `read`, `append`, `seek` and `sync` must return `BUSY` during
+//! that time, and leave the log unchanged. The log is usable again as soon as `erase_done` has
+//! been issued, including from within the `erase_done` callback.
+//!
+//! After an `erase_done` reporting success, the log is empty: `log_start()`, `log_end()` and
+//! `next_read_entry_id()` are all equal, `used_size()` is 0, and `read` fails with `FAIL` like at
+//! the end of any log, rather than returning erased or stale flash contents. If the erase did not
+//! complete, the read position is never left on a page that was already erased.
+
+use core::cmp::Ordering;
 
 use crate::ErrorCode;
 
@@ -18,7 +31,12 @@ pub trait LogRead<'a> {
     fn set_read_client(&'a self, read_client: &'a dyn LogReadClient);
 
     /// Read the next entry from the log. The log advances to the next entry after a successful
-    /// read. State does not change in the event of a failure.
+    /// read. State does not change in the event of a failure. Returns `BUSY` while an erase is in
+    /// progress.
+    ///
+    /// `length` is the maximum number of bytes to read into `buffer`, and must not exceed
+    /// `buffer.len()`. Otherwise the read fails with `SIZE`, and the buffer is returned without
+    /// being accessed.
     fn read(
         &self,
         buffer: &'static mut [u8],
@@ -35,12 +53,42 @@ pub trait LogRead<'a> {
     /// Returns the ID of the next entry to be read.
     fn next_read_entry_id(&self) -> Self::EntryID;
 
+    /// Compare the positions of two entry IDs in the log. `Ordering::Less` means that `a` comes
+    /// before `b`, so a reader has caught up with the end of the log once
+    /// `entry_id_ordering(next_read_entry_id(), log_end())` is no longer `Less`. Entry IDs are
+    /// opaque, so the comparison is implementation-defined, and returns `None` if the IDs cannot
+    /// be compared, e.g. because one of them is not a valid position in this log.
+    fn entry_id_ordering(&self, a: Self::EntryID, b: Self::EntryID) -> Option<Ordering>;
+
     /// Seek to the entry with the given entry ID and begin reading from there. Fails without
-    /// modifying the read position if the given entry ID is invalid or no longer in the log.
+    /// modifying the read position if the given entry ID is invalid or no longer in the log, or
+    /// with `BUSY` while an erase is in progress.
     fn seek(&self, entry: Self::EntryID) -> Result<(), ErrorCode>;
 
     /// Get approximate log capacity in bytes.
     fn get_size(&self) -> usize;
+
+    /// Get the approximate number of bytes of valid data currently in the log, i.e. of the
+    /// entries from `log_start()` up to `log_end()`. This never exceeds `get_size()`, and is less
+    /// than it until the log fills up, after which a circular log starts to overwrite its oldest
+    /// entries.
+    ///
+    /// A reader that seeks to `log_start()` and reads until `next_read_entry_id()` equals
+    /// `log_end()` walks through `used_size()` bytes, so it can be used to track the progress of
+    /// reading the whole log.
+    fn used_size(&self) -> usize;
+
+    /// Get an estimate of the number of entries currently in the log, e.g. for a UI to show
+    /// "entry 37 of 200" while reading it. Logs whose entries all have the same size can compute
+    /// it as `used_size() / entry_size`; the default returns `None`, meaning no estimate is
+    /// available, which is the case for logs of variable-length entries.
+    ///
+    /// This is only an estimate: entries appended or overwritten between this call and its use
+    /// make it stale, so it must not be used to decide when reading is complete. Compare
+    /// `next_read_entry_id()` with `log_end()` for that.
+    fn estimate_record_count(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// Receive callbacks from `LogRead`.
@@ -58,7 +106,12 @@ pub trait LogWrite<'a> {
     /// Set the client for appending from a log. The client will be called when writing operations complete.
     fn set_append_client(&'a self, append_client: &'a dyn LogWriteClient);
 
-    /// Append an entry to the end of the log. May fail if the entry is too large.
+    /// Append an entry to the end of the log. May fail if the entry is too large. Returns `BUSY`
+    /// while an erase is in progress.
+    ///
+    /// The entry consists of the first `length` bytes of `buffer`, so `length` must not exceed
+    /// `buffer.len()`. Otherwise the append fails with `SIZE`, and the buffer is returned without
+    /// being accessed.
     fn append(
         &self,
         buffer: &'static mut [u8],
@@ -68,11 +121,12 @@ pub trait LogWrite<'a> {
     /// Sync log to storage, making all entries persistent (not including any entries that were
     /// previously overwritten). There is no guarantee that any changes to the log are persistent
     /// until it is synced. In the event of an error, not all pages may be synced, but the log will
-    /// remain in a valid state.
+    /// remain in a valid state. Returns `BUSY` while an erase is in progress.
     fn sync(&self) -> Result<(), ErrorCode>;
 
     /// Erase the entire log. In the event of a failure, only some pages may be erased, but the log
-    /// will remain in a valid state.
+    /// will remain in a valid state. On success, all other operations fail with `BUSY` until
+    /// `erase_done` is issued.
     fn erase(&self) -> Result<(), ErrorCode>;
 }
 
@@ -91,6 +145,7 @@ pub trait LogWriteClient {
     /// Returns whether or not all pages were correctly synced, making all changes persistent.
     fn sync_done(&self, error: Result<(), ErrorCode>);
 
-    /// Returns whether or not all pages of the log were erased.
+    /// Returns whether or not all pages of the log were erased. The log accepts other operations
+    /// again from this callback on.
     fn erase_done(&self, error: Result<(), ErrorCode>);
 }
@@ -18,6 +18,7 @@
 use crate::ErrorCode;
 use core::cmp::Ordering;
 use core::fmt;
+use core::marker::PhantomData;
 
 /// An integer type defining the width of a time value, which allows
 /// clients to know when wraparound will occur.
@@ -68,6 +69,15 @@ pub trait Ticks: Clone + Copy + From<u32> + fmt::Debug + Ord + PartialOrd + Eq {
         F::frequency() << Self::usize_padding()
     }
 
+    /// Creates a value from a raw count of ticks, e.g. a counter value
+    /// read from hardware or passed by userspace. This is the same as
+    /// `From<u32>`, but makes it explicit at the call site that `raw` is
+    /// not a duration in some other unit: durations should be converted
+    /// with `ConvertTicks::ticks_from_ms` or `ticks_from_us` instead.
+    fn from_raw(raw: u32) -> Self {
+        Self::from(raw)
+    }
+
     /// Converts the type into a `u32`, stripping the higher bits
     /// it if it is larger than `u32` and filling the higher bits
     /// with 0 if it is smaller than `u32`. Included as a simple
@@ -75,6 +85,10 @@ pub trait Ticks: Clone + Copy + From<u32> + fmt::Debug + Ord + PartialOrd + Eq {
     /// are 32 bits.
     fn into_u32(self) -> u32;
 
+    /// Converts the type into a `u64`, filling the higher bits with 0.
+    /// Unlike `into_u32`, this is lossless for all widths.
+    fn into_u64(self) -> u64;
+
     /// The amount of bits required to left-justify this ticks value
     /// range (filling the lower bits with `0`) for it wrap at `(2 **
     /// 32) - 1` bits. For timers with a `width` larger than 32, this
@@ -110,11 +124,44 @@ pub trait Ticks: Clone + Copy + From<u32> + fmt::Debug + Ord + PartialOrd + Eq {
 
     /// Add two values, wrapping around on overflow using standard
     /// unsigned arithmetic.
+    ///
+    /// This is the right operation for points in time of a free-running
+    /// counter, such as `now() + dt`, which wrap around together with the
+    /// counter.
     fn wrapping_add(self, other: Self) -> Self;
     /// Subtract two values, wrapping around on underflow using standard
     /// unsigned arithmetic.
+    ///
+    /// This is the right operation for the distance between two points in
+    /// time, such as `now() - reference`.
     fn wrapping_sub(self, other: Self) -> Self;
 
+    /// Add two values, returning `max_value()` on overflow.
+    ///
+    /// Use this for durations rather than points in time, e.g. when
+    /// extending a `dt` by some margin, where wrapping around would turn a
+    /// long duration into a short one.
+    fn saturating_add(self, other: Self) -> Self {
+        let sum = self.wrapping_add(other);
+        if sum < self {
+            Self::max_value()
+        } else {
+            sum
+        }
+    }
+
+    /// Subtract two values, returning 0 on underflow.
+    ///
+    /// Use this for durations rather than points in time, e.g. for the time
+    /// remaining of a `dt` of which some part has elapsed.
+    fn saturating_sub(self, other: Self) -> Self {
+        if other > self {
+            Self::from(0)
+        } else {
+            self.wrapping_sub(other)
+        }
+    }
+
     /// Returns whether the value is in the range of [`start, `end`) using
     /// unsigned arithmetic and considering wraparound. It returns `true`
     /// if, incrementing from `start`, the value will be reached before `end`.
@@ -143,6 +190,23 @@ pub trait Ticks: Clone + Copy + From<u32> + fmt::Debug + Ord + PartialOrd + Eq {
 pub trait Frequency {
     /// Returns frequency in Hz.
     fn frequency() -> u32;
+
+    /// Returns the number of ticks in `ms` milliseconds at this frequency,
+    /// rounding down any fractions. The product is computed in 64 bits, so
+    /// long durations at high frequencies don't overflow; a result that
+    /// doesn't fit in a `u32` saturates to `u32::MAX`.
+    fn ticks_in_ms(ms: u32) -> u32 {
+        let ticks = Self::frequency() as u64 * ms as u64 / 1_000;
+        u32::try_from(ticks).unwrap_or(u32::MAX)
+    }
+
+    /// Returns the number of milliseconds in `ticks` ticks at this
+    /// frequency, rounding down any fractions. A result that doesn't fit in
+    /// a `u32`, or a frequency of zero, saturates to `u32::MAX`.
+    fn ms_in_ticks(ticks: u32) -> u32 {
+        let ms = (ticks as u64 * 1_000).checked_div(Self::frequency() as u64);
+        ms.map_or(u32::MAX, |ms| u32::try_from(ms).unwrap_or(u32::MAX))
+    }
 }
 
 /// Represents a moment in time, obtained by calling `now`.
@@ -172,6 +236,13 @@ pub trait ConvertTicks<T: Ticks> {
 
     fn ticks_from_ms(&self, ms: u32) -> T;
 
+    /// Returns the number of ticks in the provided number of milliseconds,
+    /// rounding down any fractions. Fails with `SIZE` if the duration
+    /// doesn't fit in one period of the counter, i.e. if `ticks_from_ms`
+    /// would return `Ticks::max_value()`, so that a delay that can't be
+    /// represented is caught rather than silently shortened.
+    fn checked_ticks_from_ms(&self, ms: u32) -> Result<T, ErrorCode>;
+
     /// Returns the number of ticks in the provided number of microseconds,
     /// rounding down any fractions. If the value overflows Ticks it
     /// returns `Ticks::max_value()`.
@@ -205,6 +276,15 @@ impl<T: Time + ?Sized> ConvertTicks<<T as Time>::Ticks> for T {
         <T as Time>::Ticks::from_or_max(val / 1_000)
     }
     #[inline]
+    fn checked_ticks_from_ms(&self, ms: u32) -> Result<<T as Time>::Ticks, ErrorCode> {
+        let ticks = self.ticks_from_ms(ms);
+        if ticks == <T as Time>::Ticks::max_value() {
+            Err(ErrorCode::SIZE)
+        } else {
+            Ok(ticks)
+        }
+    }
+    #[inline]
     fn ticks_from_us(&self, us: u32) -> <T as Time>::Ticks {
         let val = <T as Time>::Frequency::frequency() as u64 * us as u64;
         <T as Time>::Ticks::from_or_max(val / 1_000_000)
@@ -224,6 +304,15 @@ impl<T: Time + ?Sized> ConvertTicks<<T as Time>::Ticks> for T {
     }
 }
 
+/// Converts a number of ticks of a clock with frequency `F1` to the number
+/// of ticks of a clock with frequency `F2` spanning the same duration, e.g.
+/// to relate an alarm running at 32kHz to a cycle counter. Rounds down any
+/// fractions. If the value overflows u32, `u32::MAX` is returned.
+pub fn convert_ticks<F1: Frequency, F2: Frequency>(ticks: u32) -> u32 {
+    let val = ticks as u64 * F2::frequency() as u64 / F1::frequency() as u64;
+    val.try_into().unwrap_or(u32::MAX)
+}
+
 /// Represents a static moment in time, that does not change over
 /// repeated calls to `Time::now`.
 pub trait Timestamp: Time {}
@@ -305,10 +394,39 @@ pub trait Alarm<'a>: Time {
     /// passed and those in the far far future (see #1651).
     fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks);
 
+    /// Specify that the callback should be called `dt` ticks from now, and
+    /// enable it. This is `set_alarm(self.now(), dt)`; prefer it over
+    /// computing an absolute expiration from `now()` by hand.
+    fn set_alarm_from_now(&self, dt: Self::Ticks) {
+        self.set_alarm(self.now(), dt);
+    }
+
     /// Return the current alarm value. This is undefined at boot and
     /// otherwise returns `now + dt` from the last call to `set_alarm`.
     fn get_alarm(&self) -> Self::Ticks;
 
+    /// Return the `reference` of the current alarm, such that it expires at
+    /// `get_alarm_reference() + get_alarm_dt() == get_alarm()`. Together with
+    /// `get_alarm_dt` this allows checking whether an alarm has already
+    /// expired with `Ticks::within_range`, like `set_alarm` does.
+    ///
+    /// Many alarms only store the expiration in hardware, so the default
+    /// returns the earliest reference an alarm with a `dt` of at most half
+    /// the counter range could have had, `get_alarm() - half_max_value()`.
+    /// This window contains the one passed to `set_alarm` for such alarms,
+    /// so an alarm that expired is still recognized as expired. Alarms that
+    /// know the actual reference should override this.
+    fn get_alarm_reference(&self) -> Self::Ticks {
+        self.get_alarm().wrapping_sub(Self::Ticks::half_max_value())
+    }
+
+    /// Return the `dt` of the current alarm, relative to
+    /// `get_alarm_reference`. The default derives it from `get_alarm` and
+    /// `get_alarm_reference`.
+    fn get_alarm_dt(&self) -> Self::Ticks {
+        self.get_alarm().wrapping_sub(self.get_alarm_reference())
+    }
+
     /// Disable the alarm and stop it from firing in the future.
     /// Valid `Result<(), ErrorCode>` codes are:
     ///   - `Ok(())` the alarm has been disarmed and will not invoke
@@ -317,6 +435,19 @@ pub trait Alarm<'a>: Time {
     ///   the callback in the future
     fn disarm(&self) -> Result<(), ErrorCode>;
 
+    /// Disable the alarm like `disarm`, and report whether this cancelled a
+    /// pending alarm:
+    ///   - `Ok(true)` the alarm was armed and has been disarmed
+    ///   - `Ok(false)` the alarm was not armed, nothing changed
+    ///   - `Err(ErrorCode::FAIL)` the alarm could not be disarmed
+    ///
+    /// As for `is_armed`, a callback that is already pending may still be
+    /// called after this returns `Ok(false)`.
+    fn disarm_if_armed(&self) -> Result<bool, ErrorCode> {
+        let was_armed = self.is_armed();
+        self.disarm().map(|()| was_armed)
+    }
+
     /// Returns whether the alarm is currently armed. Note that this
     /// does not reliably indicate whether there will be a future
     /// callback: it is possible that the alarm has triggered (and
@@ -459,6 +590,52 @@ impl Frequency for Freq1KHz {
     }
 }
 
+/// Formats a number of ticks as a human-readable duration, for use in
+/// debug output. `F` is the frequency of the clock the ticks were read
+/// from, typically `<A as Time>::Frequency`.
+///
+/// The duration is printed in the largest of ns, us, ms or s that keeps the
+/// integer part non-zero, with three fractional digits, e.g. `1.500ms` for
+/// 1500 ticks at 1MHz. Ticks wider than `usize` are truncated.
+///
+/// ```rust,ignore
+/// debug!("alarm fired {} late", TicksDuration::<A::Ticks, A::Frequency>::new(late));
+/// ```
+pub struct TicksDuration<T: Ticks, F: Frequency> {
+    ticks: T,
+    _frequency: PhantomData<F>,
+}
+
+impl<T: Ticks, F: Frequency> TicksDuration<T, F> {
+    pub fn new(ticks: T) -> Self {
+        TicksDuration {
+            ticks,
+            _frequency: PhantomData,
+        }
+    }
+}
+
+impl<T: Ticks, F: Frequency> fmt::Display for TicksDuration<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ns = self.ticks.into_usize() as u128 * 1_000_000_000 / F::frequency() as u128;
+        if ns < 1_000 {
+            write!(f, "{}ns", ns)
+        } else if ns < 1_000_000 {
+            write!(f, "{}.{:03}us", ns / 1_000, ns % 1_000)
+        } else if ns < 1_000_000_000 {
+            write!(f, "{}.{:03}ms", ns / 1_000_000, ns / 1_000 % 1_000)
+        } else {
+            write!(f, "{}.{:03}s", ns / 1_000_000_000, ns / 1_000_000 % 1_000)
+        }
+    }
+}
+
+impl<T: Ticks, F: Frequency> fmt::Debug for TicksDuration<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 /// u32 `Ticks`
 #[derive(Clone, Copy, Debug)]
 pub struct Ticks32(u32);
@@ -482,6 +659,10 @@ impl Ticks for Ticks32 {
         self.0
     }
 
+    fn into_u64(self) -> u64 {
+        self.0 as u64
+    }
+
     fn wrapping_add(self, other: Self) -> Self {
         Ticks32(self.0.wrapping_add(other.0))
     }
@@ -571,6 +752,10 @@ impl Ticks for Ticks24 {
         self.0
     }
 
+    fn into_u64(self) -> u64 {
+        self.0 as u64
+    }
+
     fn wrapping_add(self, other: Self) -> Self {
         Ticks24(self.0.wrapping_add(other.0) & Self::MASK)
     }
@@ -668,6 +853,10 @@ impl Ticks for Ticks16 {
         self.0 as u32
     }
 
+    fn into_u64(self) -> u64 {
+        self.0 as u64
+    }
+
     fn wrapping_add(self, other: Self) -> Self {
         Ticks16(self.0.wrapping_add(other.0))
     }
@@ -734,12 +923,6 @@ impl Eq for Ticks16 {}
 #[derive(Clone, Copy, Debug)]
 pub struct Ticks64(u64);
 
-impl Ticks64 {
-    pub fn into_u64(self) -> u64 {
-        self.0
-    }
-}
-
 impl From<u32> for Ticks64 {
     fn from(val: u32) -> Self {
         Ticks64(val as u64)
@@ -765,6 +948,10 @@ impl Ticks for Ticks64 {
         self.0 as u32
     }
 
+    fn into_u64(self) -> u64 {
+        self.0
+    }
+
     fn wrapping_add(self, other: Self) -> Self {
         Ticks64(self.0.wrapping_add(other.0))
     }
@@ -826,6 +1013,7 @@ impl Eq for Ticks64 {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::cell::Cell;
 
     struct Test1MHz64();
     impl Time for Test1MHz64 {
@@ -837,6 +1025,71 @@ mod tests {
         }
     }
 
+    fn check_saturating<T: Ticks>() {
+        let max = T::max_value();
+        let one = T::from(1);
+        assert_eq!(T::from(2).saturating_add(one), T::from(3));
+        assert_eq!(max.wrapping_sub(one).saturating_add(one), max);
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(max.saturating_add(max), max);
+        assert_eq!(T::from(3).saturating_sub(one), T::from(2));
+        assert_eq!(one.saturating_sub(one), T::from(0));
+        assert_eq!(one.saturating_sub(T::from(2)), T::from(0));
+        assert_eq!(T::from(0).saturating_sub(max), T::from(0));
+    }
+
+    #[test]
+    fn test_saturating() {
+        check_saturating::<Ticks16>();
+        check_saturating::<Ticks24>();
+        check_saturating::<Ticks32>();
+        check_saturating::<Ticks64>();
+    }
+
+    #[test]
+    fn test_convert_ticks() {
+        // Slower to faster clock
+        assert_eq!(convert_ticks::<Freq32KHz, Freq16MHz>(32_768), 16_000_000);
+        assert_eq!(convert_ticks::<Freq1KHz, Freq1MHz>(5), 5_000);
+        // Faster to slower clock, rounding down
+        assert_eq!(convert_ticks::<Freq1MHz, Freq1KHz>(1_999), 1);
+        assert_eq!(convert_ticks::<Freq16MHz, Freq32KHz>(16_000_000), 32_768);
+        // Same clock
+        assert_eq!(convert_ticks::<Freq1MHz, Freq1MHz>(u32::MAX), u32::MAX);
+        // Saturation, the intermediate product doesn't overflow
+        assert_eq!(convert_ticks::<Freq1KHz, Freq100MHz>(u32::MAX), u32::MAX);
+        assert_eq!(
+            convert_ticks::<Freq1KHz, Freq1MHz>(4_294_967),
+            4_294_967_000
+        );
+    }
+
+    enum Freq48MHz {}
+    impl Frequency for Freq48MHz {
+        fn frequency() -> u32 {
+            48_000_000
+        }
+    }
+
+    #[test]
+    fn test_frequency_ms_conversions() {
+        assert_eq!(Freq32KHz::ticks_in_ms(1), 32);
+        // One hour
+        assert_eq!(Freq32KHz::ticks_in_ms(3_600_000), 117_964_800);
+        assert_eq!(Freq32KHz::ticks_in_ms(u32::MAX), u32::MAX);
+        assert_eq!(Freq32KHz::ms_in_ticks(u32::MAX), 131_071_999);
+
+        assert_eq!(Freq1MHz::ticks_in_ms(4_294_967), 4_294_967_000);
+        assert_eq!(Freq1MHz::ticks_in_ms(4_294_968), u32::MAX);
+        assert_eq!(Freq1MHz::ms_in_ticks(1_999), 1);
+        assert_eq!(Freq1MHz::ms_in_ticks(u32::MAX), 4_294_967);
+
+        assert_eq!(Freq48MHz::ticks_in_ms(89_478), 4_294_944_000);
+        assert_eq!(Freq48MHz::ticks_in_ms(89_479), u32::MAX);
+        assert_eq!(Freq48MHz::ms_in_ticks(47_999), 0);
+        assert_eq!(Freq48MHz::ms_in_ticks(u32::MAX), 89_478);
+    }
+
     #[test]
     fn test_from_ticks64() {
         let s = Test1MHz64().ticks_to_seconds(1_000_000u32.into());
@@ -914,6 +1167,12 @@ mod tests {
 
         let t = Test1KHz16().ticks_from_us(1);
         assert_eq!(t.into_u32(), 0);
+
+        let t = Test1KHz16().checked_ticks_from_ms(65_000);
+        assert_eq!(t.map(Ticks16::into_u32), Ok(65_000));
+
+        let t = Test1KHz16().checked_ticks_from_ms(u16::MAX as u32 + 1);
+        assert_eq!(t, Err(ErrorCode::SIZE));
     }
 
     struct Test1KHz24();
@@ -951,4 +1210,104 @@ mod tests {
         let us = time.ticks_to_us(5_000_000u32.into());
         assert_eq!(us, u32::MAX);
     }
+
+    struct FmtBuffer {
+        buf: [u8; 32],
+        len: usize,
+    }
+
+    impl fmt::Write for FmtBuffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let bytes = s.as_bytes();
+            let end = self.len + bytes.len();
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(bytes);
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn assert_duration<T: Ticks, F: Frequency>(ticks: T, expected: &str) {
+        use core::fmt::Write;
+        let mut out = FmtBuffer {
+            buf: [0; 32],
+            len: 0,
+        };
+        write!(out, "{}", TicksDuration::<T, F>::new(ticks)).unwrap();
+        assert_eq!(core::str::from_utf8(&out.buf[..out.len]).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_ticks_duration() {
+        assert_duration::<Ticks32, Freq16MHz>(5u32.into(), "312ns");
+        assert_duration::<Ticks32, Freq1MHz>(0u32.into(), "0ns");
+        assert_duration::<Ticks32, Freq1MHz>(7u32.into(), "7.000us");
+        assert_duration::<Ticks32, Freq1MHz>(1_500u32.into(), "1.500ms");
+        assert_duration::<Ticks32, Freq32KHz>(32_768u32.into(), "1.000s");
+        assert_duration::<Ticks32, Freq32KHz>(1u32.into(), "30.517us");
+        assert_duration::<Ticks24, Freq1KHz>(123_456u32.into(), "123.456s");
+        assert_duration::<Ticks32, Freq1MHz>(u32::MAX.into(), "4294.967s");
+    }
+
+    struct TestCompareAlarm(Cell<Ticks32>, Cell<Ticks32>);
+
+    impl Time for TestCompareAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            self.0.get()
+        }
+    }
+
+    impl<'a> Alarm<'a> for TestCompareAlarm {
+        fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            // Only store the expiration, like a compare register
+            self.1.set(reference.wrapping_add(dt));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.1.get()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            true
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1u32.into()
+        }
+    }
+
+    #[test]
+    fn test_default_alarm_reference_dt() {
+        let alarm = TestCompareAlarm(Cell::new(1_000u32.into()), Cell::new(0u32.into()));
+        alarm.set_alarm(1_000u32.into(), 500u32.into());
+
+        let reference = alarm.get_alarm_reference();
+        let dt = alarm.get_alarm_dt();
+        assert_eq!(reference.wrapping_add(dt).into_u32(), 1_500);
+        assert_eq!(dt, Ticks32::half_max_value());
+
+        // Before the expiration, the alarm is pending
+        for now in [1_000u32, 1_499] {
+            assert!(Ticks32::from(now).within_range(reference, reference.wrapping_add(dt)));
+        }
+        // Once it passed, it is recognized as expired
+        for now in [1_500u32, 100_000] {
+            assert!(!Ticks32::from(now).within_range(reference, reference.wrapping_add(dt)));
+        }
+
+        alarm.0.set(u32::MAX.into());
+        alarm.set_alarm_from_now(10u32.into());
+        assert_eq!(alarm.get_alarm().into_u32(), 9);
+    }
 }
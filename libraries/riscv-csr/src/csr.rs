@@ -14,6 +14,7 @@ pub const MINSTRETH: usize = 0xB82;
 pub const MINSTRET: usize = 0xB02;
 pub const MCYCLEH: usize = 0xB80;
 pub const MCYCLE: usize = 0xB00;
+pub const MCOUNTINHIBIT: usize = 0x320;
 pub const MIE: usize = 0x304;
 pub const MTVEC: usize = 0x305;
 pub const MSTATUS: usize = 0x300;